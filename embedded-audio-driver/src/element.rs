@@ -2,6 +2,7 @@ pub use rivulets_driver::element::Element as BaseElement;
 pub use rivulets_driver::element::{ProcessResult, ProcessStatus, Eof, Fine};
 
 use crate::info::Info;
+use crate::Error;
 
 /// A specialized `Element` for audio processing pipelines.
 ///
@@ -9,4 +10,23 @@ use crate::info::Info;
 /// common interface for all audio components.
 pub trait Element: BaseElement<Info = Info> {}
 
-impl<T> Element for T where T: BaseElement<Info = Info> {}
\ No newline at end of file
+impl<T> Element for T where T: BaseElement<Info = Info> {}
+
+/// A source whose stream can be repositioned.
+///
+/// After a seek, the next payload of the source is marked `Position::First` (or `Single`),
+/// which tells downstream elements that the stream is discontinuous.
+pub trait Seekable: BaseElement<Info = Info, Error = Error> {
+    /// Returns the index of the next frame the source produces.
+    fn current_frame(&self) -> u64;
+
+    /// Moves to `frame`, clamped to the length of the stream if it is known. Returns the
+    /// frame moved to.
+    fn seek(&mut self, frame: u64) -> Result<u64, Error>;
+
+    /// Moves to `ms` milliseconds into the stream, using the sample rate of the output `Info`.
+    fn seek_ms(&mut self, ms: u64) -> Result<u64, Error> {
+        let info = self.get_out_info().ok_or(Error::NotInitialized)?;
+        self.seek(ms * info.sample_rate as u64 / 1000)
+    }
+}
@@ -2,6 +2,26 @@ use core::ops::{Div, Mul, Rem};
 
 use rivulets_driver::info::Info as BaseInfo;
 
+/// The numeric encoding of individual audio samples.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SampleEncoding {
+    /// Two's complement integer PCM samples.
+    #[default]
+    Signed,
+    /// Offset-binary integer PCM samples, with silence at the mid-point (e.g., WAV 8-bit).
+    Unsigned,
+    /// IEEE 754 floating-point samples.
+    Float,
+}
+
+/// The byte order of multi-byte samples.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Endianness {
+    #[default]
+    Little,
+    Big,
+}
+
 /// Represents metadata information about an audio data stream or file.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Info {
@@ -12,8 +32,23 @@ pub struct Info {
     pub channels: u8,
 
     /// The number of bits per sample (e.g., 8, 16, 24).
+    /// This is the container size, each sample occupies `bits_per_sample / 8` bytes.
     pub bits_per_sample: u8,
 
+    /// The number of significant bits in each sample, at most `bits_per_sample`.
+    /// Integer samples are MSB-aligned in their container, the unused low bits are zero.
+    pub valid_bits_per_sample: u8,
+
+    /// How each sample is encoded (signed, unsigned or IEEE float).
+    pub encoding: SampleEncoding,
+
+    /// The byte order of each sample.
+    pub endianness: Endianness,
+
+    /// Speaker position bitmask as defined by `WAVE_FORMAT_EXTENSIBLE`
+    /// (e.g., `0x3` for front left + front right). `0` if unspecified.
+    pub channel_mask: u32,
+
     /// The total number of audio frames.
     /// This is `None` if the number of frames is unknown.
     pub num_frames: Option<u64>,
@@ -25,6 +60,10 @@ impl Default for Info {
             sample_rate: 0,
             channels: 0,
             bits_per_sample: 0,
+            valid_bits_per_sample: 0,
+            encoding: SampleEncoding::Signed,
+            endianness: Endianness::Little,
+            channel_mask: 0,
             num_frames: None,
         }
     }
@@ -32,6 +71,10 @@ impl Default for Info {
 
 impl Info {
     /// Creates a new `Info` instance with the specified parameters.
+    ///
+    /// The samples are assumed to be little-endian integer PCM using the whole container,
+    /// unsigned for 8 bits and signed otherwise (the WAV convention), with an unspecified
+    /// channel mask.
     /// 
     /// # Parameters
     /// - `sample_rate`: The sample rate in Hz. 1 for mono, 2 for stereo.
@@ -43,10 +86,36 @@ impl Info {
             sample_rate,
             channels,
             bits_per_sample,
+            valid_bits_per_sample: bits_per_sample,
+            encoding: if bits_per_sample == 8 { SampleEncoding::Unsigned } else { SampleEncoding::Signed },
+            endianness: Endianness::Little,
+            channel_mask: 0,
             num_frames,
         }
     }
 
+    /// Creates a new `Info` describing little-endian IEEE float samples.
+    pub fn new_float(sample_rate: u32, channels: u8, bits_per_sample: u8, num_frames: Option<u64>) -> Self {
+        let mut info = Self::new(sample_rate, channels, bits_per_sample, num_frames);
+        info.encoding = SampleEncoding::Float;
+        info
+    }
+
+    /// Sets the sample encoding.
+    pub fn set_encoding(&mut self, encoding: SampleEncoding) {
+        self.encoding = encoding;
+    }
+
+    /// Sets the sample byte order.
+    pub fn set_endianness(&mut self, endianness: Endianness) {
+        self.endianness = endianness;
+    }
+
+    /// Sets the number of significant bits in each sample container.
+    pub fn set_valid_bits_per_sample(&mut self, valid_bits_per_sample: u8) {
+        self.valid_bits_per_sample = valid_bits_per_sample;
+    }
+
     pub fn set_duration_ms(&mut self, duration_ms: u32) {
         self.num_frames = Some(((duration_ms * self.sample_rate) / 1000) as _);
     }
@@ -60,7 +129,25 @@ impl Info {
     }
 
     pub fn vaild(&self) -> bool {
-        self.sample_rate > 0 && self.channels > 0 && self.bits_per_sample > 0 && (self.bits_per_sample % 8 == 0)
+        let encoding_ok = match self.encoding {
+            SampleEncoding::Signed | SampleEncoding::Unsigned => true,
+            SampleEncoding::Float => {
+                (self.bits_per_sample == 32 || self.bits_per_sample == 64)
+                    && self.valid_bits_per_sample == self.bits_per_sample
+            }
+        };
+        let valid_bits_ok = self.valid_bits_per_sample > 0 && self.valid_bits_per_sample <= self.bits_per_sample;
+        self.sample_rate > 0 && self.channels > 0 && self.bits_per_sample > 0 && (self.bits_per_sample % 8 == 0) && valid_bits_ok && encoding_ok
+    }
+
+    /// Returns `true` if the samples are IEEE floating point.
+    pub fn is_float(&self) -> bool {
+        self.encoding == SampleEncoding::Float
+    }
+
+    /// Returns `true` if both describe the same sample stream layout, regardless of length.
+    pub fn is_same_format(&self, other: &Info) -> bool {
+        Info { num_frames: None, ..*self } == Info { num_frames: None, ..*other }
     }
 
     pub fn get_alignment_bytes(&self) -> u8 {
@@ -10,7 +10,7 @@ pub use rivulets_driver::databus;
 pub use rivulets_driver::payload;
 
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Error {
     /// Invalid parameters provided
     InvalidParameter,
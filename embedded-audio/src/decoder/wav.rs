@@ -1,24 +1,36 @@
 use embedded_io::{Read, Seek, SeekFrom};
 
 use embedded_audio_driver::databus::{Producer};
-use embedded_audio_driver::element::{BaseElement, ProcessResult, Eof, Fine};
-use embedded_audio_driver::info::Info;
+use embedded_audio_driver::element::{BaseElement, ProcessResult, Eof, Fine, Seekable};
+use embedded_audio_driver::info::{Endianness, Info, SampleEncoding};
 use embedded_audio_driver::payload::Position;
 use embedded_audio_driver::port::{InPlacePort, InPort, OutPort, PayloadSize, PortRequirements};
 use embedded_audio_driver::Error;
 
+use crate::metadata::WavMetadata;
+use crate::riff::{SUBFORMAT_GUID_TAIL, UNKNOWN_SIZE, WAVE_FORMAT_EXTENSIBLE, WAVE_FORMAT_IEEE_FLOAT, WAVE_FORMAT_PCM};
+
 /// A Simlpe WAV decoder
 ///
 /// This element reads data from an internal reader that implements `Read` and `Seek`,
 /// parses the WAV format, and produces a raw audio data stream.
+///
+/// RF64 and BW64 files, which store sizes beyond 4 GiB in a `ds64` chunk, are read as well.
+/// `LIST`/`INFO`, `bext` and `cue ` chunks are read into [`WavDecoder::metadata`].
+/// A `data` chunk of size `0xFFFFFFFF`, as written by streaming encoders that do not know
+/// the final length, is read until the reader ends. [`WavDecoder::tolerant`] does the same
+/// for every file.
 pub struct WavDecoder<R: Read + Seek> {
     reader: R,
     info: Option<Info>,
     data_start: u64,
+    /// The end of the `data` chunk, or `u64::MAX` until the reader ends if the length is unknown.
     data_end: u64,
     current_frame: u64,
     bytes_per_frame: u8,
     is_first_chunk: bool,
+    tolerant: bool,
+    metadata: WavMetadata,
     frames_per_process: u16,
 }
 
@@ -33,11 +45,116 @@ impl<R: Read + Seek> WavDecoder<R> {
             current_frame: 0,
             bytes_per_frame: 0,
             is_first_chunk: true,
+            tolerant: false,
+            metadata: WavMetadata::new(),
             frames_per_process,
         }
     }
 
+    /// Ignores the declared size of the `data` chunk and reads until the reader ends.
+    ///
+    /// Use this for streamed recordings that may have been cut off, or whose writer left
+    /// the size at zero. `Info::num_frames` is then unknown.
+    pub fn tolerant(mut self) -> Self {
+        self.tolerant = true;
+        self
+    }
+
+    /// Parses a `fmt ` chunk body of `chunk_size` bytes into `info`.
+    ///
+    /// Accepts `WAVE_FORMAT_PCM`, `WAVE_FORMAT_IEEE_FLOAT` and `WAVE_FORMAT_EXTENSIBLE`
+    /// with a PCM or IEEE float sub-format. Malformed chunks yield `Error::InvalidParameter`,
+    /// well-formed chunks describing any other encoding yield `Error::Unsupported`.
+    fn parse_fmt_chunk(&mut self, chunk_size: u32, info: &mut Info) -> Result<(), Error>
+    where
+        <R as embedded_io::ErrorType>::Error: core::fmt::Debug,
+    {
+        if chunk_size < 16 {
+            return Err(Error::InvalidParameter);
+        }
+
+        // 16 bytes of WAVEFORMAT, plus up to 24 bytes of WAVEFORMATEXTENSIBLE fields.
+        let mut fmt_buf = [0u8; 40];
+        let read_len = (chunk_size as usize).min(fmt_buf.len());
+        self.reader.read_exact(&mut fmt_buf[..read_len]).map_err(|_| Error::DeviceError)?;
+
+        let mut format_tag = u16::from_le_bytes(fmt_buf[0..2].try_into().unwrap());
+        info.channels = u16::from_le_bytes(fmt_buf[2..4].try_into().unwrap()) as u8;
+        info.sample_rate = u32::from_le_bytes(fmt_buf[4..8].try_into().unwrap());
+        info.bits_per_sample = u16::from_le_bytes(fmt_buf[14..16].try_into().unwrap()) as u8;
+        info.valid_bits_per_sample = info.bits_per_sample;
+        info.endianness = Endianness::Little;
+        info.channel_mask = 0;
+
+        if format_tag == WAVE_FORMAT_EXTENSIBLE {
+            let cb_size = u16::from_le_bytes(fmt_buf[16..18].try_into().unwrap());
+            if read_len < 40 || cb_size < 22 {
+                return Err(Error::InvalidParameter);
+            }
+
+            let valid_bits = u16::from_le_bytes(fmt_buf[18..20].try_into().unwrap());
+            let channel_mask = u32::from_le_bytes(fmt_buf[20..24].try_into().unwrap());
+            if valid_bits > info.bits_per_sample as u16 || channel_mask.count_ones() > info.channels as u32 {
+                return Err(Error::InvalidParameter);
+            }
+            if fmt_buf[26..40] != SUBFORMAT_GUID_TAIL {
+                warn!("WAV: unsupported WAVE_FORMAT_EXTENSIBLE sub-format GUID");
+                return Err(Error::Unsupported);
+            }
+
+            // A zero valid-bits field means "same as the container".
+            if valid_bits != 0 {
+                info.valid_bits_per_sample = valid_bits as u8;
+            }
+            info.channel_mask = channel_mask;
+            format_tag = u16::from_le_bytes(fmt_buf[24..26].try_into().unwrap());
+        }
+
+        info.encoding = match format_tag {
+            // WAV stores 8-bit PCM as unsigned and everything wider as signed.
+            WAVE_FORMAT_PCM if info.bits_per_sample == 8 => SampleEncoding::Unsigned,
+            WAVE_FORMAT_PCM => SampleEncoding::Signed,
+            WAVE_FORMAT_IEEE_FLOAT => SampleEncoding::Float,
+            _ => {
+                warn!("WAV: unsupported format tag {:#x}", format_tag);
+                return Err(Error::Unsupported);
+            }
+        };
+
+        if !info.vaild() {
+            return Err(Error::InvalidParameter);
+        }
+        self.bytes_per_frame = info.get_alignment_bytes();
+
+        // Skip the rest of the fmt chunk if it's larger than what we parsed
+        if chunk_size as usize > read_len {
+            self.reader.seek(SeekFrom::Current((chunk_size as usize - read_len) as i64)).map_err(|_| Error::DeviceError)?;
+        }
+        Ok(())
+    }
+
+    /// Parses a `ds64` chunk body of `chunk_size` bytes and returns the 64-bit `data` size.
+    ///
+    /// The RIFF size and sample count are not needed, and the chunk size table is skipped.
+    fn parse_ds64_chunk(&mut self, chunk_size: u32) -> Result<u64, Error>
+    where
+        <R as embedded_io::ErrorType>::Error: core::fmt::Debug,
+    {
+        let mut ds64_buf = [0u8; 24];
+        if (chunk_size as usize) < ds64_buf.len() {
+            return Err(Error::InvalidParameter);
+        }
+        self.reader.read_exact(&mut ds64_buf).map_err(|_| Error::DeviceError)?;
+        let data_size = u64::from_le_bytes(ds64_buf[8..16].try_into().unwrap());
+
+        self.reader.seek(SeekFrom::Current((chunk_size as usize - ds64_buf.len()) as i64)).map_err(|_| Error::DeviceError)?;
+        Ok(data_size)
+    }
+
     /// Parses the WAV header from the internal reader.
+    ///
+    /// RF64 and BW64 files are read like RIFF files, taking the 64-bit `data` size from
+    /// their `ds64` chunk.
     fn parse_header(&mut self) -> Result<(), Error>
     where
         <R as embedded_io::ErrorType>::Error: core::fmt::Debug,
@@ -45,67 +162,97 @@ impl<R: Read + Seek> WavDecoder<R> {
         let mut header_buf = [0u8; 12]; // Read only the RIFF header first
         self.reader.read_exact(&mut header_buf).map_err(|_| Error::DeviceError)?;
 
-        if &header_buf[0..4] != b"RIFF" || &header_buf[8..12] != b"WAVE" {
+        let rf64 = match &header_buf[0..4] {
+            b"RIFF" => false,
+            b"RF64" | b"BW64" => true,
+            _ => return Err(Error::InvalidParameter),
+        };
+        if &header_buf[8..12] != b"WAVE" {
             return Err(Error::InvalidParameter);
         }
 
         // Search for "fmt " and "data" chunks
         let mut fmt_chunk_found = false;
         let mut data_chunk_found = false;
+        let mut ds64_data_size = None;
 
         let mut info = Info::default();
 
         loop {
             let mut chunk_header = [0u8; 8];
             if self.reader.read_exact(&mut chunk_header).is_err() {
-                // Reached end of file before finding both required chunks
+                // Reached end of file
                 break;
             }
             let chunk_id = &chunk_header[0..4];
             let chunk_size = u32::from_le_bytes(chunk_header[4..8].try_into().unwrap());
+            let chunk_start = self.reader.seek(SeekFrom::Current(0)).map_err(|_| Error::DeviceError)?;
+            // Chunks are padded to an even length.
+            let mut chunk_end = chunk_start + chunk_size as u64 + (chunk_size & 1) as u64;
 
             match chunk_id {
-                b"fmt " => {
-                    let mut fmt_buf = [0u8; 16];
-                    self.reader.read_exact(&mut fmt_buf).map_err(|_| Error::DeviceError)?;
-                    
-                    info.channels = u16::from_le_bytes(fmt_buf[2..4].try_into().unwrap()) as u8;
-                    info.sample_rate = u32::from_le_bytes(fmt_buf[4..8].try_into().unwrap());
-                    info.bits_per_sample = u16::from_le_bytes(fmt_buf[14..16].try_into().unwrap()) as u8;
-
-                    if !info.vaild() {
-                        return Err(Error::InvalidParameter);
-                    }
-                    self.bytes_per_frame = info.get_alignment_bytes();
-
-                    // Skip rest of fmt chunk if it's larger than 16
-                    if chunk_size > 16 {
-                        self.reader.seek(SeekFrom::Current((chunk_size - 16) as i64)).map_err(|_| Error::DeviceError)?;
-                    }
+                b"ds64" if rf64 => {
+                    ds64_data_size = Some(self.parse_ds64_chunk(chunk_size)?);
+                }
+                b"fmt " if !fmt_chunk_found => {
+                    self.parse_fmt_chunk(chunk_size, &mut info)?;
                     fmt_chunk_found = true;
                 }
-                b"data" => {
-                    self.data_start = self.reader.seek(SeekFrom::Current(0)).map_err(|_| Error::DeviceError)?;
-                    self.data_end = self.data_start + chunk_size as u64;
-                    
-                    if self.bytes_per_frame > 0 {
-                        info.num_frames = Some((chunk_size / self.bytes_per_frame as u32) as u64);
-                    }
+                b"data" if !data_chunk_found => {
+                    self.data_start = chunk_start;
+                    let data_size = match chunk_size {
+                        _ if self.tolerant => None,
+                        // RF64 moves the real size to the ds64 chunk.
+                        UNKNOWN_SIZE if rf64 => Some(ds64_data_size.ok_or(Error::InvalidParameter)?),
+                        UNKNOWN_SIZE => None,
+                        size => Some(size as u64),
+                    };
                     data_chunk_found = true;
+                    match data_size {
+                        Some(size) => {
+                            self.data_end = self.data_start + size;
+                            chunk_end = self.data_end + (size & 1);
+                            if self.bytes_per_frame > 0 {
+                                info.num_frames = Some(size / self.bytes_per_frame as u64);
+                            }
+                        }
+                        None => {
+                            // The data runs to the end of the file, so nothing can follow it.
+                            self.data_end = u64::MAX;
+                            break;
+                        }
+                    }
                 }
-                _ => {
-                    // Skip unknown chunks
-                    self.reader.seek(SeekFrom::Current(chunk_size as i64)).map_err(|_| Error::DeviceError)?;
+                b"LIST" | b"bext" | b"cue " => {
+                    let result = match chunk_id {
+                        b"LIST" => self.metadata.read_list(&mut self.reader, chunk_size),
+                        b"bext" => self.metadata.read_bext(&mut self.reader, chunk_size),
+                        _ => self.metadata.read_cue(&mut self.reader, chunk_size),
+                    };
+                    if result.is_err() {
+                        warn!("WAV: ignoring a malformed metadata chunk");
+                    }
                 }
+                _ => {} // Skip unknown chunks
             }
 
-            if fmt_chunk_found && data_chunk_found {
-                self.info = Some(info);
-                return Ok(());
-            }
+            // Metadata often follows the data, so keep scanning to the end of the file.
+            self.reader.seek(SeekFrom::Start(chunk_end)).map_err(|_| Error::DeviceError)?;
+        }
+
+        if fmt_chunk_found && data_chunk_found {
+            self.info = Some(info);
+            Ok(())
+        } else {
+            Err(Error::InvalidParameter) // Required chunks not found
         }
+    }
 
-        Err(Error::InvalidParameter) // Required chunks not found
+    /// Returns the metadata read from the file.
+    ///
+    /// Metadata chunks after the audio data are only found if the length of the data is known.
+    pub fn metadata(&self) -> &WavMetadata {
+        &self.metadata
     }
 }
 
@@ -127,7 +274,7 @@ where
     fn available(&self) -> u32 {
         if let Some(info) = &self.info {
             if let Some(num_frames) = info.num_frames {
-                return num_frames.saturating_sub(self.current_frame) as u32;
+                return num_frames.saturating_sub(self.current_frame).min(u32::MAX as u64) as u32;
             }
         }
         u32::MAX // If num_frames is unknown, assume a large number.
@@ -153,6 +300,7 @@ where
         self.current_frame = 0;
         self.bytes_per_frame = 0;
         self.is_first_chunk = true;
+        self.metadata = WavMetadata::new();
         self.reader.seek(SeekFrom::Start(0)).map_err(|_| Error::DeviceError)?;
         Ok(())
     }
@@ -170,7 +318,8 @@ where
     {
         if let OutPort::Producer(producer) = out_port {
             let current_pos_bytes = self.data_start + (self.current_frame * self.bytes_per_frame as u64);
-            if current_pos_bytes >= self.data_end {
+            // After a seek to the end, an empty payload still marks the end of the stream.
+            if current_pos_bytes >= self.data_end && !self.is_first_chunk {
                 return Ok(Eof);
             }
             
@@ -179,21 +328,41 @@ where
             let mut payload = producer.acquire_write().await;
             
             // Limit read to the max payload size and remaining data in the chunk.
-            let max_read = (self.data_end - current_pos_bytes)
+            let max_read = self.data_end.saturating_sub(current_pos_bytes)
                 .min(payload.len() as u64) as usize;
             let aligned_read = (max_read as u32 / self.bytes_per_frame as u32) * self.bytes_per_frame as u32;
 
-            if aligned_read == 0 {
+            if aligned_read == 0 && max_read > 0 {
                 panic!("Payload buffer too small for even one frame");
             }
 
-            let bytes_read = self.reader.read(&mut payload[..aligned_read as usize]).map_err(|_| Error::DeviceError)?;
-            payload.set_valid_length(bytes_read);
-            
+            // A reader may return less than asked for, e.g. a stream that is still arriving,
+            // so keep reading until the payload is full or the reader ends.
+            let mut bytes_read = 0;
+            let mut reader_ended = false;
+            while bytes_read < aligned_read as usize {
+                let n = self.reader.read(&mut payload[bytes_read..aligned_read as usize]).map_err(|_| Error::DeviceError)?;
+                if n == 0 {
+                    reader_ended = true;
+                    break;
+                }
+                bytes_read += n;
+            }
+
+            // A trailing partial frame is dropped.
             let frames_read = bytes_read as u64 / self.bytes_per_frame as u64;
+            payload.set_valid_length((frames_read * self.bytes_per_frame as u64) as usize);
             self.current_frame += frames_read;
 
-            let is_last = (self.data_start + (self.current_frame * self.bytes_per_frame as u64)) >= self.data_end;
+            let current_pos_bytes = self.data_start + (self.current_frame * self.bytes_per_frame as u64);
+            if reader_ended {
+                if self.data_end != u64::MAX {
+                    warn!("WAV: data chunk ended {} bytes early", self.data_end - current_pos_bytes);
+                }
+                // The stream ends here, also for seeks that come later.
+                self.data_end = current_pos_bytes;
+            }
+            let is_last = current_pos_bytes >= self.data_end;
             
             match (self.is_first_chunk, is_last) {
                 (true, true) => payload.set_position(Position::Single),
@@ -213,6 +382,25 @@ where
     }
 }
 
+impl<R: Read + Seek> Seekable for WavDecoder<R>
+where
+    <R as embedded_io::ErrorType>::Error: core::fmt::Debug,
+{
+    fn current_frame(&self) -> u64 {
+        self.current_frame
+    }
+
+    fn seek(&mut self, frame: u64) -> Result<u64, Error> {
+        if self.info.is_none() || self.bytes_per_frame == 0 {
+            return Err(Error::NotInitialized);
+        }
+        let total_frames = (self.data_end - self.data_start) / self.bytes_per_frame as u64;
+        self.current_frame = frame.min(total_frames);
+        self.is_first_chunk = true;
+        Ok(self.current_frame)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -305,7 +493,7 @@ mod tests {
 
     impl Read for MockReader {
         fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
-            let bytes_to_read = (self.data.len() as u64 - self.position).min(buf.len() as u64) as usize;
+            let bytes_to_read = (self.data.len() as u64).saturating_sub(self.position).min(buf.len() as u64) as usize;
             if bytes_to_read == 0 {
                 return Ok(0);
             }
@@ -363,6 +551,142 @@ mod tests {
         data
     }
 
+    // Helper to wrap a raw `fmt ` chunk body and silent audio into a WAV file.
+    fn create_wav_data_with_fmt(fmt_body: &[u8], bytes_per_frame: u32, num_frames: u32) -> Vec<u8> {
+        let mut data = Vec::new();
+        let data_size = num_frames * bytes_per_frame;
+        let file_size = 4 + 8 + fmt_body.len() as u32 + 8 + data_size;
+
+        data.extend_from_slice(b"RIFF");
+        data.extend_from_slice(&file_size.to_le_bytes());
+        data.extend_from_slice(b"WAVE");
+
+        data.extend_from_slice(b"fmt ");
+        data.extend_from_slice(&(fmt_body.len() as u32).to_le_bytes());
+        data.extend_from_slice(fmt_body);
+
+        data.extend_from_slice(b"data");
+        data.extend_from_slice(&data_size.to_le_bytes());
+        data.extend_from_slice(&vec![0; data_size as usize]);
+
+        data
+    }
+
+    // Helper to build a `fmt ` chunk body. `extensible` is (valid bits, channel mask, sub-format tag).
+    fn create_fmt_body(format_tag: u16, channels: u16, bits_per_sample: u16, extensible: Option<(u16, u32, u16)>) -> Vec<u8> {
+        let sample_rate = 48000u32;
+        let block_align = channels * (bits_per_sample / 8);
+
+        let mut body = Vec::new();
+        body.extend_from_slice(&format_tag.to_le_bytes());
+        body.extend_from_slice(&channels.to_le_bytes());
+        body.extend_from_slice(&sample_rate.to_le_bytes());
+        body.extend_from_slice(&(sample_rate * block_align as u32).to_le_bytes());
+        body.extend_from_slice(&block_align.to_le_bytes());
+        body.extend_from_slice(&bits_per_sample.to_le_bytes());
+
+        if let Some((valid_bits, channel_mask, sub_format)) = extensible {
+            body.extend_from_slice(&22u16.to_le_bytes()); // cbSize
+            body.extend_from_slice(&valid_bits.to_le_bytes());
+            body.extend_from_slice(&channel_mask.to_le_bytes());
+            body.extend_from_slice(&sub_format.to_le_bytes());
+            body.extend_from_slice(&SUBFORMAT_GUID_TAIL);
+        }
+        body
+    }
+
+    #[tokio::test]
+    async fn test_ieee_float_format() {
+        let fmt_body = create_fmt_body(WAVE_FORMAT_IEEE_FLOAT, 2, 32, None);
+        let reader = MockReader::new(create_wav_data_with_fmt(&fmt_body, 8, 16));
+        let mut decoder = WavDecoder::new(reader, 64);
+
+        decoder.initialize(None).await.expect("IEEE float WAV should be accepted");
+
+        let info = decoder.get_out_info().unwrap();
+        assert_eq!(info.encoding, SampleEncoding::Float);
+        assert_eq!(info.bits_per_sample, 32);
+        assert_eq!(info.channel_mask, 0);
+        assert_eq!(info.num_frames, Some(16));
+    }
+
+    #[tokio::test]
+    async fn test_extensible_format() {
+        // 5.1 channel 24-bit PCM, as typically exported by DAWs.
+        let fmt_body = create_fmt_body(WAVE_FORMAT_EXTENSIBLE, 6, 24, Some((24, 0x3F, WAVE_FORMAT_PCM)));
+        let reader = MockReader::new(create_wav_data_with_fmt(&fmt_body, 18, 10));
+        let mut decoder = WavDecoder::new(reader, 64);
+
+        decoder.initialize(None).await.expect("Extensible PCM WAV should be accepted");
+
+        let info = decoder.get_out_info().unwrap();
+        assert_eq!(info.encoding, SampleEncoding::Signed);
+        assert_eq!(info.channels, 6);
+        assert_eq!(info.bits_per_sample, 24);
+        assert_eq!(info.channel_mask, 0x3F);
+        assert_eq!(info.num_frames, Some(10));
+
+        // 20 valid bits in a 24-bit container
+        let fmt_body = create_fmt_body(WAVE_FORMAT_EXTENSIBLE, 2, 24, Some((20, 0x3, WAVE_FORMAT_PCM)));
+        let reader = MockReader::new(create_wav_data_with_fmt(&fmt_body, 6, 10));
+        let mut decoder = WavDecoder::new(reader, 64);
+
+        decoder.initialize(None).await.expect("Extensible PCM WAV should be accepted");
+        let info = decoder.get_out_info().unwrap();
+        assert_eq!(info.bits_per_sample, 24);
+        assert_eq!(info.valid_bits_per_sample, 20);
+
+        // Extensible float
+        let fmt_body = create_fmt_body(WAVE_FORMAT_EXTENSIBLE, 2, 32, Some((32, 0x3, WAVE_FORMAT_IEEE_FLOAT)));
+        let reader = MockReader::new(create_wav_data_with_fmt(&fmt_body, 8, 10));
+        let mut decoder = WavDecoder::new(reader, 64);
+
+        decoder.initialize(None).await.expect("Extensible float WAV should be accepted");
+        assert_eq!(decoder.get_out_info().unwrap().encoding, SampleEncoding::Float);
+    }
+
+    #[tokio::test]
+    async fn test_8bit_pcm_is_unsigned() {
+        let fmt_body = create_fmt_body(WAVE_FORMAT_PCM, 1, 8, None);
+        let reader = MockReader::new(create_wav_data_with_fmt(&fmt_body, 1, 10));
+        let mut decoder = WavDecoder::new(reader, 64);
+
+        decoder.initialize(None).await.unwrap();
+        let info = decoder.get_out_info().unwrap();
+        assert_eq!(info.encoding, SampleEncoding::Unsigned);
+        assert_eq!(info.endianness, Endianness::Little);
+        assert_eq!(info.valid_bits_per_sample, 8);
+    }
+
+    #[tokio::test]
+    async fn test_unsupported_formats_rejected() {
+        // ADPCM
+        let fmt_body = create_fmt_body(0x0002, 1, 16, None);
+        let reader = MockReader::new(create_wav_data_with_fmt(&fmt_body, 2, 10));
+        let mut decoder = WavDecoder::new(reader, 64);
+        assert!(matches!(decoder.initialize(None).await, Err(Error::Unsupported)));
+
+        // Extensible with a non-PCM sub-format GUID
+        let mut fmt_body = create_fmt_body(WAVE_FORMAT_EXTENSIBLE, 2, 16, Some((16, 0x3, WAVE_FORMAT_PCM)));
+        fmt_body[39] = 0x00;
+        let reader = MockReader::new(create_wav_data_with_fmt(&fmt_body, 4, 10));
+        let mut decoder = WavDecoder::new(reader, 64);
+        assert!(matches!(decoder.initialize(None).await, Err(Error::Unsupported)));
+
+        // Float with a bit depth that cannot be IEEE 754
+        let fmt_body = create_fmt_body(WAVE_FORMAT_IEEE_FLOAT, 2, 24, None);
+        let reader = MockReader::new(create_wav_data_with_fmt(&fmt_body, 6, 10));
+        let mut decoder = WavDecoder::new(reader, 64);
+        assert!(matches!(decoder.initialize(None).await, Err(Error::InvalidParameter)));
+
+        // Valid bits larger than the container
+        let fmt_body = create_fmt_body(WAVE_FORMAT_EXTENSIBLE, 2, 16, Some((24, 0x3, WAVE_FORMAT_PCM)));
+        let reader = MockReader::new(create_wav_data_with_fmt(&fmt_body, 4, 10));
+        let mut decoder = WavDecoder::new(reader, 64);
+        assert!(matches!(decoder.initialize(None).await, Err(Error::InvalidParameter)));
+        assert!(decoder.get_out_info().is_none());
+    }
+
     #[tokio::test]
     async fn test_header_parsing_with_mock_data() {
         let wav_data = create_valid_wav_data();
@@ -378,6 +702,258 @@ mod tests {
         assert_eq!(info.num_frames, Some(64));
     }
 
+    #[tokio::test]
+    async fn test_seek() {
+        // Tag the first sample of each frame with the frame index.
+        let mut wav_data = create_valid_wav_data();
+        for (frame, bytes) in wav_data[44..].chunks_exact_mut(4).enumerate() {
+            bytes[..2].copy_from_slice(&(frame as u16).to_le_bytes());
+        }
+        let mut decoder = WavDecoder::new(MockReader::new(wav_data), 16);
+        assert!(matches!(decoder.seek(10), Err(Error::NotInitialized)));
+        let requirements = decoder.initialize(None).await.unwrap();
+
+        let mut slot = HeapSlot::new_heap(64);
+        slot.register(Operation::Produce, requirements.out.unwrap());
+        slot.register(Operation::Consume, requirements.out.unwrap());
+        let mut in_port = InPort::new_none();
+        let mut out_port = slot.out_port();
+        let mut in_place_port = InPlacePort::new_none();
+
+        decoder.process(&mut in_port, &mut out_port, &mut in_place_port).await.unwrap();
+        assert_eq!(slot.acquire_read().await.metadata.position, Position::First);
+
+        // The next payload starts at the new position and marks the discontinuity.
+        assert_eq!(decoder.seek(10).unwrap(), 10);
+        decoder.process(&mut in_port, &mut out_port, &mut in_place_port).await.unwrap();
+        {
+            let payload = slot.acquire_read().await;
+            assert_eq!(payload.metadata.position, Position::First);
+            assert_eq!(u16::from_le_bytes([payload[0], payload[1]]), 10);
+        }
+        assert_eq!(decoder.current_frame(), 26);
+
+        assert_eq!(decoder.seek_ms(1).unwrap(), 44);
+
+        // Seeking past the end still ends the stream with a payload.
+        assert_eq!(decoder.seek(1000).unwrap(), 64);
+        let result = decoder.process(&mut in_port, &mut out_port, &mut in_place_port).await.unwrap();
+        assert_eq!(result, Eof);
+        let payload = slot.acquire_read().await;
+        assert_eq!(payload.metadata.position, Position::Single);
+        assert_eq!(payload.metadata.valid_length, 0);
+    }
+
+    // A reader that serves a file header followed by `len` bytes of silence, so tests can
+    // read files of many gigabytes.
+    struct SparseReader {
+        head: Vec<u8>,
+        len: u64,
+        position: u64,
+    }
+
+    impl ErrorType for SparseReader {
+        type Error = core::convert::Infallible;
+    }
+
+    impl Read for SparseReader {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+            let n = (self.len.saturating_sub(self.position)).min(buf.len() as u64) as usize;
+            for (i, byte) in buf[..n].iter_mut().enumerate() {
+                *byte = self.head.get(self.position as usize + i).copied().unwrap_or(0);
+            }
+            self.position += n as u64;
+            Ok(n)
+        }
+    }
+
+    impl Seek for SparseReader {
+        fn seek(&mut self, pos: SeekFrom) -> Result<u64, Self::Error> {
+            self.position = match pos {
+                SeekFrom::Start(p) => p,
+                SeekFrom::End(p) => (self.len as i64 + p) as u64,
+                SeekFrom::Current(p) => (self.position as i64 + p) as u64,
+            };
+            Ok(self.position)
+        }
+    }
+
+    // Helper to build an RF64-style header whose ds64 chunk declares `data_size` bytes.
+    fn create_rf64_header(magic: &[u8; 4], fmt_body: &[u8], data_size: u64) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(magic);
+        data.extend_from_slice(&UNKNOWN_SIZE.to_le_bytes());
+        data.extend_from_slice(b"WAVE");
+
+        data.extend_from_slice(b"ds64");
+        data.extend_from_slice(&28u32.to_le_bytes());
+        data.extend_from_slice(&(4 + 36 + 8 + fmt_body.len() as u64 + 8 + data_size).to_le_bytes());
+        data.extend_from_slice(&data_size.to_le_bytes());
+        data.extend_from_slice(&0u64.to_le_bytes()); // Sample count, unused
+        data.extend_from_slice(&0u32.to_le_bytes()); // Table length
+
+        data.extend_from_slice(b"fmt ");
+        data.extend_from_slice(&(fmt_body.len() as u32).to_le_bytes());
+        data.extend_from_slice(fmt_body);
+
+        data.extend_from_slice(b"data");
+        data.extend_from_slice(&UNKNOWN_SIZE.to_le_bytes());
+        data
+    }
+
+    #[tokio::test]
+    async fn test_rf64_reads_ds64_size() {
+        // 5 GiB of stereo 16-bit audio.
+        let data_size = 5u64 << 30;
+        let fmt_body = create_fmt_body(WAVE_FORMAT_PCM, 2, 16, None);
+        for magic in [b"RF64", b"BW64"] {
+            let head = create_rf64_header(magic, &fmt_body, data_size);
+            let len = head.len() as u64 + data_size;
+            let mut decoder = WavDecoder::new(SparseReader { head, len, position: 0 }, 16);
+            let requirements = decoder.initialize(None).await.expect("RF64 WAV should be accepted");
+            assert_eq!(decoder.get_out_info().unwrap().num_frames, Some(data_size / 4));
+
+            // The last payload ends exactly at the 64-bit data size.
+            let mut slot = HeapSlot::new_heap(64);
+            slot.register(Operation::Produce, requirements.out.unwrap());
+            slot.register(Operation::Consume, requirements.out.unwrap());
+            assert_eq!(decoder.seek(data_size / 4 - 10).unwrap(), data_size / 4 - 10);
+            let result = decoder.process(&mut InPort::new_none(), &mut slot.out_port(), &mut InPlacePort::new_none()).await.unwrap();
+            assert_eq!(result, Eof);
+            let payload = slot.acquire_read().await;
+            assert_eq!(payload.metadata.valid_length, 40);
+            assert_eq!(payload.metadata.position, Position::Single);
+        }
+
+        // More frames than fit into `available`.
+        let fmt_body = create_fmt_body(WAVE_FORMAT_PCM, 1, 8, None);
+        let head = create_rf64_header(b"RF64", &fmt_body, data_size);
+        let len = head.len() as u64 + data_size;
+        let mut decoder = WavDecoder::new(SparseReader { head, len, position: 0 }, 16);
+        decoder.initialize(None).await.unwrap();
+        assert_eq!(decoder.available(), u32::MAX);
+
+        // RF64 without a ds64 chunk has no usable data size.
+        let mut wav_data = create_valid_wav_data();
+        wav_data[0..4].copy_from_slice(b"RF64");
+        wav_data[40..44].copy_from_slice(&UNKNOWN_SIZE.to_le_bytes());
+        let mut decoder = WavDecoder::new(MockReader::new(wav_data), 16);
+        assert!(matches!(decoder.initialize(None).await, Err(Error::InvalidParameter)));
+    }
+
+    // Reads payloads until the decoder returns `Eof`, collecting their data and positions.
+    async fn decode_all(decoder: &mut WavDecoder<MockReader>, payload_size: usize) -> (Vec<u8>, Vec<Position>) {
+        let requirements = decoder.initialize(None).await.unwrap();
+        let mut slot = HeapSlot::new_heap(payload_size);
+        slot.register(Operation::Produce, requirements.out.unwrap());
+        slot.register(Operation::Consume, requirements.out.unwrap());
+
+        let (mut data, mut positions) = (Vec::new(), Vec::new());
+        loop {
+            let result = decoder.process(&mut InPort::new_none(), &mut slot.out_port(), &mut InPlacePort::new_none()).await.unwrap();
+            let payload = slot.acquire_read().await;
+            data.extend_from_slice(&payload[..]);
+            positions.push(payload.metadata.position);
+            if result == Eof {
+                return (data, positions);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_unknown_length_reads_until_eof() {
+        // 10 stereo 16-bit frames plus a trailing partial frame.
+        let mut wav_data = create_valid_wav_data();
+        wav_data.truncate(44 + 40 + 2);
+        wav_data[4..8].copy_from_slice(&UNKNOWN_SIZE.to_le_bytes());
+        wav_data[40..44].copy_from_slice(&UNKNOWN_SIZE.to_le_bytes());
+        for (i, byte) in wav_data[44..].iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+
+        let mut decoder = WavDecoder::new(MockReader::new(wav_data.clone()), 4);
+        let (data, positions) = decode_all(&mut decoder, 16).await;
+        assert_eq!(decoder.get_out_info().unwrap().num_frames, None);
+        assert_eq!(data, wav_data[44..84]);
+        assert_eq!(positions, [Position::First, Position::Middle, Position::Last]);
+        assert_eq!(decoder.current_frame(), 10);
+
+        // The end is known once reached.
+        assert_eq!(decoder.seek(100).unwrap(), 10);
+    }
+
+    #[tokio::test]
+    async fn test_tolerant_ignores_declared_size() {
+        // A streaming writer that left the data size at zero.
+        let mut wav_data = create_valid_wav_data();
+        wav_data[40..44].copy_from_slice(&0u32.to_le_bytes());
+
+        let mut decoder = WavDecoder::new(MockReader::new(wav_data.clone()), 16);
+        let (data, _) = decode_all(&mut decoder, 64).await;
+        assert!(data.is_empty());
+
+        let mut decoder = WavDecoder::new(MockReader::new(wav_data), 16).tolerant();
+        let (data, positions) = decode_all(&mut decoder, 64).await;
+        assert_eq!(data.len(), 64 * 4);
+        assert_eq!(positions.first(), Some(&Position::First));
+        assert_eq!(positions.last(), Some(&Position::Last));
+
+        // A file cut off before its declared size ends at the last whole frame.
+        let mut wav_data = create_valid_wav_data();
+        wav_data.truncate(44 + 30);
+        let mut decoder = WavDecoder::new(MockReader::new(wav_data), 16);
+        let (data, positions) = decode_all(&mut decoder, 64).await;
+        assert_eq!(data.len(), 28);
+        assert_eq!(positions, [Position::Single]);
+    }
+
+    #[tokio::test]
+    async fn test_metadata_after_data() {
+        // A LIST chunk with an odd-length field follows the audio data.
+        let mut wav_data = create_valid_wav_data();
+        let mut list = Vec::new();
+        list.extend_from_slice(b"INFO");
+        list.extend_from_slice(b"INAM");
+        list.extend_from_slice(&5u32.to_le_bytes());
+        list.extend_from_slice(b"Rain\0\0");
+        list.extend_from_slice(b"ICMT");
+        list.extend_from_slice(&4u32.to_le_bytes());
+        list.extend_from_slice(b"odd\0");
+        wav_data.extend_from_slice(b"LIST");
+        wav_data.extend_from_slice(&(list.len() as u32).to_le_bytes());
+        wav_data.extend_from_slice(&list);
+        wav_data.extend_from_slice(b"cue ");
+        wav_data.extend_from_slice(&28u32.to_le_bytes());
+        wav_data.extend_from_slice(&1u32.to_le_bytes());
+        let mut point = [0u8; 24];
+        point[0] = 7;
+        point[20] = 32;
+        wav_data.extend_from_slice(&point);
+
+        let mut decoder = WavDecoder::new(MockReader::new(wav_data.clone()), 16);
+        decoder.initialize(None).await.unwrap();
+        assert_eq!(decoder.get_out_info().unwrap().num_frames, Some(64));
+        let metadata = decoder.metadata();
+        assert_eq!(metadata.title(), Some("Rain"));
+        assert_eq!(metadata.info(crate::metadata::INFO_COMMENT), Some("odd"));
+        assert_eq!(metadata.cues[..], [crate::metadata::CuePoint { id: 7, frame: 32 }]);
+        assert_eq!(metadata.bext, None);
+
+        // Metadata is parsed again after a reset.
+        decoder.reset().await.unwrap();
+        assert!(decoder.metadata().is_empty());
+        decoder.initialize(None).await.unwrap();
+        assert_eq!(decoder.metadata().title(), Some("Rain"));
+
+        // A malformed metadata chunk does not make the file unreadable.
+        // The INAM field claims more bytes than its LIST chunk holds.
+        wav_data[316..320].copy_from_slice(&1000u32.to_le_bytes());
+        let mut decoder = WavDecoder::new(MockReader::new(wav_data), 16);
+        decoder.initialize(None).await.unwrap();
+        assert_eq!(decoder.metadata().title(), None);
+        assert_eq!(decoder.metadata().cues.len(), 1);
+    }
+
     #[tokio::test]
     async fn test_invalid_header_fails_parsing() {
         // Test case: Ensure initialize returns an error for an invalid RIFF header.
@@ -1,2 +1,2 @@
 mod wav;
-pub use wav::WavEncoder;
\ No newline at end of file
+pub use wav::{Streaming, WavEncoder, WavWriter};
\ No newline at end of file
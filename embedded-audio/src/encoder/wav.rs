@@ -1,118 +1,359 @@
 //! A simple WAV Encoder.
 
-use embedded_io::{Seek, SeekFrom, Write};
+use embedded_io::{ErrorType, Seek, SeekFrom, Write};
 
 use embedded_audio_driver::databus::{Consumer, Producer, Transformer};
 use embedded_audio_driver::element::{BaseElement, ProcessResult, Eof, Fine};
-use embedded_audio_driver::info::Info;
+use embedded_audio_driver::info::{Endianness, Info, SampleEncoding};
 use embedded_audio_driver::payload::Position;
 use embedded_audio_driver::port::{InPlacePort, InPort, OutPort, PayloadSize, PortRequirements};
 use embedded_audio_driver::Error;
 
+use crate::metadata::WavMetadata;
+use crate::riff::{DS64_CHUNK_LEN, SUBFORMAT_GUID_TAIL, UNKNOWN_SIZE, WAVE_FORMAT_EXTENSIBLE, WAVE_FORMAT_IEEE_FLOAT, WAVE_FORMAT_PCM};
+
+/// Builds a `ds64` chunk with an empty chunk size table.
+fn ds64_chunk(riff_size: u64, data_size: u64, sample_count: u64) -> [u8; DS64_CHUNK_LEN] {
+    let mut chunk = [0u8; DS64_CHUNK_LEN];
+    chunk[0..4].copy_from_slice(b"ds64");
+    chunk[4..8].copy_from_slice(&(DS64_CHUNK_LEN as u32 - 8).to_le_bytes());
+    chunk[8..16].copy_from_slice(&riff_size.to_le_bytes());
+    chunk[16..24].copy_from_slice(&data_size.to_le_bytes());
+    chunk[24..32].copy_from_slice(&sample_count.to_le_bytes());
+    chunk
+}
+
+/// The output of a [`WavEncoder`].
+///
+/// Every writer that implements `Write` and `Seek` is a `WavWriter`: the RIFF sizes are
+/// patched once the stream ends. Wrap a writer that cannot seek in [`Streaming`].
+pub trait WavWriter: Write {
+    /// Whether the header can be rewritten after the audio data.
+    const SEEKABLE: bool;
+
+    /// Writes `bytes` at `offset` from the start of the file, then moves back to `end`.
+    fn write_at(&mut self, offset: u64, bytes: &[u8], end: u64) -> Result<(), Self::Error>;
+}
+
+impl<W: Write + Seek> WavWriter for W {
+    const SEEKABLE: bool = true;
+
+    fn write_at(&mut self, offset: u64, bytes: &[u8], end: u64) -> Result<(), Self::Error> {
+        self.seek(SeekFrom::Start(offset))?;
+        self.write_all(bytes)?;
+        self.seek(SeekFrom::Start(end))?;
+        Ok(())
+    }
+}
+
+/// A writer that cannot seek, such as a UART, USB CDC or network sink.
+///
+/// The header is written once, before any audio data. If the upstream `Info` carries
+/// `num_frames`, the header declares exactly that many frames: excess frames are dropped
+/// and a stream that ends early is padded with silence. Otherwise both RIFF sizes are
+/// set to `0xFFFFFFFF`, which readers take as "unknown length, read until the end".
+pub struct Streaming<W>(pub W);
+
+impl<W: ErrorType> ErrorType for Streaming<W> {
+    type Error = W::Error;
+}
+
+impl<W: Write> Write for Streaming<W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        self.0.flush()
+    }
+}
+
+impl<W: Write> WavWriter for Streaming<W> {
+    const SEEKABLE: bool = false;
+
+    /// Never called: a streaming header is final when it is written.
+    fn write_at(&mut self, _offset: u64, _bytes: &[u8], _end: u64) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
 /// A WAV encoder.
 ///
 /// This element consumes audio data from an input port and writes it into a
-/// WAV-formatted stream using an internal writer that implements `Write` and `Seek`.
-pub struct WavEncoder<W: Write + Seek> {
+/// WAV-formatted stream using an internal [`WavWriter`].
+pub struct WavEncoder<W: WavWriter> {
     writer: W,
     info: Option<Info>,
     encoded_frames: u64,
+    /// The number of frames a streaming header declares, if known.
+    declared_frames: Option<u64>,
+    metadata: WavMetadata,
     header_written: bool,
+    header_len: u64,
     data_size_pos: u64,
     bytes_per_frame: u32,
     frames_per_process: u16,
 }
 
-impl<W: Write + Seek> WavEncoder<W> {
+impl<W: Write> WavEncoder<Streaming<W>> {
+    /// Creates a new WAV encoder that writes to a writer that cannot seek.
+    ///
+    /// See [`Streaming`] for how the header sizes are chosen.
+    pub fn streaming(writer: W, frames_per_process: u16) -> Self {
+        Self::new(Streaming(writer), frames_per_process)
+    }
+}
+
+impl<W: WavWriter> WavEncoder<W> {
     /// Creates a new WAV encoder with a given writer.
     pub fn new(writer: W, frames_per_process: u16) -> Self {
         Self {
             writer,
             info: None,
             encoded_frames: 0,
+            declared_frames: None,
+            metadata: WavMetadata::new(),
             header_written: false,
+            header_len: 0,
             data_size_pos: 0,
             bytes_per_frame: 0,
             frames_per_process,
         }
     }
 
+    /// Writes `metadata` into the header, e.g. a title, a `bext` chunk or cue points.
+    pub fn with_metadata(mut self, metadata: WavMetadata) -> Self {
+        self.metadata = metadata;
+        self
+    }
+
+    /// Checks that samples described by `info` can be stored in a WAV file.
+    ///
+    /// WAV requires little-endian samples, unsigned 8-bit or signed wider integer PCM,
+    /// or IEEE float.
+    fn check_format(info: &Info) -> Result<(), Error> {
+        if info.endianness != Endianness::Little {
+            return Err(Error::Unsupported);
+        }
+        match info.encoding {
+            SampleEncoding::Unsigned if info.bits_per_sample == 8 => Ok(()),
+            SampleEncoding::Signed if info.bits_per_sample > 8 => Ok(()),
+            SampleEncoding::Float => Ok(()),
+            _ => Err(Error::Unsupported),
+        }
+    }
+
     /// Writes the WAV header to the output writer.
+    ///
+    /// `WAVE_FORMAT_EXTENSIBLE` is used when the format cannot be described by a plain
+    /// `fmt ` chunk: more than two channels, fewer valid bits than the container,
+    /// or an explicit channel mask.
+    ///
+    /// The chunks of [`WavMetadata`] are written between the `fmt ` and `data` chunks.
+    ///
+    /// Seekable files reserve a `JUNK` chunk right after the RIFF header, which becomes the
+    /// `ds64` chunk if the file has to be promoted to RF64. A streaming header that declares
+    /// more data than 32-bit sizes can describe is written as RF64 straight away.
     fn write_header(&mut self) -> Result<(), Error>
     where
         <W as embedded_io::ErrorType>::Error: core::fmt::Debug,
     {
         let info = self.info.ok_or(Error::NotInitialized)?;
-        
-        let mut header = [0u8; 44];
-        
+
+        let format_tag = if info.is_float() { WAVE_FORMAT_IEEE_FLOAT } else { WAVE_FORMAT_PCM };
+        let extensible = info.channels > 2
+            || info.valid_bits_per_sample != info.bits_per_sample
+            || info.channel_mask != 0;
+        let fmt_size: usize = match (extensible, info.is_float()) {
+            (true, _) => 40,
+            (false, true) => 18, // Non-PCM formats carry a (zero) cbSize field.
+            (false, false) => 16,
+        };
+
+        let metadata_len = self.metadata.chunks_len();
+
+        // A streaming header cannot be patched later, so it carries the final sizes.
+        let mut rf64 = false;
+        if !W::SEEKABLE {
+            self.declared_frames = info.num_frames;
+            rf64 = info.num_frames.is_some_and(|frames| {
+                // RIFF header, ds64 chunk, fmt chunk, metadata and data chunk header.
+                let header_len = 12 + DS64_CHUNK_LEN + 8 + fmt_size + metadata_len + 8;
+                header_len as u64 - 8 + frames * self.bytes_per_frame as u64 >= UNKNOWN_SIZE as u64
+            });
+        }
+        let reserve_ds64 = W::SEEKABLE || rf64;
+
+        let mut header = [0u8; 12 + DS64_CHUNK_LEN + 8 + 40 + 8];
+
         // RIFF header
-        header[0..4].copy_from_slice(b"RIFF");
+        header[0..4].copy_from_slice(if rf64 { b"RF64" } else { b"RIFF" });
         header[4..8].copy_from_slice(&0u32.to_le_bytes()); // File size placeholder
         header[8..12].copy_from_slice(b"WAVE");
-        
+
+        // "JUNK" chunk, replaced by "ds64" on promotion to RF64
+        let mut pos = 12;
+        if reserve_ds64 {
+            header[12..16].copy_from_slice(b"JUNK");
+            header[16..20].copy_from_slice(&(DS64_CHUNK_LEN as u32 - 8).to_le_bytes());
+            pos += DS64_CHUNK_LEN;
+        }
+
         // "fmt " chunk
-        header[12..16].copy_from_slice(b"fmt ");
-        header[16..20].copy_from_slice(&16u32.to_le_bytes()); // Subchunk1Size for PCM
-        header[20..22].copy_from_slice(&1u16.to_le_bytes());  // AudioFormat (1 for PCM)
-        header[22..24].copy_from_slice(&(info.channels as u16).to_le_bytes());
-        header[24..28].copy_from_slice(&info.sample_rate.to_le_bytes());
-        
+        header[pos..pos + 4].copy_from_slice(b"fmt ");
+        header[pos + 4..pos + 8].copy_from_slice(&(fmt_size as u32).to_le_bytes());
+        let fmt = &mut header[pos + 8..pos + 8 + fmt_size];
+        let tag = if extensible { WAVE_FORMAT_EXTENSIBLE } else { format_tag };
+        fmt[0..2].copy_from_slice(&tag.to_le_bytes());
+        fmt[2..4].copy_from_slice(&(info.channels as u16).to_le_bytes());
+        fmt[4..8].copy_from_slice(&info.sample_rate.to_le_bytes());
+
         let byte_rate = info.sample_rate * info.channels as u32 * (info.bits_per_sample as u32 / 8);
-        header[28..32].copy_from_slice(&byte_rate.to_le_bytes());
-        
+        fmt[8..12].copy_from_slice(&byte_rate.to_le_bytes());
+
         let block_align = info.channels as u16 * (info.bits_per_sample as u16 / 8);
-        header[32..34].copy_from_slice(&block_align.to_le_bytes());
-        header[34..36].copy_from_slice(&(info.bits_per_sample as u16).to_le_bytes());
-        
-        // "data" chunk
-        header[36..40].copy_from_slice(b"data");
-        header[40..44].copy_from_slice(&0u32.to_le_bytes()); // Data size placeholder
-        
-        self.writer.write_all(&header).map_err(|_| Error::DeviceError)?;
-        
+        fmt[12..14].copy_from_slice(&block_align.to_le_bytes());
+        fmt[14..16].copy_from_slice(&(info.bits_per_sample as u16).to_le_bytes());
+
+        if fmt_size > 16 {
+            let cb_size = (fmt_size - 18) as u16;
+            fmt[16..18].copy_from_slice(&cb_size.to_le_bytes());
+        }
+        if extensible {
+            fmt[18..20].copy_from_slice(&(info.valid_bits_per_sample as u16).to_le_bytes());
+            fmt[20..24].copy_from_slice(&info.channel_mask.to_le_bytes());
+            fmt[24..26].copy_from_slice(&format_tag.to_le_bytes());
+            fmt[26..40].copy_from_slice(&SUBFORMAT_GUID_TAIL);
+        }
+        pos += 8 + fmt_size;
+
+        // "data" chunk, written after the metadata chunks
+        header[pos..pos + 4].copy_from_slice(b"data");
+        header[pos + 4..pos + 8].copy_from_slice(&0u32.to_le_bytes()); // Data size placeholder
+        let header_len = pos + metadata_len + 8;
+
+        if !W::SEEKABLE {
+            let (file_size, data_size) = match self.declared_frames {
+                Some(frames) if rf64 => {
+                    let data_size = frames * self.bytes_per_frame as u64;
+                    let ds64 = ds64_chunk(header_len as u64 - 8 + data_size, data_size, frames);
+                    header[12..12 + DS64_CHUNK_LEN].copy_from_slice(&ds64);
+                    (UNKNOWN_SIZE, UNKNOWN_SIZE)
+                }
+                Some(frames) => {
+                    let data_size = (frames * self.bytes_per_frame as u64) as u32;
+                    (header_len as u32 - 8 + data_size, data_size)
+                }
+                None => (UNKNOWN_SIZE, UNKNOWN_SIZE),
+            };
+            header[4..8].copy_from_slice(&file_size.to_le_bytes());
+            header[pos + 4..pos + 8].copy_from_slice(&data_size.to_le_bytes());
+        }
+
+        self.writer.write_all(&header[..pos]).map_err(|_| Error::DeviceError)?;
+        self.metadata.write_chunks(&mut self.writer)?;
+        self.writer.write_all(&header[pos..pos + 8]).map_err(|_| Error::DeviceError)?;
+
         self.header_written = true;
-        self.data_size_pos = 40; // Position of the data size field in the header
-        
+        self.header_len = header_len as u64;
+        self.data_size_pos = (pos + metadata_len + 4) as u64; // Position of the data size field in the header
+
         Ok(())
     }
 
     /// Updates the size fields in the WAV header after all data has been written.
+    ///
+    /// Files whose sizes do not fit in 32 bits are promoted to RF64: the reserved `JUNK`
+    /// chunk becomes a `ds64` chunk holding the 64-bit sizes, and the 32-bit fields are
+    /// set to `0xFFFFFFFF`.
     fn update_header_sizes(&mut self) -> Result<(), Error>
     where
         <W as embedded_io::ErrorType>::Error: core::fmt::Debug,
     {
         let data_size = self.encoded_frames * self.bytes_per_frame as u64;
-        let file_size = 36 + data_size;
+        let file_size = self.header_len - 8 + data_size;
+
+        // Every write seeks back to the end of the file for any subsequent operations.
+        let end = self.header_len + data_size;
 
-        // Update file size in RIFF header
-        self.writer.seek(SeekFrom::Start(4)).map_err(|_| Error::DeviceError)?;
-        self.writer.write_all(&(file_size as u32).to_le_bytes()).map_err(|_| Error::DeviceError)?;
+        if file_size < UNKNOWN_SIZE as u64 {
+            // Update file size in RIFF header
+            self.writer.write_at(4, &(file_size as u32).to_le_bytes(), end).map_err(|_| Error::DeviceError)?;
 
-        // Update data chunk size
-        self.writer.seek(SeekFrom::Start(self.data_size_pos)).map_err(|_| Error::DeviceError)?;
-        self.writer.write_all(&(data_size as u32).to_le_bytes()).map_err(|_| Error::DeviceError)?;
+            // Update data chunk size
+            self.writer.write_at(self.data_size_pos, &(data_size as u32).to_le_bytes(), end).map_err(|_| Error::DeviceError)?;
+        } else {
+            let mut riff = [0u8; 8];
+            riff[0..4].copy_from_slice(b"RF64");
+            riff[4..8].copy_from_slice(&UNKNOWN_SIZE.to_le_bytes());
+            self.writer.write_at(0, &riff, end).map_err(|_| Error::DeviceError)?;
+
+            let ds64 = ds64_chunk(file_size, data_size, self.encoded_frames);
+            self.writer.write_at(12, &ds64, end).map_err(|_| Error::DeviceError)?;
 
-        // Seek back to the end of the file for any subsequent operations.
-        self.writer.seek(SeekFrom::Start(44 + data_size)).map_err(|_| Error::DeviceError)?;
+            self.writer.write_at(self.data_size_pos, &UNKNOWN_SIZE.to_le_bytes(), end).map_err(|_| Error::DeviceError)?;
+        }
+
+        Ok(())
+    }
 
+    /// Pads a streaming file with silence up to the number of frames its header declares.
+    fn pad_to_declared_frames(&mut self) -> Result<(), Error>
+    where
+        <W as embedded_io::ErrorType>::Error: core::fmt::Debug,
+    {
+        let Some(declared_frames) = self.declared_frames else {
+            return Ok(());
+        };
+        let missing_frames = declared_frames.saturating_sub(self.encoded_frames);
+        if missing_frames == 0 {
+            return Ok(());
+        }
+        warn!("WavEncoder: stream ended {} frames early, padding with silence", missing_frames);
+
+        // Unsigned 8-bit PCM is centred on 0x80.
+        let silence = match self.info.map(|info| info.encoding) {
+            Some(SampleEncoding::Unsigned) => 0x80,
+            _ => 0,
+        };
+        let chunk = [silence; 64];
+        let mut remaining = missing_frames * self.bytes_per_frame as u64;
+        while remaining > 0 {
+            let len = remaining.min(chunk.len() as u64) as usize;
+            self.writer.write_all(&chunk[..len]).map_err(|_| Error::DeviceError)?;
+            remaining -= len as u64;
+        }
+        self.encoded_frames = declared_frames;
         Ok(())
     }
 
-    /// Finalizes the WAV file by updating header sizes.
+    /// Completes the header once the last audio data has been written.
+    fn finish(&mut self) -> Result<(), Error>
+    where
+        <W as embedded_io::ErrorType>::Error: core::fmt::Debug,
+    {
+        if W::SEEKABLE {
+            self.update_header_sizes()
+        } else {
+            self.pad_to_declared_frames()
+        }
+    }
+
+    /// Finalizes the WAV file by updating header sizes, or, in streaming mode, by padding
+    /// it to the declared length.
     /// This should be called if the stream ends unexpectedly without a `Last` or `Single` payload.
     pub fn finalize(&mut self) -> Result<(), Error>
     where
         <W as embedded_io::ErrorType>::Error: core::fmt::Debug,
     {
         if self.header_written {
-            self.update_header_sizes()?;
+            self.finish()?;
         }
         Ok(())
     }
 }
 
-impl<W: Write + Seek> BaseElement for WavEncoder<W>
+impl<W: WavWriter> BaseElement for WavEncoder<W>
 where
     <W as embedded_io::ErrorType>::Error: core::fmt::Debug,
 {
@@ -139,6 +380,7 @@ where
         if !info.vaild() {
             return Err(Error::InvalidParameter);
         }
+        Self::check_format(&info)?;
 
         self.bytes_per_frame = info.get_alignment_bytes() as u32;
         self.info = Some(info);
@@ -152,7 +394,9 @@ where
     async fn reset(&mut self) -> Result<(), Self::Error> {
         self.info = None;
         self.encoded_frames = 0;
+        self.declared_frames = None;
         self.header_written = false;
+        self.header_len = 0;
         self.data_size_pos = 0;
         self.bytes_per_frame = 0;
         // TODO: The internal writer is NOT reset. A new instance should be created for a new file.
@@ -180,16 +424,25 @@ where
             if payload.is_empty() {
                  // If the last payload is empty, we still need to finalize the header.
                  if payload.metadata.position == Position::Last || payload.metadata.position == Position::Single {
-                    self.update_header_sizes()?;
+                    self.finish()?;
                     return Ok(Eof);
                 }
                 return Ok(Fine);
             }
-            
+
             let data_to_write = &payload[..];
 
             // Ensure we only write full frames.
-            let aligned_len = (data_to_write.len() as u32 / self.bytes_per_frame) * self.bytes_per_frame;
+            let mut aligned_len = (data_to_write.len() as u32 / self.bytes_per_frame) * self.bytes_per_frame;
+
+            // A streaming header cannot grow, so frames beyond the declared length are dropped.
+            if let Some(declared_frames) = self.declared_frames {
+                let remaining = declared_frames.saturating_sub(self.encoded_frames) * self.bytes_per_frame as u64;
+                if aligned_len as u64 > remaining {
+                    warn!("WavEncoder: dropping {} bytes beyond the declared length", aligned_len as u64 - remaining);
+                    aligned_len = remaining as u32;
+                }
+            }
 
             if aligned_len > 0 {
                 self.writer.write_all(&data_to_write[..aligned_len as usize]).map_err(|_| Error::DeviceError)?;
@@ -199,7 +452,7 @@ where
 
             // If this is the last payload, update the header with the final sizes.
             if payload.metadata.position == Position::Last || payload.metadata.position == Position::Single {
-                self.update_header_sizes()?;
+                self.finish()?;
                 Ok(Eof)
             } else {
                 Ok(Fine)
@@ -268,11 +521,83 @@ mod tests {
         }
     }
     
+    // A writer that cannot seek, like a UART.
+    struct StreamWriter {
+        data: Vec<u8>,
+    }
+
+    impl ErrorType for StreamWriter {
+        type Error = core::convert::Infallible;
+    }
+
+    impl Write for StreamWriter {
+        fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+            self.data.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> Result<(), Self::Error> { Ok(()) }
+    }
+
+    // A writer that keeps only the first bytes of a file, so tests can write gigabytes.
+    struct SparseWriter {
+        head: [u8; 128],
+        len: u64,
+        position: u64,
+    }
+
+    impl SparseWriter {
+        fn new() -> Self {
+            Self { head: [0; 128], len: 0, position: 0 }
+        }
+    }
+
+    impl ErrorType for SparseWriter {
+        type Error = core::convert::Infallible;
+    }
+
+    impl Write for SparseWriter {
+        fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+            if let Some(head) = self.head.get_mut(self.position as usize..) {
+                let n = head.len().min(buf.len());
+                head[..n].copy_from_slice(&buf[..n]);
+            }
+            self.position += buf.len() as u64;
+            self.len = self.len.max(self.position);
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> Result<(), Self::Error> { Ok(()) }
+    }
+
+    impl Seek for SparseWriter {
+        fn seek(&mut self, pos: SeekFrom) -> Result<u64, Self::Error> {
+            self.position = match pos {
+                SeekFrom::Start(p) => p,
+                SeekFrom::End(p) => (self.len as i64 + p) as u64,
+                SeekFrom::Current(p) => (self.position as i64 + p) as u64,
+            };
+            Ok(self.position)
+        }
+    }
+
+    // Writes `bytes` into the slot and lets the encoder consume them.
+    async fn encode<W: WavWriter>(encoder: &mut WavEncoder<W>, slot: &mut HeapSlot, bytes: &[u8], position: Position) -> ProcessResult<Error>
+    where
+        <W as ErrorType>::Error: core::fmt::Debug,
+    {
+        {
+            let mut p = slot.acquire_write().await;
+            p[..bytes.len()].copy_from_slice(bytes);
+            p.set_valid_length(bytes.len());
+            p.set_position(position);
+        }
+        encoder.process(&mut slot.in_port(), &mut OutPort::new_none(), &mut InPlacePort::new_none()).await
+    }
+
     #[tokio::test]
     async fn test_process_writes_header_and_data() {
         let writer = MockWriter::new();
         let mut encoder = WavEncoder::new(writer, 64);
-        let info = Info { sample_rate: 44100, channels: 1, bits_per_sample: 16, num_frames: None };
+        let info = Info::new(44100, 1, 16, None);
         
         let requirements = encoder.initialize(Some(info)).await.unwrap();
 
@@ -296,18 +621,20 @@ mod tests {
         assert!(encoder.header_written);
         assert_eq!(encoder.encoded_frames, 2); // 4 bytes / (16 bits/8 * 1 channel) = 2 frames
         let written_data = encoder.writer.get_data();
-        assert_eq!(written_data.len(), 44 + 4);
-        assert_eq!(&written_data[44..48], &[0x12, 0x34, 0x56, 0x78]);
+        assert_eq!(written_data.len(), 80 + 4);
+        assert_eq!(&written_data[80..84], &[0x12, 0x34, 0x56, 0x78]);
         // Header sizes should still be 0 because it wasn't the last packet.
         assert_eq!(&written_data[4..8], &[0, 0, 0, 0]);
-        assert_eq!(&written_data[40..44], &[0, 0, 0, 0]);
+        assert_eq!(&written_data[76..80], &[0, 0, 0, 0]);
+        // A JUNK chunk is reserved for a possible promotion to RF64.
+        assert_eq!(&written_data[12..16], b"JUNK");
     }
 
     #[tokio::test]
     async fn test_process_last_chunk_updates_header() {
         let writer = MockWriter::new();
         let mut encoder = WavEncoder::new(writer, 300);
-        let info = Info { sample_rate: 8000, channels: 2, bits_per_sample: 16, num_frames: None };
+        let info = Info::new(8000, 2, 16, None);
         
         let requirements = encoder.initialize(Some(info)).await.unwrap();
 
@@ -332,9 +659,183 @@ mod tests {
 
         let data_after_process = encoder.writer.get_data();
         let data_size = 1024u32;
-        let file_size = 36 + data_size;
+        let file_size = 72 + data_size;
 
         assert_eq!(&data_after_process[4..8], &file_size.to_le_bytes(), "File size was not updated correctly");
-        assert_eq!(&data_after_process[40..44], &data_size.to_le_bytes(), "Data chunk size was not updated correctly");
+        assert_eq!(&data_after_process[76..80], &data_size.to_le_bytes(), "Data chunk size was not updated correctly");
+    }
+
+    #[tokio::test]
+    async fn test_float_and_extensible_headers() {
+        // Plain IEEE float: fmt chunk with a zero cbSize.
+        let mut encoder = WavEncoder::new(MockWriter::new(), 64);
+        encoder.initialize(Some(Info::new_float(48000, 2, 32, None))).await.unwrap();
+        encoder.write_header().unwrap();
+        let data = encoder.writer.get_data();
+        assert_eq!(data.len(), 82);
+        assert_eq!(&data[52..56], &18u32.to_le_bytes());
+        assert_eq!(&data[56..58], &WAVE_FORMAT_IEEE_FLOAT.to_le_bytes());
+        assert_eq!(&data[74..78], b"data");
+
+        // 24 valid bits in a 32-bit container needs WAVE_FORMAT_EXTENSIBLE.
+        let mut info = Info::new(48000, 2, 32, None);
+        info.set_valid_bits_per_sample(24);
+        let mut encoder = WavEncoder::new(MockWriter::new(), 64);
+        encoder.initialize(Some(info)).await.unwrap();
+        encoder.write_header().unwrap();
+        encoder.finalize().unwrap();
+        let data = encoder.writer.get_data();
+        assert_eq!(data.len(), 104);
+        assert_eq!(&data[52..56], &40u32.to_le_bytes());
+        assert_eq!(&data[56..58], &WAVE_FORMAT_EXTENSIBLE.to_le_bytes());
+        assert_eq!(&data[74..76], &24u16.to_le_bytes());
+        assert_eq!(&data[80..82], &WAVE_FORMAT_PCM.to_le_bytes());
+        assert_eq!(&data[82..96], &SUBFORMAT_GUID_TAIL);
+        assert_eq!(&data[96..100], b"data");
+        assert_eq!(&data[4..8], &96u32.to_le_bytes());
+    }
+
+    #[tokio::test]
+    async fn test_unrepresentable_formats_rejected() {
+        let mut big_endian = Info::new(44100, 2, 16, None);
+        big_endian.set_endianness(Endianness::Big);
+        let mut encoder = WavEncoder::new(MockWriter::new(), 64);
+        assert!(matches!(encoder.initialize(Some(big_endian)).await, Err(Error::Unsupported)));
+
+        let mut signed_8bit = Info::new(44100, 2, 8, None);
+        signed_8bit.set_encoding(SampleEncoding::Signed);
+        let mut encoder = WavEncoder::new(MockWriter::new(), 64);
+        assert!(matches!(encoder.initialize(Some(signed_8bit)).await, Err(Error::Unsupported)));
+    }
+
+    #[tokio::test]
+    async fn test_streaming_unknown_length() {
+        let mut encoder = WavEncoder::streaming(StreamWriter { data: Vec::new() }, 4);
+        let requirements = encoder.initialize(Some(Info::new(8000, 1, 16, None))).await.unwrap();
+        let mut slot = HeapSlot::new_heap(8);
+        slot.register(Operation::Consume, requirements.in_.unwrap());
+        slot.register(Operation::Produce, requirements.in_.unwrap());
+
+        assert_eq!(encode(&mut encoder, &mut slot, &[1, 0, 2, 0], Position::First).await, Ok(Fine));
+        assert_eq!(encode(&mut encoder, &mut slot, &[3, 0], Position::Last).await, Ok(Eof));
+
+        let data = &encoder.writer.0.data;
+        assert_eq!(data.len(), 44 + 6);
+        assert_eq!(&data[4..8], &UNKNOWN_SIZE.to_le_bytes());
+        assert_eq!(&data[40..44], &UNKNOWN_SIZE.to_le_bytes());
+        assert_eq!(&data[44..], &[1, 0, 2, 0, 3, 0]);
+    }
+
+    #[tokio::test]
+    async fn test_streaming_declared_length() {
+        // A stream that ends early is padded with silence.
+        let mut encoder = WavEncoder::streaming(StreamWriter { data: Vec::new() }, 4);
+        let requirements = encoder.initialize(Some(Info::new(8000, 1, 8, Some(4)))).await.unwrap();
+        let mut slot = HeapSlot::new_heap(8);
+        slot.register(Operation::Consume, requirements.in_.unwrap());
+        slot.register(Operation::Produce, requirements.in_.unwrap());
+
+        assert_eq!(encode(&mut encoder, &mut slot, &[1, 2], Position::Last).await, Ok(Eof));
+        let data = &encoder.writer.0.data;
+        assert_eq!(&data[4..8], &(36u32 + 4).to_le_bytes());
+        assert_eq!(&data[40..44], &4u32.to_le_bytes());
+        assert_eq!(&data[44..], &[1, 2, 0x80, 0x80]);
+
+        // Frames beyond the declared length are dropped.
+        let mut encoder = WavEncoder::streaming(StreamWriter { data: Vec::new() }, 4);
+        encoder.initialize(Some(Info::new(8000, 1, 16, Some(2)))).await.unwrap();
+        assert_eq!(encode(&mut encoder, &mut slot, &[1, 0, 2, 0, 3, 0], Position::Middle).await, Ok(Fine));
+        assert_eq!(encode(&mut encoder, &mut slot, &[4, 0], Position::Last).await, Ok(Eof));
+        let data = &encoder.writer.0.data;
+        assert_eq!(&data[40..44], &4u32.to_le_bytes());
+        assert_eq!(&data[44..], &[1, 0, 2, 0]);
+    }
+
+    #[tokio::test]
+    async fn test_promotes_to_rf64() {
+        let mut encoder = WavEncoder::new(SparseWriter::new(), 4);
+        let requirements = encoder.initialize(Some(Info::new(48000, 2, 16, None))).await.unwrap();
+        let mut slot = HeapSlot::new_heap(1 << 20);
+        slot.register(Operation::Consume, requirements.in_.unwrap());
+        slot.register(Operation::Produce, requirements.in_.unwrap());
+
+        // 4 GiB of audio, then one more frame.
+        for _ in 0..4096 {
+            {
+                let mut p = slot.acquire_write().await;
+                p.set_valid_length(1 << 20);
+                p.set_position(Position::Middle);
+            }
+            encoder.process(&mut slot.in_port(), &mut OutPort::new_none(), &mut InPlacePort::new_none()).await.unwrap();
+        }
+        assert_eq!(encode(&mut encoder, &mut slot, &[0; 4], Position::Last).await, Ok(Eof));
+
+        let data_size = (1u64 << 32) + 4;
+        let writer = &encoder.writer;
+        assert_eq!(writer.len, 80 + data_size);
+        assert_eq!(writer.position, writer.len);
+        assert_eq!(&writer.head[0..4], b"RF64");
+        assert_eq!(&writer.head[4..8], &UNKNOWN_SIZE.to_le_bytes());
+        assert_eq!(&writer.head[12..16], b"ds64");
+        assert_eq!(&writer.head[16..20], &28u32.to_le_bytes());
+        assert_eq!(&writer.head[20..28], &(72 + data_size).to_le_bytes());
+        assert_eq!(&writer.head[28..36], &data_size.to_le_bytes());
+        assert_eq!(&writer.head[36..44], &(data_size / 4).to_le_bytes());
+        assert_eq!(&writer.head[72..76], b"data");
+        assert_eq!(&writer.head[76..80], &UNKNOWN_SIZE.to_le_bytes());
+    }
+
+    #[tokio::test]
+    async fn test_streaming_rf64_header() {
+        // A declared length beyond 4 GiB is written as RF64 up front.
+        let frames = 1u64 << 31;
+        let mut encoder = WavEncoder::streaming(StreamWriter { data: Vec::new() }, 64);
+        encoder.initialize(Some(Info::new(48000, 2, 16, Some(frames)))).await.unwrap();
+        encoder.write_header().unwrap();
+
+        let data = &encoder.writer.0.data;
+        assert_eq!(data.len(), 80);
+        assert_eq!(&data[0..4], b"RF64");
+        assert_eq!(&data[4..8], &UNKNOWN_SIZE.to_le_bytes());
+        assert_eq!(&data[12..16], b"ds64");
+        assert_eq!(&data[20..28], &(72 + frames * 4).to_le_bytes());
+        assert_eq!(&data[28..36], &(frames * 4).to_le_bytes());
+        assert_eq!(&data[36..44], &frames.to_le_bytes());
+        assert_eq!(&data[76..80], &UNKNOWN_SIZE.to_le_bytes());
+    }
+
+    #[tokio::test]
+    async fn test_metadata_round_trip() {
+        use crate::decoder::WavDecoder;
+        use crate::metadata::{Bext, INFO_TITLE};
+        use embedded_audio_driver::element::Seekable;
+        use embedded_io_adapters::std::FromStd;
+
+        let mut metadata = WavMetadata::new();
+        metadata.set_info(INFO_TITLE, "Dawn chorus").unwrap();
+        let mut bext = Bext::default();
+        bext.originator_reference.push_str("FR-0042").unwrap();
+        bext.origination_date.push_str("2024-05-01").unwrap();
+        metadata.bext = Some(bext);
+        metadata.add_cue(1).unwrap();
+
+        let mut encoder = WavEncoder::new(MockWriter::new(), 4).with_metadata(metadata.clone());
+        let requirements = encoder.initialize(Some(Info::new(8000, 1, 16, None))).await.unwrap();
+        let mut slot = HeapSlot::new_heap(8);
+        slot.register(Operation::Consume, requirements.in_.unwrap());
+        slot.register(Operation::Produce, requirements.in_.unwrap());
+        assert_eq!(encode(&mut encoder, &mut slot, &[1, 0, 2, 0], Position::Single).await, Ok(Eof));
+
+        let data = encoder.writer.get_data().to_vec();
+        let header_len = 80 + metadata.chunks_len();
+        assert_eq!(data.len(), header_len + 4);
+        assert_eq!(&data[header_len - 8..header_len - 4], b"data");
+        assert_eq!(&data[4..8], &(header_len as u32 - 8 + 4).to_le_bytes());
+
+        let mut decoder = WavDecoder::new(FromStd::new(std::io::Cursor::new(data)), 4);
+        decoder.initialize(None).await.unwrap();
+        assert_eq!(decoder.metadata(), &metadata);
+        assert_eq!(decoder.get_out_info().unwrap().num_frames, Some(2));
+        assert_eq!(decoder.current_frame(), 0);
     }
 }
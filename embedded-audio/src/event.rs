@@ -0,0 +1,283 @@
+//! Notifications from elements and runners.
+//!
+//! An [`EventBus`] carries typed [`Event`]s from the audio path to other tasks, e.g. a UI.
+//! Elements and runners post through an [`EventSender`], which tags every event with the
+//! index of the element it concerns. Events are queued in a [`Channel`]; posting never
+//! waits, so an event is dropped if the queue is full. Progress is reported separately
+//! through one [`Signal`] per element that only keeps the latest value, so it cannot crowd out
+//! events and reports of different elements do not replace each other.
+//!
+//! The bus can be created in a `static` on targets without an allocator.
+
+use core::cell::Cell;
+
+use embedded_audio_driver::databus::{Consumer, Producer, Transformer};
+use embedded_audio_driver::info::Info;
+use embedded_audio_driver::payload::Payload;
+use embedded_audio_driver::stream::StreamState;
+use embedded_audio_driver::Error;
+
+use crate::{Channel, Signal};
+
+/// The maximum number of queued events.
+pub const EVENT_QUEUE: usize = 32;
+
+/// The number of elements that can report progress; reports of higher indices are dropped.
+pub const PROGRESS_ELEMENTS: usize = 8;
+
+/// What happened to an element.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventKind {
+    /// A stream changed its state.
+    State(StreamState),
+    /// The output format of an element was set or changed.
+    Format(Info),
+    /// An element returned `Eof`.
+    Eof,
+    /// An output stream ran out of data and played silence.
+    Underrun,
+    /// An input stream had no room for captured samples and dropped them.
+    Overrun,
+    /// An element failed.
+    Error(Error),
+}
+
+/// An event and the index of the element it concerns.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Event {
+    pub element: usize,
+    pub kind: EventKind,
+}
+
+/// A queue of events, shared between the audio path and its observers.
+pub struct EventBus {
+    events: Channel<Event, EVENT_QUEUE>,
+    /// The number of frames each element has processed so far.
+    progress: [Signal<u64>; PROGRESS_ELEMENTS],
+}
+
+impl EventBus {
+    pub const fn new() -> Self {
+        Self {
+            events: Channel::new(),
+            progress: [const { Signal::new() }; PROGRESS_ELEMENTS],
+        }
+    }
+
+    /// Returns a sender that posts events for the element at `element`.
+    pub fn sender(&self, element: usize) -> EventSender<'_> {
+        EventSender { bus: self, element }
+    }
+
+    /// Waits for the next event.
+    pub async fn receive(&self) -> Event {
+        self.events.receive().await
+    }
+
+    /// Returns the next event, if one is queued.
+    pub fn try_receive(&self) -> Option<Event> {
+        self.events.try_receive().ok()
+    }
+
+    /// Waits for the next progress report of the element at `element`, in frames. Reports
+    /// posted in between are skipped.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `element` is not below [`PROGRESS_ELEMENTS`].
+    pub async fn progress(&self, element: usize) -> u64 {
+        self.progress[element].wait().await
+    }
+
+    /// Returns the latest progress report of the element at `element`, if one was posted since
+    /// the last call.
+    pub fn try_progress(&self, element: usize) -> Option<u64> {
+        self.progress.get(element).and_then(Signal::try_take)
+    }
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Posts events for one element to an [`EventBus`].
+#[derive(Clone, Copy)]
+pub struct EventSender<'b> {
+    bus: &'b EventBus,
+    element: usize,
+}
+
+impl<'b> EventSender<'b> {
+    pub fn element(&self) -> usize {
+        self.element
+    }
+
+    /// Returns a sender on the same bus for the element at `element`.
+    pub fn for_element(&self, element: usize) -> Self {
+        self.bus.sender(element)
+    }
+
+    /// Queues an event. Returns `false` if the queue was full and the event was dropped.
+    pub fn post(&self, kind: EventKind) -> bool {
+        let event = Event { element: self.element, kind };
+        if self.bus.events.try_send(event).is_err() {
+            warn!("EventBus: queue full, dropping an event of element {}", self.element);
+            return false;
+        }
+        true
+    }
+
+    /// Reports the number of frames processed so far, replacing any unread report of the same
+    /// element.
+    pub fn progress(&self, frames: u64) {
+        match self.bus.progress.get(self.element) {
+            Some(progress) => progress.signal(frames),
+            None => warn!("EventBus: no progress slot for element {}", self.element),
+        }
+    }
+}
+
+/// Posts the events of a runner with up to `N` elements to an optional bus.
+pub(crate) struct Notifier<'e, const N: usize> {
+    pub(crate) events: Option<&'e EventBus>,
+    /// The last reported output `Info` of each element.
+    infos: [Option<Info>; N],
+    /// The last reported state of each element.
+    states: [Option<StreamState>; N],
+    /// The number of frames each element has processed.
+    frames: [u64; N],
+}
+
+impl<'e, const N: usize> Notifier<'e, N> {
+    pub(crate) const fn new() -> Self {
+        Self {
+            events: None,
+            infos: [None; N],
+            states: [None; N],
+            frames: [0; N],
+        }
+    }
+
+    /// Posts `state` for `element` if it differs from the last reported one.
+    pub(crate) fn state(&mut self, element: usize, state: StreamState) {
+        if self.states[element] != Some(state) {
+            self.states[element] = Some(state);
+            self.post(element, EventKind::State(state));
+        }
+    }
+
+    /// Marks the first `len` elements initialized and starts counting their progress over.
+    pub(crate) fn initialized(&mut self, len: usize) {
+        self.frames = [0; N];
+        for element in 0..len {
+            self.state(element, StreamState::Initialized);
+        }
+    }
+
+    /// Adds `frames` to the progress of `element` and reports the total.
+    pub(crate) fn progress(&mut self, element: usize, frames: usize) {
+        if frames == 0 {
+            return;
+        }
+        self.frames[element] += frames as u64;
+        if let Some(events) = self.events {
+            events.sender(element).progress(self.frames[element]);
+        }
+    }
+
+    pub(crate) fn post(&self, element: usize, kind: EventKind) {
+        if let Some(events) = self.events {
+            events.sender(element).post(kind);
+        }
+    }
+
+    /// Posts every output `Info` of the first `len` elements that differs from the last
+    /// reported one.
+    pub(crate) fn formats(&mut self, len: usize, out_info: impl Fn(usize) -> Option<Info>) {
+        for (element, reported) in self.infos[..len].iter_mut().enumerate() {
+            let info = out_info(element);
+            if info != *reported {
+                *reported = info;
+                if let (Some(events), Some(info)) = (self.events, info) {
+                    events.sender(element).post(EventKind::Format(info));
+                }
+            }
+        }
+    }
+}
+
+/// A databus that counts the bytes read from it, so a runner can report the progress of the
+/// elements it connects.
+pub(crate) struct Metered<'d, D> {
+    databus: &'d D,
+    bytes: Cell<usize>,
+}
+
+impl<'d, D> Metered<'d, D> {
+    pub(crate) fn new(databus: &'d D) -> Self {
+        Self { databus, bytes: Cell::new(0) }
+    }
+
+    /// Returns the number of bytes read so far.
+    pub(crate) fn bytes(&self) -> usize {
+        self.bytes.get()
+    }
+}
+
+impl<'a, D: Consumer<'a>> Consumer<'a> for Metered<'_, D> {
+    async fn acquire_read(&'a self) -> Payload<'a> {
+        let payload = self.databus.acquire_read().await;
+        self.bytes.set(self.bytes.get() + payload.len());
+        payload
+    }
+}
+
+impl<'a, D: Producer<'a>> Producer<'a> for Metered<'_, D> {
+    async fn acquire_write(&'a self) -> Payload<'a> {
+        self.databus.acquire_write().await
+    }
+}
+
+impl<'a, D: Transformer<'a>> Transformer<'a> for Metered<'_, D> {
+    async fn acquire_transform(&'a self) -> Payload<'a> {
+        self.databus.acquire_transform().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_events_are_tagged_and_queued() {
+        let bus = EventBus::new();
+        let sender = bus.sender(2);
+        assert!(sender.post(EventKind::State(StreamState::Running)));
+        assert!(sender.for_element(3).post(EventKind::Eof));
+
+        assert_eq!(bus.receive().await, Event { element: 2, kind: EventKind::State(StreamState::Running) });
+        assert_eq!(bus.try_receive(), Some(Event { element: 3, kind: EventKind::Eof }));
+        assert_eq!(bus.try_receive(), None);
+
+        // Posting never waits.
+        for _ in 0..EVENT_QUEUE {
+            assert!(sender.post(EventKind::Underrun));
+        }
+        assert!(!sender.post(EventKind::Error(Error::BufferFull)));
+    }
+
+    #[tokio::test]
+    async fn test_progress_keeps_latest_per_element() {
+        let bus = EventBus::new();
+        let sender = bus.sender(1);
+        sender.progress(64);
+        sender.for_element(2).progress(32);
+        sender.progress(128);
+        assert_eq!(bus.progress(1).await, 128);
+        assert_eq!(bus.try_progress(1), None);
+        assert_eq!(bus.try_progress(2), Some(32));
+        assert_eq!(bus.try_progress(PROGRESS_ELEMENTS), None);
+    }
+}
@@ -1,2 +1,7 @@
 pub mod sine_wave;
-pub use sine_wave::SineWaveGenerator;
\ No newline at end of file
+pub use sine_wave::SineWaveGenerator;
+
+#[cfg(feature = "std")]
+pub mod sequencer;
+#[cfg(feature = "std")]
+pub use sequencer::{Sequencer, Transition};
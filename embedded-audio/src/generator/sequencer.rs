@@ -0,0 +1,491 @@
+//! A source that plays a queue of sources one after another.
+//!
+//! The [`Sequencer`] initializes the next queued source while the current one plays. With
+//! [`Transition::Gapless`] the next source starts on the frame after the current one ends,
+//! within the same payload. With [`Transition::Crossfade`] the two overlap for the given
+//! duration; this requires knowing where the current source ends, so a source whose
+//! `available()` is unknown (`u32::MAX`) is joined gaplessly instead.
+//!
+//! Only the first payload of the first source is marked `First` and only the last payload of
+//! the last source is marked `Last`. Every source must produce the `Info` of the first one
+//! (apart from `num_frames`); sources that differ or fail to initialize are skipped.
+
+use std::collections::VecDeque;
+use std::vec::Vec;
+
+use embedded_audio_driver::databus::{Consumer, Databus, Operation, Producer, Transformer};
+use embedded_audio_driver::element::{BaseElement, Eof, Fine, ProcessResult};
+use embedded_audio_driver::info::Info;
+use embedded_audio_driver::payload::Position;
+use embedded_audio_driver::port::{InPlacePort, InPort, OutPort, PayloadSize, PortRequirements};
+use embedded_audio_driver::Error;
+
+use crate::databus::slot::HeapSlot;
+use crate::sample::SampleCodec;
+use crate::transformer::fade::FadeCurve;
+use crate::transformer::Gain;
+
+/// How consecutive sources are joined.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transition {
+    /// The next source starts right after the current one, sample-accurately.
+    Gapless,
+    /// The end of the current source overlaps with the start of the next one.
+    Crossfade { duration_ms: u32, curve: FadeCurve },
+}
+
+/// An initialized source with the payload it produced last.
+struct Track<S> {
+    source: S,
+    slot: HeapSlot,
+    buffer: Vec<u8>,
+    offset: usize,
+    eof: bool,
+}
+
+impl<S: BaseElement<Info = Info, Error = Error>> Track<S> {
+    async fn open(mut source: S) -> Result<Self, Error> {
+        let requirements = source.initialize(None).await?;
+        let size = requirements.out.ok_or(Error::Unsupported)?;
+        let mut slot = HeapSlot::new_heap(size.preferred as usize);
+        slot.register(Operation::Produce, size);
+        slot.register(Operation::Consume, size);
+        Ok(Self {
+            source,
+            slot,
+            buffer: Vec::with_capacity(size.preferred as usize),
+            offset: 0,
+            eof: false,
+        })
+    }
+
+    fn buffered(&self) -> usize {
+        self.buffer.len() - self.offset
+    }
+
+    fn is_finished(&self) -> bool {
+        self.eof && self.buffered() == 0
+    }
+
+    /// The number of frames left in the source, if known.
+    fn remaining_frames(&self, bytes_per_frame: usize) -> Option<u64> {
+        let buffered = (self.buffered() / bytes_per_frame) as u64;
+        match self.source.available() {
+            _ if self.eof => Some(buffered),
+            u32::MAX => None,
+            available => Some(buffered + available as u64),
+        }
+    }
+
+    /// Pulls the next payload from the source once the buffer has been drained.
+    async fn fill(&mut self) -> Result<(), Error> {
+        if self.eof || self.buffered() > 0 {
+            return Ok(());
+        }
+        // A drained source may return `Eof` without writing a payload.
+        if self.source.available() == 0 {
+            self.eof = true;
+            return Ok(());
+        }
+        let status = self
+            .source
+            .process(&mut InPort::new_none(), &mut self.slot.out_port(), &mut InPlacePort::new_none())
+            .await?;
+
+        let payload = self.slot.acquire_read().await;
+        self.buffer.clear();
+        self.buffer.extend_from_slice(&payload);
+        self.offset = 0;
+        self.eof = status == Eof || matches!(payload.metadata.position, Position::Last | Position::Single);
+        Ok(())
+    }
+
+    /// Takes the next buffered frame.
+    fn take_frame(&mut self, bytes_per_frame: usize) -> Option<&[u8]> {
+        let frame = self.buffer.get(self.offset..self.offset + bytes_per_frame)?;
+        self.offset += bytes_per_frame;
+        Some(frame)
+    }
+}
+
+/// A source Element that plays a queue of sources back to back.
+pub struct Sequencer<S> {
+    queue: VecDeque<S>,
+    current: Option<Track<S>>,
+    next: Option<Track<S>>,
+    transition: Transition,
+    info: Option<Info>,
+    codec: Option<SampleCodec>,
+    crossfade_frames: u64,
+    /// Frames into the running crossfade and its length.
+    fade: Option<(u64, u64)>,
+    started: bool,
+    frames_per_process: u16,
+}
+
+impl<S: BaseElement<Info = Info, Error = Error>> Sequencer<S> {
+    /// Creates an empty sequencer. At least one source must be queued before `initialize`.
+    pub fn new(transition: Transition, frames_per_process: u16) -> Self {
+        Self {
+            queue: VecDeque::new(),
+            current: None,
+            next: None,
+            transition,
+            info: None,
+            codec: None,
+            crossfade_frames: 0,
+            fade: None,
+            started: false,
+            frames_per_process,
+        }
+    }
+
+    /// Appends an uninitialized source to the queue. Sources may be queued while playing.
+    pub fn push(&mut self, source: S) {
+        self.queue.push_back(source);
+    }
+
+    /// The number of sources waiting behind the current one.
+    pub fn queued(&self) -> usize {
+        self.queue.len() + self.next.is_some() as usize
+    }
+
+    /// Initializes queued sources until one matches the format of the stream.
+    async fn prepare_next(&mut self) -> Result<(), Error> {
+        let info = self.info.ok_or(Error::NotInitialized)?;
+        while self.next.is_none() {
+            let Some(source) = self.queue.pop_front() else {
+                break;
+            };
+            match Track::open(source).await {
+                Ok(track) if track.source.get_out_info().is_some_and(|own| own.is_same_format(&info)) => {
+                    self.next = Some(track);
+                }
+                Ok(_) => warn!("Sequencer: skipping a source with a different format"),
+                Err(_) => warn!("Sequencer: skipping a source that failed to initialize"),
+            }
+        }
+        Ok(())
+    }
+
+    /// Replaces the current source with the next one while it is drained.
+    async fn skip_finished(&mut self) -> Result<(), Error> {
+        // Picks up sources pushed since the queue ran empty.
+        self.prepare_next().await?;
+        while let Some(current) = self.current.as_mut() {
+            current.fill().await?;
+            if !current.is_finished() {
+                break;
+            }
+            self.current = self.next.take();
+            self.fade = None;
+            self.prepare_next().await?;
+        }
+        Ok(())
+    }
+
+    /// Starts a crossfade once the current source is within the crossfade length of its end.
+    fn start_crossfade(&mut self, bytes_per_frame: usize) {
+        if self.fade.is_some() || self.crossfade_frames == 0 || self.next.is_none() {
+            return;
+        }
+        let Some(current) = self.current.as_ref() else {
+            return;
+        };
+        if let Some(remaining) = current.remaining_frames(bytes_per_frame) {
+            if remaining > 0 && remaining <= self.crossfade_frames {
+                self.fade = Some((0, remaining));
+            }
+        }
+    }
+}
+
+impl<S: BaseElement<Info = Info, Error = Error>> BaseElement for Sequencer<S> {
+    type Error = Error;
+    type Info = Info;
+
+    fn get_in_info(&self) -> Option<Info> {
+        None
+    }
+
+    /// The format shared by all sources. The total length is unknown.
+    fn get_out_info(&self) -> Option<Info> {
+        self.info.map(|info| Info { num_frames: None, ..info })
+    }
+
+    fn available(&self) -> u32 {
+        u32::MAX
+    }
+
+    /// Initializes the first queued source, which sets the format, and prepares the next one.
+    async fn initialize(
+        &mut self,
+        _upstream_info: Option<Info>,
+    ) -> Result<PortRequirements, Self::Error> {
+        let source = self.queue.pop_front().ok_or(Error::InvalidParameter)?;
+        let track = Track::open(source).await?;
+        let info = track.source.get_out_info().ok_or(Error::NotInitialized)?;
+        let bytes_per_frame = info.get_alignment_bytes() as u16;
+        let payload_bytes = bytes_per_frame.checked_mul(self.frames_per_process).ok_or(Error::InvalidParameter)?;
+
+        if let Transition::Crossfade { duration_ms, .. } = self.transition {
+            // Crossfading decodes the samples, plain joins only copy them.
+            if !Gain::is_supported(&info) {
+                return Err(Error::Unsupported);
+            }
+            self.codec = Some(SampleCodec::from_info(&info).ok_or(Error::Unsupported)?);
+            self.crossfade_frames = duration_ms as u64 * info.sample_rate as u64 / 1000;
+        }
+        self.info = Some(info);
+        self.current = Some(track);
+        self.next = None;
+        self.fade = None;
+        self.started = false;
+        self.prepare_next().await?;
+
+        Ok(PortRequirements::source(PayloadSize {
+            min: bytes_per_frame,
+            preferred: payload_bytes,
+        }))
+    }
+
+    async fn reset(&mut self) -> Result<(), Self::Error> {
+        self.queue.clear();
+        self.current = None;
+        self.next = None;
+        self.info = None;
+        self.codec = None;
+        self.fade = None;
+        self.started = false;
+        Ok(())
+    }
+
+    async fn process<'a, C, P, T>(
+        &mut self,
+        _in_port: &mut InPort<'a, C>,
+        out_port: &mut OutPort<'a, P>,
+        _inplace_port: &mut InPlacePort<'a, T>,
+    ) -> ProcessResult<Self::Error>
+    where
+        C: Consumer<'a>,
+        P: Producer<'a>,
+        T: Transformer<'a>,
+    {
+        let OutPort::Producer(producer) = out_port else {
+            return Err(Error::Unsupported);
+        };
+        let info = self.info.ok_or(Error::NotInitialized)?;
+        let bytes_per_frame = info.get_alignment_bytes() as usize;
+        let bytes = bytes_per_frame / info.channels as usize;
+
+        let mut payload = producer.acquire_write().await;
+        let capacity = payload.len() / bytes_per_frame;
+        let mut frames = 0;
+
+        while frames < capacity {
+            self.skip_finished().await?;
+            self.start_crossfade(bytes_per_frame);
+            let Some(current) = self.current.as_mut() else {
+                break;
+            };
+            let out = &mut payload[frames * bytes_per_frame..(frames + 1) * bytes_per_frame];
+
+            match (self.fade.as_mut(), self.next.as_mut()) {
+                (Some((position, length)), Some(next)) => {
+                    let Transition::Crossfade { curve, .. } = self.transition else {
+                        unreachable!()
+                    };
+                    let codec = self.codec.ok_or(Error::NotInitialized)?;
+                    let progress = *position as f32 / *length as f32;
+                    let (gain_out, gain_in) = (curve.gain(1.0 - progress), curve.gain(progress));
+                    *position += 1;
+
+                    next.fill().await?;
+                    let outgoing = current.take_frame(bytes_per_frame).ok_or(Error::InvalidState)?;
+                    let incoming = next.take_frame(bytes_per_frame);
+                    for (c, sample) in out.chunks_exact_mut(bytes).enumerate() {
+                        let range = c * bytes..(c + 1) * bytes;
+                        let value = codec.read_f32(&outgoing[range.clone()]) * gain_out
+                            + incoming.map_or(0.0, |frame| codec.read_f32(&frame[range]) * gain_in);
+                        codec.write_f32(sample, value);
+                    }
+                }
+                _ => out.copy_from_slice(current.take_frame(bytes_per_frame).ok_or(Error::InvalidState)?),
+            }
+            frames += 1;
+        }
+
+        // Look ahead so the payload that drains the last source is the one marked `Last`.
+        self.skip_finished().await?;
+        let finished = self.current.is_none();
+
+        payload.set_valid_length(frames * bytes_per_frame);
+        payload.set_position(match (self.started, finished) {
+            (false, false) => Position::First,
+            (true, false) => Position::Middle,
+            (false, true) => Position::Single,
+            (true, true) => Position::Last,
+        });
+        self.started = !finished;
+
+        if finished {
+            Ok(Eof)
+        } else {
+            Ok(Fine)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embedded_audio_driver::element::ProcessStatus;
+
+    use crate::test_util::{new_slot, read_samples};
+
+    /// A source playing a fixed list of 16-bit mono samples.
+    struct Samples {
+        info: Info,
+        samples: Vec<i16>,
+        position: usize,
+    }
+
+    impl Samples {
+        fn new(sample_rate: u32, samples: Vec<i16>) -> Self {
+            Self {
+                info: Info::new(sample_rate, 1, 16, Some(samples.len() as u64)),
+                samples,
+                position: 0,
+            }
+        }
+    }
+
+    impl BaseElement for Samples {
+        type Error = Error;
+        type Info = Info;
+
+        fn get_in_info(&self) -> Option<Info> {
+            None
+        }
+
+        fn get_out_info(&self) -> Option<Info> {
+            Some(self.info)
+        }
+
+        fn available(&self) -> u32 {
+            (self.samples.len() - self.position) as u32
+        }
+
+        async fn initialize(&mut self, _upstream_info: Option<Info>) -> Result<PortRequirements, Error> {
+            Ok(PortRequirements::source(PayloadSize { min: 2, preferred: 6 }))
+        }
+
+        async fn process<'a, C, P, T>(
+            &mut self,
+            _in_port: &mut InPort<'a, C>,
+            out_port: &mut OutPort<'a, P>,
+            _inplace_port: &mut InPlacePort<'a, T>,
+        ) -> ProcessResult<Error>
+        where
+            C: Consumer<'a>,
+            P: Producer<'a>,
+            T: Transformer<'a>,
+        {
+            let OutPort::Producer(producer) = out_port else {
+                return Err(Error::Unsupported);
+            };
+            let mut payload = producer.acquire_write().await;
+            let first = self.position == 0;
+            let frames = (payload.len() / 2).min(self.samples.len() - self.position);
+            for (i, s) in self.samples[self.position..self.position + frames].iter().enumerate() {
+                payload[i * 2..i * 2 + 2].copy_from_slice(&s.to_le_bytes());
+            }
+            self.position += frames;
+            let last = self.position == self.samples.len();
+            payload.set_valid_length(frames * 2);
+            payload.set_position(match (first, last) {
+                (true, true) => Position::Single,
+                (true, false) => Position::First,
+                (false, true) => Position::Last,
+                (false, false) => Position::Middle,
+            });
+            Ok(if last { Eof } else { Fine })
+        }
+    }
+
+    async fn read(sequencer: &mut Sequencer<Samples>, slot: &HeapSlot) -> (Vec<i16>, Position, ProcessStatus) {
+        let status = sequencer
+            .process(&mut InPort::new_none(), &mut slot.out_port(), &mut InPlacePort::new_none())
+            .await
+            .unwrap();
+        let (samples, position) = read_samples(slot).await;
+        (samples, position, status)
+    }
+
+    async fn run(sequencer: &mut Sequencer<Samples>) -> (Vec<i16>, Vec<Position>) {
+        let requirements = sequencer.initialize(None).await.unwrap();
+        let slot = new_slot(requirements.out.unwrap());
+
+        let (mut samples, mut positions) = (Vec::new(), Vec::new());
+        loop {
+            let (payload, position, status) = read(sequencer, &slot).await;
+            samples.extend(payload);
+            positions.push(position);
+            if status == Eof {
+                return (samples, positions);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_gapless_join() {
+        let mut sequencer = Sequencer::new(Transition::Gapless, 4);
+        sequencer.push(Samples::new(1000, vec![1, 2, 3, 4, 5]));
+        sequencer.push(Samples::new(1000, vec![6, 7, 8]));
+        sequencer.push(Samples::new(1000, vec![9, 10, 11]));
+
+        let (samples, positions) = run(&mut sequencer).await;
+        assert_eq!(samples, (1..=11).collect::<Vec<i16>>());
+        assert_eq!(positions, [Position::First, Position::Middle, Position::Last]);
+    }
+
+    #[tokio::test]
+    async fn test_crossfade() {
+        let curve = FadeCurve::Linear;
+        let mut sequencer = Sequencer::new(Transition::Crossfade { duration_ms: 4, curve }, 4);
+        sequencer.push(Samples::new(1000, vec![1000; 8]));
+        sequencer.push(Samples::new(1000, vec![2000; 8]));
+
+        let (samples, positions) = run(&mut sequencer).await;
+        assert_eq!(samples, [1000, 1000, 1000, 1000, 1000, 1250, 1500, 1750, 2000, 2000, 2000, 2000]);
+        assert_eq!(positions, [Position::First, Position::Middle, Position::Last]);
+    }
+
+    #[tokio::test]
+    async fn test_skips_other_formats() {
+        let mut sequencer = Sequencer::new(Transition::Gapless, 8);
+        sequencer.push(Samples::new(1000, vec![1, 2]));
+        sequencer.push(Samples::new(2000, vec![3, 4]));
+        sequencer.push(Samples::new(1000, vec![5, 6]));
+
+        let (samples, positions) = run(&mut sequencer).await;
+        assert_eq!(samples, [1, 2, 5, 6]);
+        assert_eq!(positions, [Position::Single]);
+        assert!(matches!(sequencer.initialize(None).await, Err(Error::InvalidParameter)));
+    }
+
+    #[tokio::test]
+    async fn test_push_while_playing() {
+        let mut sequencer = Sequencer::new(Transition::Gapless, 4);
+        sequencer.push(Samples::new(1000, (1..=8).collect()));
+        let requirements = sequencer.initialize(None).await.unwrap();
+        let slot = new_slot(requirements.out.unwrap());
+        assert_eq!(read(&mut sequencer, &slot).await, ((1..=4).collect(), Position::First, Fine));
+
+        // Nothing is waiting behind the current source when the next one is pushed.
+        sequencer.push(Samples::new(1000, vec![9, 10]));
+        assert_eq!(read(&mut sequencer, &slot).await, ((5..=8).collect(), Position::Middle, Fine));
+        assert_eq!(read(&mut sequencer, &slot).await, (vec![9, 10], Position::Last, Eof));
+        assert_eq!(sequencer.queued(), 0);
+    }
+}
@@ -2,8 +2,8 @@ use core::f32::consts::PI;
 use libm::sinf;
 
 use embedded_audio_driver::databus::{Consumer, Producer, Transformer};
-use embedded_audio_driver::element::{BaseElement, ProcessResult, Eof, Fine};
-use embedded_audio_driver::info::Info;
+use embedded_audio_driver::element::{BaseElement, ProcessResult, Eof, Fine, Seekable};
+use embedded_audio_driver::info::{Endianness, Info, SampleEncoding};
 use embedded_audio_driver::payload::Position;
 use embedded_audio_driver::port::{InPlacePort, InPort, OutPort, PayloadSize, PortRequirements};
 use embedded_audio_driver::Error;
@@ -27,7 +27,7 @@ impl SineWaveGenerator {
     /// * `frequency` - The frequency of the sine wave in Hz.
     /// * `amplitude` - The amplitude of the wave, from 0.0 to 1.0.
     pub fn new(info: Info, frequency: f32, amplitude: f32, frames_per_process: u16) -> Self {
-        if !info.vaild() {
+        if !Self::is_supported(&info) {
             panic!("Invalid Info for SineWaveGenerator");
         }
 
@@ -46,7 +46,7 @@ impl SineWaveGenerator {
     }
 
     pub fn set_info(&mut self, info: Info) {
-        if !info.vaild() {
+        if !Self::is_supported(&info) {
             panic!("Invalid Info for SineWaveGenerator");
         }
         self.info = info;
@@ -69,6 +69,37 @@ impl SineWaveGenerator {
         let t = sample_idx as f32 / self.info.sample_rate as f32;
         self.amplitude * sinf(2.0 * PI * self.frequency * t)
     }
+
+    /// Encodes a sample in the range [-1.0, 1.0] into `dest` using the sample format of `info`.
+    fn encode_sample(&self, value: f32, dest: &mut [u8]) {
+        let bytes_per_sample = dest.len();
+        let mut raw = [0u8; 8];
+
+        match self.info.encoding {
+            SampleEncoding::Float if bytes_per_sample == 4 => raw[..4].copy_from_slice(&value.to_le_bytes()),
+            SampleEncoding::Float => raw.copy_from_slice(&(value as f64).to_le_bytes()),
+            SampleEncoding::Signed | SampleEncoding::Unsigned => {
+                // Scale to the valid bits, then MSB-align in the container.
+                let valid_bits = self.info.valid_bits_per_sample as u32;
+                let max = ((1i64 << (valid_bits - 1)) - 1) as f64;
+                let mut int_sample = ((value as f64 * max) as i64) << (self.info.bits_per_sample as u32 - valid_bits);
+                if self.info.encoding == SampleEncoding::Unsigned {
+                    int_sample += 1i64 << (self.info.bits_per_sample - 1);
+                }
+                raw = int_sample.to_le_bytes();
+            }
+        }
+
+        dest.copy_from_slice(&raw[..bytes_per_sample]);
+        if self.info.endianness == Endianness::Big {
+            dest.reverse();
+        }
+    }
+
+    /// Returns `true` if the generator can produce samples in the format of `info`.
+    fn is_supported(info: &Info) -> bool {
+        info.vaild() && (info.is_float() || info.bits_per_sample <= 32)
+    }
 }
 
 impl BaseElement for SineWaveGenerator {
@@ -103,7 +134,10 @@ impl BaseElement for SineWaveGenerator {
         &mut self,
         _upstream_info: Option<Self::Info>,
     ) -> Result<PortRequirements, Self::Error> {
-        
+        if !Self::is_supported(&self.info) {
+            return Err(Error::Unsupported);
+        }
+
         let min_payload_size = self.info.get_alignment_bytes();
         Ok(PortRequirements::source(PayloadSize { 
             min: min_payload_size as _, 
@@ -140,18 +174,10 @@ impl BaseElement for SineWaveGenerator {
             for _ in 0..max_frames {
                 let sample_value = self.generate_sample(self.current_sample);
 
-                // Convert the float sample to the target integer format.
-                let int_sample = match self.info.bits_per_sample {
-                    8 => ((sample_value * 127.0 + 128.0) as i8) as i32,
-                    16 => (sample_value * 32767.0) as i16 as i32,
-                    _ => (sample_value * 8388607.0) as i32, // For 24 and 32 bits
-                };
-
                 // Write the same sample to all channels for this frame.
                 for _ in 0..self.info.channels {
-                    let sample_bytes = int_sample.to_le_bytes();
                     let dest_slice = &mut payload[bytes_written..bytes_written + bytes_per_sample];
-                    dest_slice.copy_from_slice(&sample_bytes[..bytes_per_sample]);
+                    self.encode_sample(sample_value, dest_slice);
                     bytes_written += bytes_per_sample;
                 }
 
@@ -192,6 +218,21 @@ impl BaseElement for SineWaveGenerator {
     }
 }
 
+impl Seekable for SineWaveGenerator {
+    fn current_frame(&self) -> u64 {
+        self.current_sample
+    }
+
+    fn seek(&mut self, frame: u64) -> Result<u64, Error> {
+        self.current_sample = match self.info.num_frames {
+            Some(total) => frame.min(total),
+            None => frame,
+        };
+        self.is_first_chunk = true;
+        Ok(self.current_sample)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -301,4 +342,32 @@ mod tests {
         // Available frames check
         assert_eq!(generator.available(), u32::MAX, "Available frames should be max for an infinite stream");
     }
+
+    #[test]
+    fn test_sample_encoding() {
+        // Test case: Verify that samples are encoded according to the Info sample format.
+        let mut buf = [0u8; 4];
+
+        let generator = SineWaveGenerator::new(Info::new(8000, 1, 8, None), 440.0, 1.0, 64);
+        generator.encode_sample(0.0, &mut buf[..1]);
+        assert_eq!(buf[0], 128, "8-bit WAV samples are unsigned with silence at 128");
+        generator.encode_sample(1.0, &mut buf[..1]);
+        assert_eq!(buf[0], 255);
+
+        let mut info = Info::new(8000, 1, 16, None);
+        info.set_endianness(Endianness::Big);
+        let generator = SineWaveGenerator::new(info, 440.0, 1.0, 64);
+        generator.encode_sample(1.0, &mut buf[..2]);
+        assert_eq!(&buf[..2], &i16::MAX.to_be_bytes());
+
+        let mut info = Info::new(8000, 1, 32, None);
+        info.set_valid_bits_per_sample(24);
+        let generator = SineWaveGenerator::new(info, 440.0, 1.0, 64);
+        generator.encode_sample(-1.0, &mut buf);
+        assert_eq!(i32::from_le_bytes(buf), -8388607 << 8, "24 valid bits are MSB-aligned in 32");
+
+        let generator = SineWaveGenerator::new(Info::new_float(8000, 1, 32, None), 440.0, 1.0, 64);
+        generator.encode_sample(0.5, &mut buf);
+        assert_eq!(f32::from_le_bytes(buf), 0.5);
+    }
 }
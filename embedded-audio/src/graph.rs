@@ -0,0 +1,768 @@
+//! Running a graph of elements with fan-out and fan-in.
+//!
+//! A [`Graph`] takes a tuple of up to 8 nodes and a list of edges between them. Any element is
+//! a node with at most one input and one output; a [`Tee`] copies one input to several
+//! outputs, and a [`Join`] feeds several inputs into a [`Mixer`] or [`Ducker`]. The inputs and
+//! outputs of a node are ordered as its edges are listed.
+//!
+//! `initialize` rejects cycles, initializes the nodes in topological order with the `Info` of
+//! their upstream nodes, and creates one databus per edge. An in-place node transforms the
+//! payloads of its input edge, which then continues as its output edge; each databus takes
+//! at most one in-place node.
+//!
+//! Every call to `step` processes each node once, in topological order. A node is done once
+//! it returns `Eof`, once all its upstream nodes are done (a [`Join`] keeps running until it
+//! has drained its inputs), or once all its downstream nodes are done. The graph ends when
+//! every node is done.
+//!
+//! Like a pipeline, a graph with an [`EventBus`] attached posts output formats, `Eof`, errors,
+//! state changes and progress of its nodes, tagged with the index of the node. A node is
+//! reported `Stopped` once it is done.
+
+use std::vec::Vec;
+
+use embedded_audio_driver::databus::{Consumer, Databus, Operation, Producer, Transformer};
+use embedded_audio_driver::element::{BaseElement, Eof, Fine, ProcessResult};
+use embedded_audio_driver::info::Info;
+use embedded_audio_driver::payload::Position;
+use embedded_audio_driver::port::{InPlacePort, InPort, OutPort, PayloadSize, PortRequirements};
+use embedded_audio_driver::stream::StreamState;
+use embedded_audio_driver::Error;
+
+use crate::databus::slot::HeapSlot;
+use crate::event::{EventBus, EventKind, Metered, Notifier};
+use crate::transformer::{Ducker, Mixer};
+
+/// The maximum number of nodes in a graph.
+pub const MAX_GRAPH_NODES: usize = 8;
+
+/// The maximum number of edges in a graph.
+pub const MAX_GRAPH_EDGES: usize = 16;
+
+/// The maximum number of inputs or outputs of a node.
+pub const MAX_NODE_PORTS: usize = 8;
+
+/// An element with any number of inputs and outputs.
+///
+/// Implemented for every element with at most one of each, and for [`Tee`] and [`Join`].
+#[allow(async_fn_in_trait)]
+pub trait Node {
+    /// Initializes the node with the `Info` of each input and the number of outputs.
+    async fn initialize_node(&mut self, inputs: &[Info], outputs: usize) -> Result<PortRequirements, Error>;
+
+    fn node_out_info(&self) -> Option<Info>;
+
+    /// Returns `true` if the node stops reading an input after its `Last` payload, so it can
+    /// keep running after its upstream nodes are done.
+    fn tracks_input_eof(&self) -> bool {
+        false
+    }
+
+    async fn process_node<'a, D>(
+        &mut self,
+        inputs: &mut [InPort<'a, D>],
+        outputs: &mut [OutPort<'a, D>],
+        in_place: &mut InPlacePort<'a, D>,
+    ) -> ProcessResult<Error>
+    where
+        D: Consumer<'a> + Producer<'a> + Transformer<'a>;
+}
+
+impl<E: BaseElement<Info = Info, Error = Error>> Node for E {
+    async fn initialize_node(&mut self, inputs: &[Info], outputs: usize) -> Result<PortRequirements, Error> {
+        if inputs.len() > 1 || outputs > 1 {
+            return Err(Error::InvalidParameter);
+        }
+        self.initialize(inputs.first().copied()).await
+    }
+
+    fn node_out_info(&self) -> Option<Info> {
+        self.get_out_info()
+    }
+
+    async fn process_node<'a, D>(
+        &mut self,
+        inputs: &mut [InPort<'a, D>],
+        outputs: &mut [OutPort<'a, D>],
+        in_place: &mut InPlacePort<'a, D>,
+    ) -> ProcessResult<Error>
+    where
+        D: Consumer<'a> + Producer<'a> + Transformer<'a>,
+    {
+        let (mut no_input, mut no_output) = (InPort::None, OutPort::None);
+        let in_port = inputs.first_mut().unwrap_or(&mut no_input);
+        let out_port = outputs.first_mut().unwrap_or(&mut no_output);
+        self.process(in_port, out_port, in_place).await
+    }
+}
+
+/// A node copying its input to up to `N` outputs.
+///
+/// The databuses have a single consumer each, so every output receives a copy of the payload.
+/// Outputs whose downstream node is done are skipped. With a single output, the tee passes
+/// the payload on in place without copying, which takes the in-place slot of its databus.
+pub struct Tee<const N: usize> {
+    info: Option<Info>,
+    frames_per_process: u16,
+}
+
+impl<const N: usize> Tee<N> {
+    pub fn new(frames_per_process: u16) -> Self {
+        Self {
+            info: None,
+            frames_per_process,
+        }
+    }
+}
+
+impl<const N: usize> Node for Tee<N> {
+    async fn initialize_node(&mut self, inputs: &[Info], outputs: usize) -> Result<PortRequirements, Error> {
+        let [info] = inputs else {
+            return Err(Error::InvalidParameter);
+        };
+        if !(1..=N).contains(&outputs) {
+            return Err(Error::InvalidParameter);
+        }
+        self.info = Some(*info);
+
+        let bytes_per_frame = info.get_alignment_bytes() as u16;
+        let size = PayloadSize {
+            min: bytes_per_frame,
+            preferred: bytes_per_frame.checked_mul(self.frames_per_process).ok_or(Error::InvalidParameter)?,
+        };
+        if outputs == 1 {
+            return Ok(PortRequirements::new_in_place(size));
+        }
+        let mut requirements = PortRequirements::sink(size);
+        requirements.out = Some(size);
+        Ok(requirements)
+    }
+
+    fn node_out_info(&self) -> Option<Info> {
+        self.info
+    }
+
+    async fn process_node<'a, D>(
+        &mut self,
+        inputs: &mut [InPort<'a, D>],
+        outputs: &mut [OutPort<'a, D>],
+        in_place: &mut InPlacePort<'a, D>,
+    ) -> ProcessResult<Error>
+    where
+        D: Consumer<'a> + Producer<'a> + Transformer<'a>,
+    {
+        let position = if let InPlacePort::Transformer(transformer) = in_place {
+            transformer.acquire_transform().await.metadata.position
+        } else {
+            let Some(InPort::Consumer(consumer)) = inputs.first() else {
+                return Err(Error::Unsupported);
+            };
+            let payload = consumer.acquire_read().await;
+            for output in outputs.iter() {
+                let OutPort::Producer(producer) = output else {
+                    continue;
+                };
+                let mut copy = producer.acquire_write().await;
+                if payload.len() > copy.len() {
+                    return Err(Error::BufferFull);
+                }
+                copy[..payload.len()].copy_from_slice(&payload);
+                copy.set_valid_length(payload.len());
+                copy.set_position(payload.metadata.position);
+            }
+            payload.metadata.position
+        };
+
+        match position {
+            Position::Last | Position::Single => Ok(Eof),
+            _ => Ok(Fine),
+        }
+    }
+}
+
+/// An element mixing several inputs into one output.
+#[allow(async_fn_in_trait)]
+pub trait MultiInput: BaseElement<Info = Info, Error = Error> {
+    /// The maximum number of inputs.
+    const INPUTS: usize;
+
+    async fn process_inputs<'a, C, P>(
+        &mut self,
+        inputs: &mut [InPort<'a, C>],
+        out_port: &mut OutPort<'a, P>,
+    ) -> ProcessResult<Error>
+    where
+        C: Consumer<'a>,
+        P: Producer<'a>;
+}
+
+impl<const N: usize, const FIFO: usize> MultiInput for Mixer<'_, N, FIFO> {
+    const INPUTS: usize = N;
+
+    async fn process_inputs<'a, C, P>(
+        &mut self,
+        inputs: &mut [InPort<'a, C>],
+        out_port: &mut OutPort<'a, P>,
+    ) -> ProcessResult<Error>
+    where
+        C: Consumer<'a>,
+        P: Producer<'a>,
+    {
+        Mixer::process_inputs(self, inputs, out_port).await
+    }
+}
+
+impl<const FIFO: usize> MultiInput for Ducker<'_, FIFO> {
+    const INPUTS: usize = 2;
+
+    async fn process_inputs<'a, C, P>(
+        &mut self,
+        inputs: &mut [InPort<'a, C>],
+        out_port: &mut OutPort<'a, P>,
+    ) -> ProcessResult<Error>
+    where
+        C: Consumer<'a>,
+        P: Producer<'a>,
+    {
+        Ducker::process_inputs(self, inputs, out_port).await
+    }
+}
+
+/// A node feeding its inputs into a [`MultiInput`] element.
+///
+/// All inputs must have the same format; otherwise `initialize` fails with `Error::Unsupported`.
+pub struct Join<M>(pub M);
+
+impl<M: MultiInput> Node for Join<M> {
+    async fn initialize_node(&mut self, inputs: &[Info], outputs: usize) -> Result<PortRequirements, Error> {
+        let Some(first) = inputs.first() else {
+            return Err(Error::InvalidParameter);
+        };
+        if inputs.len() > M::INPUTS || outputs > 1 {
+            return Err(Error::InvalidParameter);
+        }
+        if !inputs.iter().all(|info| info.is_same_format(first)) {
+            return Err(Error::Unsupported);
+        }
+        self.0.initialize(Some(*first)).await
+    }
+
+    fn node_out_info(&self) -> Option<Info> {
+        self.0.get_out_info()
+    }
+
+    fn tracks_input_eof(&self) -> bool {
+        true
+    }
+
+    async fn process_node<'a, D>(
+        &mut self,
+        inputs: &mut [InPort<'a, D>],
+        outputs: &mut [OutPort<'a, D>],
+        _in_place: &mut InPlacePort<'a, D>,
+    ) -> ProcessResult<Error>
+    where
+        D: Consumer<'a> + Producer<'a> + Transformer<'a>,
+    {
+        let out_port = outputs.first_mut().ok_or(Error::InvalidParameter)?;
+        self.0.process_inputs(inputs, out_port).await
+    }
+}
+
+/// The nodes of a graph, addressed by index.
+///
+/// Implemented for tuples of 2 to [`MAX_GRAPH_NODES`] nodes.
+#[allow(async_fn_in_trait)]
+pub trait Nodes {
+    /// The number of nodes.
+    const LEN: usize;
+
+    async fn initialize(&mut self, node: usize, inputs: &[Info], outputs: usize) -> Result<PortRequirements, Error>;
+
+    fn out_info(&self, node: usize) -> Option<Info>;
+
+    fn tracks_input_eof(&self, node: usize) -> bool;
+
+    async fn process<'a, D>(
+        &mut self,
+        node: usize,
+        inputs: &mut [InPort<'a, D>],
+        outputs: &mut [OutPort<'a, D>],
+        in_place: &mut InPlacePort<'a, D>,
+    ) -> ProcessResult<Error>
+    where
+        D: Consumer<'a> + Producer<'a> + Transformer<'a>;
+}
+
+macro_rules! impl_nodes {
+    ($len:literal; $($index:tt $node:ident),+) => {
+        impl<$($node: Node),+> Nodes for ($($node,)+) {
+            const LEN: usize = $len;
+
+            async fn initialize(&mut self, node: usize, inputs: &[Info], outputs: usize) -> Result<PortRequirements, Error> {
+                match node {
+                    $($index => self.$index.initialize_node(inputs, outputs).await,)+
+                    _ => Err(Error::InvalidParameter),
+                }
+            }
+
+            fn out_info(&self, node: usize) -> Option<Info> {
+                match node {
+                    $($index => self.$index.node_out_info(),)+
+                    _ => None,
+                }
+            }
+
+            fn tracks_input_eof(&self, node: usize) -> bool {
+                match node {
+                    $($index => self.$index.tracks_input_eof(),)+
+                    _ => false,
+                }
+            }
+
+            async fn process<'a, D>(
+                &mut self,
+                node: usize,
+                inputs: &mut [InPort<'a, D>],
+                outputs: &mut [OutPort<'a, D>],
+                in_place: &mut InPlacePort<'a, D>,
+            ) -> ProcessResult<Error>
+            where
+                D: Consumer<'a> + Producer<'a> + Transformer<'a>,
+            {
+                match node {
+                    $($index => self.$index.process_node(inputs, outputs, in_place).await,)+
+                    _ => Err(Error::InvalidParameter),
+                }
+            }
+        }
+    };
+}
+
+impl_nodes!(2; 0 N0, 1 N1);
+impl_nodes!(3; 0 N0, 1 N1, 2 N2);
+impl_nodes!(4; 0 N0, 1 N1, 2 N2, 3 N3);
+impl_nodes!(5; 0 N0, 1 N1, 2 N2, 3 N3, 4 N4);
+impl_nodes!(6; 0 N0, 1 N1, 2 N2, 3 N3, 4 N4, 5 N5);
+impl_nodes!(7; 0 N0, 1 N1, 2 N2, 3 N3, 4 N4, 5 N5, 6 N6);
+impl_nodes!(8; 0 N0, 1 N1, 2 N2, 3 N3, 4 N4, 5 N5, 6 N6, 7 N7);
+
+/// The databuses a node is connected to.
+#[derive(Debug, Clone, Copy, Default)]
+struct NodeLink {
+    inputs: [usize; MAX_NODE_PORTS],
+    input_count: usize,
+    outputs: [usize; MAX_NODE_PORTS],
+    output_count: usize,
+    in_place: Option<usize>,
+    requirements: Option<PortRequirements>,
+}
+
+/// A graph of nodes connected by heap-allocated databuses.
+pub struct Graph<'e, N> {
+    nodes: N,
+    edges: [(usize, usize); MAX_GRAPH_EDGES],
+    edge_count: usize,
+    order: [usize; MAX_GRAPH_NODES],
+    links: [NodeLink; MAX_GRAPH_NODES],
+    databuses: Vec<HeapSlot>,
+    done: [bool; MAX_GRAPH_NODES],
+    notifier: Notifier<'e, MAX_GRAPH_NODES>,
+}
+
+impl<'e, N: Nodes> Graph<'e, N> {
+    /// Creates a new graph.
+    ///
+    /// # Arguments
+    ///
+    /// * `nodes` - The nodes, addressed by their index in the tuple.
+    /// * `edges` - `(from, to)` pairs of node indices.
+    pub fn new(nodes: N, edges: &[(usize, usize)]) -> Self {
+        let mut graph = Self {
+            nodes,
+            edges: [(0, 0); MAX_GRAPH_EDGES],
+            // An invalid count is reported by `initialize`.
+            edge_count: edges.len(),
+            order: [0; MAX_GRAPH_NODES],
+            links: [NodeLink::default(); MAX_GRAPH_NODES],
+            databuses: Vec::new(),
+            done: [false; MAX_GRAPH_NODES],
+            notifier: Notifier::new(),
+        };
+        let count = edges.len().min(MAX_GRAPH_EDGES);
+        graph.edges[..count].copy_from_slice(&edges[..count]);
+        graph
+    }
+
+    /// Posts events about the nodes to `events`.
+    pub fn with_events(mut self, events: &'e EventBus) -> Self {
+        self.notifier.events = Some(events);
+        self
+    }
+
+    pub fn nodes(&self) -> &N {
+        &self.nodes
+    }
+
+    pub fn nodes_mut(&mut self) -> &mut N {
+        &mut self.nodes
+    }
+
+    fn edges(&self) -> &[(usize, usize)] {
+        &self.edges[..self.edge_count]
+    }
+
+    /// Sorts the nodes so that every edge points forward.
+    ///
+    /// Returns `Error::InvalidParameter` if the edges form a cycle.
+    fn sort(&mut self) -> Result<(), Error> {
+        let mut in_degree = [0usize; MAX_GRAPH_NODES];
+        for &(_, to) in self.edges() {
+            in_degree[to] += 1;
+        }
+        let mut sorted = 0;
+        let mut visited = [false; MAX_GRAPH_NODES];
+        while sorted < N::LEN {
+            let Some(node) = (0..N::LEN).find(|&node| !visited[node] && in_degree[node] == 0) else {
+                warn!("Graph: the edges contain a cycle");
+                return Err(Error::InvalidParameter);
+            };
+            visited[node] = true;
+            self.order[sorted] = node;
+            sorted += 1;
+            for &(from, to) in self.edges[..self.edge_count].iter() {
+                if from == node {
+                    in_degree[to] -= 1;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks the topology, initializes the nodes and creates the databuses.
+    ///
+    /// Returns `Error::InvalidParameter` for cycles, unknown nodes, too many edges or ports, and
+    /// nodes whose ports do not match their edges. Errors from the nodes, such as an
+    /// unsupported upstream `Info`, are passed on.
+    pub async fn initialize(&mut self) -> Result<(), Error> {
+        if self.edge_count > MAX_GRAPH_EDGES || self.edges().iter().any(|&(from, to)| from >= N::LEN || to >= N::LEN) {
+            return Err(Error::InvalidParameter);
+        }
+        self.sort()?;
+
+        let edges = self.edges;
+        let edges = &edges[..self.edge_count];
+        let mut edge_databus = [None; MAX_GRAPH_EDGES];
+        let mut sizes: Vec<usize> = Vec::new();
+        let mut transformed: Vec<bool> = Vec::new();
+        let mut out_infos = [None; MAX_GRAPH_NODES];
+        for &node in self.order[..N::LEN].iter() {
+            let mut link = NodeLink::default();
+            let mut infos = [Info::default(); MAX_NODE_PORTS];
+            let incoming = || edges.iter().enumerate().filter(move |(_, &(_, to))| to == node);
+            let outgoing = || edges.iter().enumerate().filter(move |(_, &(from, _))| from == node);
+
+            for (edge, &(from, _)) in incoming() {
+                if link.input_count == MAX_NODE_PORTS {
+                    return Err(Error::InvalidParameter);
+                }
+                infos[link.input_count] = out_infos[from].ok_or(Error::InvalidParameter)?;
+                link.inputs[link.input_count] = edge_databus[edge].ok_or(Error::InvalidParameter)?;
+                link.input_count += 1;
+            }
+            let output_count = outgoing().count();
+            if output_count > MAX_NODE_PORTS {
+                return Err(Error::InvalidParameter);
+            }
+
+            let requirements = self.nodes.initialize(node, &infos[..link.input_count], output_count).await?;
+            out_infos[node] = self.nodes.out_info(node);
+
+            if let Some(size) = requirements.in_place {
+                // The input databus continues as the output.
+                if link.input_count != 1 || output_count > 1 || transformed[link.inputs[0]] {
+                    return Err(Error::InvalidParameter);
+                }
+                let databus = link.inputs[0];
+                transformed[databus] = true;
+                sizes[databus] = sizes[databus].max(size.preferred as usize);
+                link.in_place = Some(databus);
+                link.input_count = 0;
+                for (edge, _) in outgoing() {
+                    edge_databus[edge] = Some(databus);
+                }
+            } else {
+                match requirements.in_ {
+                    Some(size) if link.input_count > 0 => {
+                        for &databus in link.inputs[..link.input_count].iter() {
+                            sizes[databus] = sizes[databus].max(size.preferred as usize);
+                        }
+                    }
+                    None if link.input_count == 0 => {}
+                    _ => return Err(Error::InvalidParameter),
+                }
+                match requirements.out {
+                    Some(size) if output_count > 0 => {
+                        for (edge, _) in outgoing() {
+                            edge_databus[edge] = Some(sizes.len());
+                            link.outputs[link.output_count] = sizes.len();
+                            link.output_count += 1;
+                            sizes.push(size.preferred as usize);
+                            transformed.push(false);
+                        }
+                    }
+                    None if output_count == 0 => {}
+                    _ => return Err(Error::InvalidParameter),
+                }
+            }
+            link.requirements = Some(requirements);
+            self.links[node] = link;
+        }
+
+        self.databuses = sizes.iter().map(|&size| HeapSlot::new_heap(size)).collect();
+        for link in self.links[..N::LEN].iter() {
+            let Some(requirements) = link.requirements else {
+                continue;
+            };
+            if let Some(size) = requirements.out {
+                for &databus in link.outputs[..link.output_count].iter() {
+                    self.databuses[databus].register(Operation::Produce, size);
+                }
+            }
+            if let Some(size) = requirements.in_ {
+                for &databus in link.inputs[..link.input_count].iter() {
+                    self.databuses[databus].register(Operation::Consume, size);
+                }
+            }
+            if let (Some(databus), Some(size)) = (link.in_place, requirements.in_place) {
+                self.databuses[databus].register(Operation::InPlace, size);
+            }
+        }
+        self.done = [false; MAX_GRAPH_NODES];
+        self.notifier.initialized(N::LEN);
+        self.notifier.formats(N::LEN, |node| self.nodes.out_info(node));
+        Ok(())
+    }
+
+    /// Processes every node that is not done once. Returns `Eof` once all nodes are done.
+    pub async fn step(&mut self) -> ProcessResult<Error> {
+        if self.links[0].requirements.is_none() {
+            return Err(Error::NotInitialized);
+        }
+        let done_before = self.done;
+        let databuses: Vec<Metered<'_, HeapSlot>> = self.databuses.iter().map(Metered::new).collect();
+        for &node in self.order[..N::LEN].iter() {
+            if self.done[node] {
+                continue;
+            }
+            let mut upstream = self.edges[..self.edge_count].iter().filter(|&&(_, to)| to == node).peekable();
+            let has_inputs = upstream.peek().is_some();
+            if has_inputs && upstream.all(|&(from, _)| done_before[from]) && !self.nodes.tracks_input_eof(node) {
+                // The final payload of the upstream nodes was processed in the previous step.
+                self.done[node] = true;
+                continue;
+            }
+
+            // Outputs are ordered as the outgoing edges; skip those whose node is done, as nothing
+            // reads from them anymore.
+            let mut targets = [0; MAX_NODE_PORTS];
+            let outgoing = self.edges[..self.edge_count].iter().filter(|&&(from, _)| from == node);
+            for (target, &(_, to)) in targets.iter_mut().zip(outgoing) {
+                *target = to;
+            }
+            let link = &self.links[node];
+            let done = &self.done;
+            let databuses = &databuses;
+            let mut inputs: [InPort<'_, Metered<'_, HeapSlot>>; MAX_NODE_PORTS] = core::array::from_fn(|port| {
+                match link.inputs.get(port).filter(|_| port < link.input_count) {
+                    Some(&databus) => InPort::Consumer(&databuses[databus]),
+                    None => InPort::None,
+                }
+            });
+            let mut outputs: [OutPort<'_, Metered<'_, HeapSlot>>; MAX_NODE_PORTS] = core::array::from_fn(|port| {
+                match link.outputs.get(port).filter(|_| port < link.output_count && !done[targets[port]]) {
+                    Some(&databus) => OutPort::Producer(&databuses[databus]),
+                    None => OutPort::None,
+                }
+            });
+            let mut in_place = match link.in_place {
+                Some(databus) => InPlacePort::Transformer(&databuses[databus]),
+                None => InPlacePort::None,
+            };
+
+            self.notifier.state(node, StreamState::Running);
+            let status = self
+                .nodes
+                .process(
+                    node,
+                    &mut inputs[..link.input_count],
+                    &mut outputs[..link.output_count],
+                    &mut in_place,
+                )
+                .await
+                .inspect_err(|&error| self.notifier.post(node, EventKind::Error(error)))?;
+            if status == Eof {
+                self.done[node] = true;
+                self.notifier.post(node, EventKind::Eof);
+            }
+        }
+        self.notifier.formats(N::LEN, |node| self.nodes.out_info(node));
+        let bytes: Vec<usize> = databuses.iter().map(Metered::bytes).collect();
+        self.notify_progress(&bytes);
+
+        // Nothing reads from a node whose downstream nodes are all done.
+        for &node in self.order[..N::LEN].iter().rev() {
+            let mut downstream = self.edges[..self.edge_count].iter().filter(|&&(from, _)| from == node).peekable();
+            if downstream.peek().is_some() && downstream.all(|&(_, to)| self.done[to]) {
+                self.done[node] = true;
+            }
+        }
+        for node in (0..N::LEN).filter(|&node| self.done[node]) {
+            self.notifier.state(node, StreamState::Stopped);
+        }
+
+        if self.done[..N::LEN].iter().all(|&done| done) {
+            Ok(Eof)
+        } else {
+            Ok(Fine)
+        }
+    }
+
+    /// Posts the frames each node processed in a step, given the bytes read from each databus:
+    /// the frames read from its first output or the databus it transforms, or for a sink, the
+    /// frames it read.
+    fn notify_progress(&mut self, bytes: &[usize]) {
+        for node in 0..N::LEN {
+            let link = &self.links[node];
+            let output = link.outputs[..link.output_count].first().copied().or(link.in_place);
+            let (databus, info) = match (output, link.inputs[..link.input_count].first()) {
+                (Some(databus), _) => (databus, self.nodes.out_info(node)),
+                (None, Some(&databus)) => {
+                    let upstream = self.edges().iter().find(|&&(_, to)| to == node).map(|&(from, _)| from);
+                    (databus, upstream.and_then(|from| self.nodes.out_info(from)))
+                }
+                (None, None) => continue,
+            };
+            if let Some(info) = info.filter(|info| info.get_alignment_bytes() > 0) {
+                self.notifier.progress(node, bytes[databus] / info.get_alignment_bytes() as usize);
+            }
+        }
+    }
+
+    /// Runs the graph until every node is done.
+    pub async fn run(&mut self) -> Result<(), Error> {
+        while self.step().await? == Fine {}
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::generator::SineWaveGenerator;
+    use crate::test_util::Collect;
+    use crate::transformer::Gain;
+
+    fn sine(sample_rate: u32, frequency: f32, num_frames: u64) -> SineWaveGenerator {
+        SineWaveGenerator::new(Info::new(sample_rate, 1, 16, Some(num_frames)), frequency, 0.5, 64)
+    }
+
+    #[tokio::test]
+    async fn test_tee_to_two_sinks() {
+        let nodes = (sine(8000, 100.0, 1000), Tee::<2>::new(64), Collect::default(), Collect::default());
+        let mut graph = Graph::new(nodes, &[(0, 1), (1, 2), (1, 3)]);
+        graph.initialize().await.unwrap();
+        graph.run().await.unwrap();
+
+        let (_, _, speaker, recorder) = graph.nodes();
+        assert!(speaker.samples.len() >= 1000);
+        assert_eq!(speaker.samples, recorder.samples);
+    }
+
+    #[tokio::test]
+    async fn test_tee_skips_finished_branch() {
+        let preview = Collect { limit: Some(128), ..Default::default() };
+        let nodes = (sine(8000, 100.0, 1000), Tee::<2>::new(64), preview, Collect::default());
+        let mut graph = Graph::new(nodes, &[(0, 1), (1, 2), (1, 3)]);
+        graph.initialize().await.unwrap();
+        tokio::time::timeout(std::time::Duration::from_secs(5), graph.run()).await.unwrap().unwrap();
+
+        let (_, _, preview, recorder) = graph.nodes();
+        assert_eq!(preview.samples.len(), 128);
+        assert!(recorder.samples.len() >= 1000);
+        assert_eq!(preview.samples[..], recorder.samples[..128]);
+    }
+
+    #[tokio::test]
+    async fn test_events_and_progress() {
+        let events = EventBus::new();
+        let nodes = (sine(8000, 100.0, 1000), Tee::<2>::new(64), Collect::default(), Collect::default());
+        let mut graph = Graph::new(nodes, &[(0, 1), (1, 2), (1, 3)]).with_events(&events);
+        graph.initialize().await.unwrap();
+        graph.run().await.unwrap();
+
+        let frames = graph.nodes().2.samples.len() as u64;
+        for node in 0..4 {
+            assert_eq!(events.try_progress(node), Some(frames));
+        }
+        let mut stopped = [false; 4];
+        while let Some(event) = events.try_receive() {
+            if event.kind == EventKind::State(StreamState::Stopped) {
+                stopped[event.element] = true;
+            }
+        }
+        assert_eq!(stopped, [true; 4]);
+    }
+
+    #[tokio::test]
+    async fn test_single_output_tee_is_in_place() {
+        let nodes = (sine(8000, 100.0, 1000), Tee::<2>::new(64), Collect::default());
+        let mut graph = Graph::new(nodes, &[(0, 1), (1, 2)]);
+        graph.initialize().await.unwrap();
+        assert_eq!(graph.databuses.len(), 1);
+        graph.run().await.unwrap();
+        assert!(graph.nodes().2.samples.len() >= 1000);
+
+        // The databus has no room for a second in-place node.
+        let nodes = (sine(8000, 100.0, 1000), Tee::<2>::new(64), Gain::new(0.5, 64), Collect::default());
+        let mut graph = Graph::new(nodes, &[(0, 1), (1, 2), (2, 3)]);
+        assert!(matches!(graph.initialize().await, Err(Error::InvalidParameter)));
+    }
+
+    #[tokio::test]
+    async fn test_join_into_mixer() {
+        let mixer = Mixer::<2>::new([1.0, 1.0], 64);
+        let nodes = (sine(8000, 100.0, 1000), sine(8000, 300.0, 300), Join(mixer), Collect::default());
+        let mut graph = Graph::new(nodes, &[(0, 2), (1, 2), (2, 3)]);
+        graph.initialize().await.unwrap();
+        graph.run().await.unwrap();
+
+        // Once the short input has ended, the long one plays alone.
+        let mut alone = Graph::new((sine(8000, 100.0, 1000), Collect::default()), &[(0, 1)]);
+        alone.initialize().await.unwrap();
+        alone.run().await.unwrap();
+        let mixed = &graph.nodes().3.samples;
+        let expected = &alone.nodes().1.samples;
+        assert_eq!(mixed.len(), expected.len());
+        assert_ne!(mixed[..300], expected[..300]);
+        assert_eq!(mixed[400..], expected[400..]);
+    }
+
+    #[tokio::test]
+    async fn test_rejects_cycles_and_mismatched_joins() {
+        let nodes = (sine(8000, 100.0, 1000), Gain::new(1.0, 64), Gain::new(1.0, 64), Collect::default());
+        let mut graph = Graph::new(nodes, &[(0, 1), (1, 2), (2, 1), (2, 3)]);
+        assert!(matches!(graph.initialize().await, Err(Error::InvalidParameter)));
+
+        let mixer = Mixer::<2>::new([1.0, 1.0], 64);
+        let nodes = (sine(8000, 100.0, 1000), sine(16000, 100.0, 1000), Join(mixer), Collect::default());
+        let mut graph = Graph::new(nodes, &[(0, 2), (1, 2), (2, 3)]);
+        assert!(matches!(graph.initialize().await, Err(Error::Unsupported)));
+
+        // A source whose output is not connected.
+        let nodes = (sine(8000, 100.0, 1000), Collect::default());
+        let mut graph = Graph::new(nodes, &[]);
+        assert!(matches!(graph.initialize().await, Err(Error::InvalidParameter)));
+    }
+}
@@ -2,6 +2,8 @@
 
 pub mod fmt;
 
+pub mod sample;
+
 pub mod encoder;
 pub mod decoder;
 pub mod generator;
@@ -9,10 +11,18 @@ pub mod stream;
 
 pub mod transformer;
 
+pub mod metadata;
+mod riff;
+#[cfg(test)]
+pub(crate) mod test_util;
+
 pub use rivulets::databus;
 pub use rivulets::utils;
 
-// pub mod pipeline;
+pub mod event;
+pub mod pipeline;
+#[cfg(feature = "std")]
+pub mod graph;
 // use std::sync::Arc;
 // use embassy_time::{Duration, Timer};
 
@@ -0,0 +1,360 @@
+//! Metadata stored in WAV files.
+//!
+//! [`WavMetadata`] holds the `LIST`/`INFO` text fields, the Broadcast Wave `bext` chunk and
+//! the `cue ` points of a file. The [`WavDecoder`](crate::decoder::WavDecoder) fills it in
+//! while parsing the header, and the [`WavEncoder`](crate::encoder::WavEncoder) writes it
+//! between the `fmt ` and `data` chunks.
+//!
+//! All storage has a fixed capacity, so metadata can be kept without an allocator. Text
+//! read from a file is truncated to fit; setting text that does not fit returns
+//! `Error::BufferFull`.
+
+use embedded_io::{Read, Seek, SeekFrom, Write};
+use heapless::{String, Vec};
+
+use embedded_audio_driver::Error;
+
+/// The maximum number of `INFO` fields.
+pub const MAX_INFO_ENTRIES: usize = 8;
+/// The maximum length of an `INFO` field in bytes.
+pub const INFO_TEXT_LEN: usize = 64;
+/// The maximum number of cue points.
+pub const MAX_CUE_POINTS: usize = 16;
+
+/// The `INFO` field holding the title.
+pub const INFO_TITLE: [u8; 4] = *b"INAM";
+/// The `INFO` field holding the artist.
+pub const INFO_ARTIST: [u8; 4] = *b"IART";
+/// The `INFO` field holding a comment.
+pub const INFO_COMMENT: [u8; 4] = *b"ICMT";
+/// The `INFO` field holding the creation date, e.g. `2024-05-01`.
+pub const INFO_CREATION_DATE: [u8; 4] = *b"ICRD";
+/// The `INFO` field holding the software that wrote the file.
+pub const INFO_SOFTWARE: [u8; 4] = *b"ISFT";
+
+/// The length of the fixed part of a `bext` chunk body, up to the coding history.
+const BEXT_LEN: usize = 602;
+/// The length of one cue point in a `cue ` chunk.
+const CUE_POINT_LEN: usize = 24;
+
+/// One text field of a `LIST`/`INFO` chunk.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InfoEntry {
+    pub id: [u8; 4],
+    pub text: String<INFO_TEXT_LEN>,
+}
+
+/// The Broadcast Wave Format (EBU Tech 3285) `bext` chunk.
+///
+/// Dates and times use the `yyyy-mm-dd` and `hh:mm:ss` formats. The UMID, loudness values
+/// and coding history are not kept; a written chunk is version 1 with an empty UMID.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Bext {
+    pub description: String<256>,
+    /// The device or organisation that made the recording.
+    pub originator: String<32>,
+    /// A unique reference, e.g. the ID of the recording device.
+    pub originator_reference: String<32>,
+    pub origination_date: String<10>,
+    pub origination_time: String<8>,
+    /// The number of samples since midnight at the first sample of the file.
+    pub time_reference: u64,
+}
+
+/// A marker at a frame of the audio data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CuePoint {
+    pub id: u32,
+    pub frame: u32,
+}
+
+/// The metadata chunks of a WAV file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WavMetadata {
+    pub info: Vec<InfoEntry, MAX_INFO_ENTRIES>,
+    pub bext: Option<Bext>,
+    pub cues: Vec<CuePoint, MAX_CUE_POINTS>,
+}
+
+impl WavMetadata {
+    pub const fn new() -> Self {
+        Self {
+            info: Vec::new(),
+            bext: None,
+            cues: Vec::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.info.is_empty() && self.bext.is_none() && self.cues.is_empty()
+    }
+
+    /// Returns the text of the `INFO` field `id`, e.g. [`INFO_TITLE`].
+    pub fn info(&self, id: [u8; 4]) -> Option<&str> {
+        self.info.iter().find(|entry| entry.id == id).map(|entry| entry.text.as_str())
+    }
+
+    /// Sets the text of the `INFO` field `id`, replacing any previous text.
+    pub fn set_info(&mut self, id: [u8; 4], text: &str) -> Result<(), Error> {
+        let mut entry = InfoEntry { id, text: String::new() };
+        entry.text.push_str(text).map_err(|_| Error::BufferFull)?;
+        match self.info.iter_mut().find(|entry| entry.id == id) {
+            Some(existing) => *existing = entry,
+            None => self.info.push(entry).map_err(|_| Error::BufferFull)?,
+        }
+        Ok(())
+    }
+
+    pub fn title(&self) -> Option<&str> {
+        self.info(INFO_TITLE)
+    }
+
+    /// Adds a cue point at `frame` and returns its id, one above the highest id so far.
+    ///
+    /// Returns `Error::BufferFull` if the cue list is full or the highest id is `u32::MAX`.
+    pub fn add_cue(&mut self, frame: u32) -> Result<u32, Error> {
+        let id = match self.cues.iter().map(|cue| cue.id).max() {
+            Some(id) => id.checked_add(1).ok_or(Error::BufferFull)?,
+            None => 1,
+        };
+        self.cues.push(CuePoint { id, frame }).map_err(|_| Error::BufferFull)?;
+        Ok(id)
+    }
+
+    /// The number of bytes [`WavMetadata::write_chunks`] writes.
+    pub(crate) fn chunks_len(&self) -> usize {
+        let mut len = 0;
+        if !self.info.is_empty() {
+            len += 8 + 4 + self.info.iter().map(|entry| 8 + padded(entry.text.len() + 1)).sum::<usize>();
+        }
+        if self.bext.is_some() {
+            len += 8 + BEXT_LEN;
+        }
+        if !self.cues.is_empty() {
+            len += 8 + 4 + self.cues.len() * CUE_POINT_LEN;
+        }
+        len
+    }
+
+    /// Writes the `LIST`, `bext` and `cue ` chunks that hold any data.
+    pub(crate) fn write_chunks<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
+        if !self.info.is_empty() {
+            let len = self.info.iter().map(|entry| 8 + padded(entry.text.len() + 1)).sum::<usize>();
+            write_chunk_header(writer, b"LIST", 4 + len)?;
+            writer.write_all(b"INFO").map_err(|_| Error::DeviceError)?;
+            for entry in &self.info {
+                // INFO text is NUL-terminated and padded to an even length.
+                let text_len = entry.text.len() + 1;
+                write_chunk_header(writer, &entry.id, text_len)?;
+                writer.write_all(entry.text.as_bytes()).map_err(|_| Error::DeviceError)?;
+                writer.write_all(&[0; 2][..padded(text_len) - entry.text.len()]).map_err(|_| Error::DeviceError)?;
+            }
+        }
+
+        if let Some(bext) = &self.bext {
+            let mut body = [0u8; BEXT_LEN];
+            copy_text(&mut body[0..256], &bext.description);
+            copy_text(&mut body[256..288], &bext.originator);
+            copy_text(&mut body[288..320], &bext.originator_reference);
+            copy_text(&mut body[320..330], &bext.origination_date);
+            copy_text(&mut body[330..338], &bext.origination_time);
+            body[338..346].copy_from_slice(&bext.time_reference.to_le_bytes());
+            body[346..348].copy_from_slice(&1u16.to_le_bytes()); // Version
+            write_chunk_header(writer, b"bext", BEXT_LEN)?;
+            writer.write_all(&body).map_err(|_| Error::DeviceError)?;
+        }
+
+        if !self.cues.is_empty() {
+            write_chunk_header(writer, b"cue ", 4 + self.cues.len() * CUE_POINT_LEN)?;
+            writer.write_all(&(self.cues.len() as u32).to_le_bytes()).map_err(|_| Error::DeviceError)?;
+            for cue in &self.cues {
+                let mut point = [0u8; CUE_POINT_LEN];
+                point[0..4].copy_from_slice(&cue.id.to_le_bytes());
+                point[4..8].copy_from_slice(&cue.frame.to_le_bytes()); // Play order position
+                point[8..12].copy_from_slice(b"data");
+                point[20..24].copy_from_slice(&cue.frame.to_le_bytes());
+                writer.write_all(&point).map_err(|_| Error::DeviceError)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads a `LIST` chunk body of `size` bytes. Lists other than `INFO` are ignored.
+    pub(crate) fn read_list<R: Read + Seek>(&mut self, reader: &mut R, size: u32) -> Result<(), Error> {
+        let mut list_type = [0u8; 4];
+        if size < 4 {
+            return Err(Error::InvalidParameter);
+        }
+        reader.read_exact(&mut list_type).map_err(|_| Error::DeviceError)?;
+        if &list_type != b"INFO" {
+            return Ok(());
+        }
+
+        let mut remaining = size as usize - 4;
+        while remaining >= 8 {
+            let mut header = [0u8; 8];
+            reader.read_exact(&mut header).map_err(|_| Error::DeviceError)?;
+            let id: [u8; 4] = header[0..4].try_into().unwrap();
+            let text_len = u32::from_le_bytes(header[4..8].try_into().unwrap()) as usize;
+            if text_len > remaining - 8 {
+                return Err(Error::InvalidParameter);
+            }
+            // Some writers leave out the pad byte of the last field.
+            let field_len = padded(text_len).min(remaining - 8);
+
+            let mut text = [0u8; INFO_TEXT_LEN];
+            let read_len = text_len.min(INFO_TEXT_LEN);
+            reader.read_exact(&mut text[..read_len]).map_err(|_| Error::DeviceError)?;
+            reader.seek(SeekFrom::Current((field_len - read_len) as i64)).map_err(|_| Error::DeviceError)?;
+
+            if self.info.push(InfoEntry { id, text: decode_text(&text[..read_len]) }).is_err() {
+                warn!("WAV: too many INFO fields, dropping the rest");
+            }
+            remaining -= 8 + field_len;
+        }
+        Ok(())
+    }
+
+    /// Reads a `bext` chunk body of `size` bytes. The coding history is skipped.
+    pub(crate) fn read_bext<R: Read>(&mut self, reader: &mut R, size: u32) -> Result<(), Error> {
+        let mut body = [0u8; BEXT_LEN];
+        if (size as usize) < BEXT_LEN {
+            return Err(Error::InvalidParameter);
+        }
+        reader.read_exact(&mut body).map_err(|_| Error::DeviceError)?;
+        self.bext = Some(Bext {
+            description: decode_text(&body[0..256]),
+            originator: decode_text(&body[256..288]),
+            originator_reference: decode_text(&body[288..320]),
+            origination_date: decode_text(&body[320..330]),
+            origination_time: decode_text(&body[330..338]),
+            time_reference: u64::from_le_bytes(body[338..346].try_into().unwrap()),
+        });
+        Ok(())
+    }
+
+    /// Reads a `cue ` chunk body of `size` bytes.
+    pub(crate) fn read_cue<R: Read>(&mut self, reader: &mut R, size: u32) -> Result<(), Error> {
+        let mut count = [0u8; 4];
+        if size < 4 {
+            return Err(Error::InvalidParameter);
+        }
+        reader.read_exact(&mut count).map_err(|_| Error::DeviceError)?;
+        let count = (u32::from_le_bytes(count) as usize).min((size as usize - 4) / CUE_POINT_LEN);
+
+        for _ in 0..count {
+            let mut point = [0u8; CUE_POINT_LEN];
+            reader.read_exact(&mut point).map_err(|_| Error::DeviceError)?;
+            let cue = CuePoint {
+                id: u32::from_le_bytes(point[0..4].try_into().unwrap()),
+                frame: u32::from_le_bytes(point[20..24].try_into().unwrap()),
+            };
+            if self.cues.push(cue).is_err() {
+                warn!("WAV: too many cue points, dropping the rest");
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Chunk bodies are padded to an even length.
+fn padded(len: usize) -> usize {
+    len + (len & 1)
+}
+
+fn write_chunk_header<W: Write>(writer: &mut W, id: &[u8; 4], len: usize) -> Result<(), Error> {
+    writer.write_all(id).map_err(|_| Error::DeviceError)?;
+    writer.write_all(&(len as u32).to_le_bytes()).map_err(|_| Error::DeviceError)
+}
+
+/// Copies `text` into a NUL-padded fixed-size field.
+fn copy_text(field: &mut [u8], text: &str) {
+    field[..text.len()].copy_from_slice(text.as_bytes());
+}
+
+/// Decodes NUL-terminated text, keeping the longest valid UTF-8 prefix that fits.
+fn decode_text<const N: usize>(bytes: &[u8]) -> String<N> {
+    let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len()).min(N);
+    let text = match core::str::from_utf8(&bytes[..end]) {
+        Ok(text) => text,
+        Err(e) => core::str::from_utf8(&bytes[..e.valid_up_to()]).unwrap(),
+    };
+    let mut decoded = String::new();
+    let _ = decoded.push_str(text);
+    decoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embedded_io_adapters::std::FromStd;
+    use std::io::Cursor;
+
+    fn metadata() -> WavMetadata {
+        let mut metadata = WavMetadata::new();
+        metadata.set_info(INFO_TITLE, "Dawn chorus").unwrap();
+        metadata.set_info(INFO_COMMENT, "odd").unwrap();
+        let mut bext = Bext::default();
+        bext.originator.push_str("field-recorder").unwrap();
+        bext.originator_reference.push_str("FR-0042").unwrap();
+        bext.origination_date.push_str("2024-05-01").unwrap();
+        bext.origination_time.push_str("04:59:30").unwrap();
+        bext.time_reference = 48000 * 17970;
+        metadata.bext = Some(bext);
+        metadata.add_cue(0).unwrap();
+        metadata.add_cue(48000).unwrap();
+        metadata
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let metadata = metadata();
+        let mut bytes = std::vec::Vec::new();
+        metadata.write_chunks(&mut FromStd::new(&mut bytes)).unwrap();
+        assert_eq!(bytes.len(), metadata.chunks_len());
+        assert_eq!(bytes.len() % 2, 0);
+
+        // Read the chunks back the way the decoder walks them.
+        let mut reader = FromStd::new(Cursor::new(bytes));
+        let mut decoded = WavMetadata::new();
+        let mut header = [0u8; 8];
+        while reader.read_exact(&mut header).is_ok() {
+            let size = u32::from_le_bytes(header[4..8].try_into().unwrap());
+            match &header[0..4] {
+                b"LIST" => decoded.read_list(&mut reader, size).unwrap(),
+                b"bext" => decoded.read_bext(&mut reader, size).unwrap(),
+                b"cue " => decoded.read_cue(&mut reader, size).unwrap(),
+                _ => unreachable!(),
+            }
+        }
+        assert_eq!(decoded, metadata);
+        assert_eq!(decoded.title(), Some("Dawn chorus"));
+        assert_eq!(decoded.cues[1], CuePoint { id: 2, frame: 48000 });
+    }
+
+    #[test]
+    fn test_bounded_storage() {
+        let mut metadata = WavMetadata::new();
+        let long = "x".repeat(INFO_TEXT_LEN + 1);
+        assert_eq!(metadata.set_info(INFO_TITLE, &long), Err(Error::BufferFull));
+        metadata.set_info(INFO_TITLE, "first").unwrap();
+        metadata.set_info(INFO_TITLE, "second").unwrap();
+        assert_eq!(metadata.info.len(), 1);
+        assert_eq!(metadata.title(), Some("second"));
+
+        for frame in 0..MAX_CUE_POINTS as u32 {
+            metadata.add_cue(frame).unwrap();
+        }
+        assert_eq!(metadata.add_cue(100), Err(Error::BufferFull));
+
+        // Ids read from a file may leave no room for another one.
+        let mut metadata = WavMetadata::new();
+        metadata.cues.push(CuePoint { id: u32::MAX, frame: 0 }).unwrap();
+        assert_eq!(metadata.add_cue(100), Err(Error::BufferFull));
+
+        // Text read from a file is truncated at a character boundary.
+        let text: String<4> = decode_text("abcé".as_bytes());
+        assert_eq!(text, "abc");
+    }
+}
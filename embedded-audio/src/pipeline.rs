@@ -0,0 +1,646 @@
+//! Running a linear chain of elements.
+//!
+//! A pipeline takes a tuple of 2 to 8 elements, starting with a source. `initialize` passes
+//! the output `Info` of each element to the next one and connects them from the returned
+//! `PortRequirements`: an element with an output opens a new databus, which the next element
+//! with an input consumes. An in-place element in between transforms the payloads on that
+//! databus; each databus takes at most one. Each databus is registered with every operation
+//! performed on it.
+//!
+//! Every call to `step` processes each element once, in order, so every element must produce
+//! and consume one payload per call. The pipeline ends after the step in which an element
+//! returned `Eof`: the elements after it have then consumed its final payload.
+//!
+//! [`Pipeline`] allocates its databuses as [`HeapSlot`]s. [`StaticPipeline`] uses databuses
+//! owned by the caller, e.g. statically allocated ones.
+//!
+//! `seek` moves a [`Seekable`] source to another frame. Every element is flushed first, so
+//! stateful elements such as filters, resamplers and output streams start over cleanly; the
+//! next payload of the source is marked `Position::First`. Between steps the databuses hold
+//! no payloads, so nothing from before the seek remains in flight.
+//!
+//! With an [`EventBus`] attached, a pipeline posts the output format of every element after
+//! `initialize` and whenever it changes, the element that returned `Eof`, and the element
+//! that failed, each tagged with the index of the element in the chain. Every element is
+//! reported `Initialized` after `initialize`, `Running` once it is stepped and `Stopped` once
+//! the pipeline has finished. After every step, each element reports the number of frames it
+//! has processed: the frames read from the databus it writes or transforms, or for a sink,
+//! the frames it read.
+
+use embedded_audio_driver::databus::{Consumer, Databus, Operation, Producer, Transformer};
+use embedded_audio_driver::element::{BaseElement, Eof, Fine, ProcessResult, Seekable};
+use embedded_audio_driver::info::Info;
+use embedded_audio_driver::port::{InPlacePort, InPort, OutPort, PortRequirements};
+use embedded_audio_driver::stream::StreamState;
+use embedded_audio_driver::Error;
+
+#[cfg(feature = "std")]
+use crate::databus::slot::HeapSlot;
+use crate::event::{EventBus, EventKind, Metered, Notifier};
+
+/// The maximum number of elements in a pipeline.
+pub const MAX_PIPELINE_ELEMENTS: usize = 8;
+
+/// The databuses an element is connected to, as indices into the databuses of a pipeline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Link {
+    pub input: Option<usize>,
+    pub output: Option<usize>,
+    pub in_place: Option<usize>,
+}
+
+impl Link {
+    fn in_port<'a, D: Consumer<'a>>(&self, databuses: &'a [D]) -> InPort<'a, D> {
+        match self.input {
+            Some(index) => InPort::Consumer(&databuses[index]),
+            None => InPort::None,
+        }
+    }
+
+    fn out_port<'a, D: Producer<'a>>(&self, databuses: &'a [D]) -> OutPort<'a, D> {
+        match self.output {
+            Some(index) => OutPort::Producer(&databuses[index]),
+            None => OutPort::None,
+        }
+    }
+
+    fn in_place_port<'a, D: Transformer<'a>>(&self, databuses: &'a [D]) -> InPlacePort<'a, D> {
+        match self.in_place {
+            Some(index) => InPlacePort::Transformer(&databuses[index]),
+            None => InPlacePort::None,
+        }
+    }
+}
+
+/// A chain of elements that can be run by a pipeline.
+///
+/// Implemented for tuples of 2 to [`MAX_PIPELINE_ELEMENTS`] elements.
+#[allow(async_fn_in_trait)]
+pub trait Chain {
+    /// The number of elements.
+    const LEN: usize;
+
+    /// The first element.
+    type Source;
+
+    fn source_mut(&mut self) -> &mut Self::Source;
+
+    /// Initializes the elements in order, passing each output `Info` downstream.
+    async fn initialize(&mut self, requirements: &mut [Option<PortRequirements>]) -> Result<(), Error>;
+
+    /// Returns the output `Info` of the element at `index`.
+    fn out_info(&self, index: usize) -> Option<Info>;
+
+    /// Flushes every element. On failure, returns the index of the failing element with the
+    /// error.
+    async fn flush(&mut self) -> Result<(), (usize, Error)>;
+
+    /// Processes every element once. Returns the index of the first element that returned
+    /// `Eof`, if any. On failure, returns the index of the failing element with the error.
+    async fn process<D>(&mut self, links: &[Link], databuses: &[D]) -> Result<Option<usize>, (usize, Error)>
+    where
+        D: for<'a> Consumer<'a> + for<'a> Producer<'a> + for<'a> Transformer<'a>;
+}
+
+macro_rules! impl_chain {
+    ($len:literal; $source:ident; $($index:tt $element:ident),+) => {
+        impl<$($element),+> Chain for ($($element,)+)
+        where
+            $($element: BaseElement<Info = Info, Error = Error>),+
+        {
+            const LEN: usize = $len;
+
+            type Source = $source;
+
+            fn source_mut(&mut self) -> &mut $source {
+                &mut self.0
+            }
+
+            async fn initialize(&mut self, requirements: &mut [Option<PortRequirements>]) -> Result<(), Error> {
+                let mut info = None;
+                $(
+                    requirements[$index] = Some(self.$index.initialize(info).await?);
+                    info = self.$index.get_out_info();
+                )+
+                let _ = info;
+                Ok(())
+            }
+
+            fn out_info(&self, index: usize) -> Option<Info> {
+                match index {
+                    $($index => self.$index.get_out_info(),)+
+                    _ => None,
+                }
+            }
+
+            async fn flush(&mut self) -> Result<(), (usize, Error)> {
+                $(self.$index.flush().await.map_err(|error| ($index, error))?;)+
+                Ok(())
+            }
+
+            async fn process<D>(&mut self, links: &[Link], databuses: &[D]) -> Result<Option<usize>, (usize, Error)>
+            where
+                D: for<'a> Consumer<'a> + for<'a> Producer<'a> + for<'a> Transformer<'a>,
+            {
+                let mut eof = None;
+                $(
+                    let link = links[$index];
+                    let result = self.$index
+                        .process(
+                            &mut link.in_port(databuses),
+                            &mut link.out_port(databuses),
+                            &mut link.in_place_port(databuses),
+                        )
+                        .await
+                        .map_err(|error| ($index, error))?;
+                    if result == Eof && eof.is_none() {
+                        eof = Some($index);
+                    }
+                )+
+                Ok(eof)
+            }
+        }
+    };
+}
+
+impl_chain!(2; E0; 0 E0, 1 E1);
+impl_chain!(3; E0; 0 E0, 1 E1, 2 E2);
+impl_chain!(4; E0; 0 E0, 1 E1, 2 E2, 3 E3);
+impl_chain!(5; E0; 0 E0, 1 E1, 2 E2, 3 E3, 4 E4);
+impl_chain!(6; E0; 0 E0, 1 E1, 2 E2, 3 E3, 4 E4, 5 E5);
+impl_chain!(7; E0; 0 E0, 1 E1, 2 E2, 3 E3, 4 E4, 5 E5, 6 E6);
+impl_chain!(8; E0; 0 E0, 1 E1, 2 E2, 3 E3, 4 E4, 5 E5, 6 E6, 7 E7);
+
+/// The wiring of an initialized chain.
+struct Plan {
+    requirements: [Option<PortRequirements>; MAX_PIPELINE_ELEMENTS],
+    links: [Link; MAX_PIPELINE_ELEMENTS],
+    /// The largest preferred payload size on each databus.
+    sizes: [usize; MAX_PIPELINE_ELEMENTS],
+    databuses: usize,
+}
+
+impl Plan {
+    /// Initializes `chain` and connects its elements.
+    ///
+    /// Returns `Error::InvalidParameter` if an element has nothing to consume, if a databus
+    /// gets a second in-place element, or if the last element leaves an output unconnected.
+    async fn new<E: Chain>(chain: &mut E) -> Result<Self, Error> {
+        let mut plan = Self {
+            requirements: [None; MAX_PIPELINE_ELEMENTS],
+            links: [Link::default(); MAX_PIPELINE_ELEMENTS],
+            sizes: [0; MAX_PIPELINE_ELEMENTS],
+            databuses: 0,
+        };
+        chain.initialize(&mut plan.requirements[..E::LEN]).await?;
+
+        let mut current: Option<usize> = None;
+        let mut transformed = [false; MAX_PIPELINE_ELEMENTS];
+        for (requirements, link) in plan.requirements[..E::LEN].iter().flatten().zip(plan.links.iter_mut()) {
+            if let Some(size) = requirements.in_place {
+                let index = current.ok_or(Error::InvalidParameter)?;
+                if transformed[index] {
+                    return Err(Error::InvalidParameter);
+                }
+                transformed[index] = true;
+                plan.sizes[index] = plan.sizes[index].max(size.preferred as usize);
+                link.in_place = Some(index);
+                continue;
+            }
+            if let Some(size) = requirements.in_ {
+                let index = current.take().ok_or(Error::InvalidParameter)?;
+                plan.sizes[index] = plan.sizes[index].max(size.preferred as usize);
+                link.input = Some(index);
+            }
+            if let Some(size) = requirements.out {
+                if current.is_some() {
+                    // The previous output has no consumer.
+                    return Err(Error::InvalidParameter);
+                }
+                let index = plan.databuses;
+                plan.databuses += 1;
+                plan.sizes[index] = size.preferred as usize;
+                link.output = Some(index);
+                current = Some(index);
+            }
+        }
+        if current.is_some() {
+            return Err(Error::InvalidParameter);
+        }
+        Ok(plan)
+    }
+
+    /// Registers every operation of the chain on `databuses`.
+    fn register<D: Databus>(&self, databuses: &mut [D]) {
+        for (requirements, link) in self.requirements.iter().flatten().zip(self.links.iter()) {
+            if let (Some(index), Some(size)) = (link.output, requirements.out) {
+                databuses[index].register(Operation::Produce, size);
+            }
+            if let (Some(index), Some(size)) = (link.input, requirements.in_) {
+                databuses[index].register(Operation::Consume, size);
+            }
+            if let (Some(index), Some(size)) = (link.in_place, requirements.in_place) {
+                databuses[index].register(Operation::InPlace, size);
+            }
+        }
+    }
+}
+
+/// Processes `chain` once and posts the outcome.
+async fn step<E, D>(
+    notifier: &mut Notifier<'_, MAX_PIPELINE_ELEMENTS>,
+    chain: &mut E,
+    links: &[Link],
+    databuses: &[D],
+) -> ProcessResult<Error>
+where
+    E: Chain,
+    D: for<'a> Consumer<'a> + for<'a> Producer<'a> + for<'a> Transformer<'a>,
+{
+    for index in 0..E::LEN {
+        notifier.state(index, StreamState::Running);
+    }
+    let metered: heapless::Vec<Metered<'_, D>, MAX_PIPELINE_ELEMENTS> =
+        databuses.iter().take(MAX_PIPELINE_ELEMENTS).map(Metered::new).collect();
+    let eof = chain.process(links, &metered).await.map_err(|(index, error)| {
+        notifier.post(index, EventKind::Error(error));
+        error
+    })?;
+
+    notifier.formats(E::LEN, |index| chain.out_info(index));
+    for (index, link) in links.iter().enumerate() {
+        // A sink reads the output of the element before it.
+        let (databus, info) = match (link.output.or(link.in_place), link.input) {
+            (Some(databus), _) => (databus, chain.out_info(index)),
+            (None, Some(databus)) => (databus, index.checked_sub(1).and_then(|index| chain.out_info(index))),
+            (None, None) => continue,
+        };
+        if let Some(info) = info.filter(|info| info.get_alignment_bytes() > 0) {
+            notifier.progress(index, metered[databus].bytes() / info.get_alignment_bytes() as usize);
+        }
+    }
+
+    match eof {
+        Some(index) => {
+            notifier.post(index, EventKind::Eof);
+            for index in 0..E::LEN {
+                notifier.state(index, StreamState::Stopped);
+            }
+            Ok(Eof)
+        }
+        None => Ok(Fine),
+    }
+}
+
+/// Flushes `chain` and seeks its source, posting any error.
+async fn seek<E: Chain>(notifier: &mut Notifier<'_, MAX_PIPELINE_ELEMENTS>, chain: &mut E, frame: u64) -> Result<u64, Error>
+where
+    E::Source: Seekable,
+{
+    let result = chain.flush().await;
+    result.and_then(|()| chain.source_mut().seek(frame).map_err(|error| (0, error))).map_err(|(index, error)| {
+        notifier.post(index, EventKind::Error(error));
+        error
+    })
+}
+
+/// A pipeline running on databuses provided by the caller.
+pub struct StaticPipeline<'d, E, D> {
+    elements: E,
+    databuses: &'d mut [D],
+    links: [Link; MAX_PIPELINE_ELEMENTS],
+    notifier: Notifier<'d, MAX_PIPELINE_ELEMENTS>,
+    initialized: bool,
+    finished: bool,
+}
+
+impl<'d, E, D> StaticPipeline<'d, E, D>
+where
+    E: Chain,
+    D: Databus + for<'a> Consumer<'a> + for<'a> Producer<'a> + for<'a> Transformer<'a>,
+{
+    /// Creates a new pipeline.
+    ///
+    /// # Arguments
+    ///
+    /// * `elements` - The chain, starting with a source.
+    /// * `databuses` - Unregistered databuses, one per element with an output. Each must hold
+    ///   the preferred payload size of the elements it connects.
+    pub fn new(elements: E, databuses: &'d mut [D]) -> Self {
+        Self {
+            elements,
+            databuses,
+            links: [Link::default(); MAX_PIPELINE_ELEMENTS],
+            notifier: Notifier::new(),
+            initialized: false,
+            finished: false,
+        }
+    }
+
+    /// Posts events about the elements to `events`.
+    pub fn with_events(mut self, events: &'d EventBus) -> Self {
+        self.notifier.events = Some(events);
+        self
+    }
+
+    pub fn elements(&self) -> &E {
+        &self.elements
+    }
+
+    pub fn elements_mut(&mut self) -> &mut E {
+        &mut self.elements
+    }
+
+    /// Initializes the elements and registers the databuses.
+    ///
+    /// Before it is registered, each databus is probed with one empty payload to learn its size.
+    /// Returns `Error::InvalidParameter` if fewer databuses were provided than the chain needs,
+    /// or if a databus cannot hold the preferred payload size of the elements it connects.
+    pub async fn initialize(&mut self) -> Result<(), Error> {
+        let plan = Plan::new(&mut self.elements).await?;
+        if plan.databuses > self.databuses.len() {
+            return Err(Error::InvalidParameter);
+        }
+        for (index, (databus, &size)) in self.databuses.iter().zip(plan.sizes[..plan.databuses].iter()).enumerate() {
+            let capacity = {
+                let mut payload = databus.acquire_write().await;
+                payload.set_valid_length(0);
+                payload.len()
+            };
+            drop(databus.acquire_read().await);
+            if capacity < size {
+                warn!("StaticPipeline: databus {} holds {} bytes, {} needed", index, capacity, size);
+                return Err(Error::InvalidParameter);
+            }
+        }
+        plan.register(self.databuses);
+        self.links = plan.links;
+        self.notifier.initialized(E::LEN);
+        self.notifier.formats(E::LEN, |index| self.elements.out_info(index));
+        self.initialized = true;
+        self.finished = false;
+        Ok(())
+    }
+
+    /// Processes every element once. Returns `Eof` once the pipeline has finished.
+    pub async fn step(&mut self) -> ProcessResult<Error> {
+        if !self.initialized {
+            return Err(Error::NotInitialized);
+        }
+        if self.finished {
+            return Ok(Eof);
+        }
+        let status = step(&mut self.notifier, &mut self.elements, &self.links[..E::LEN], self.databuses).await?;
+        self.finished = status == Eof;
+        Ok(status)
+    }
+
+    /// Runs the pipeline until it finishes.
+    pub async fn run(&mut self) -> Result<(), Error> {
+        while self.step().await? == Fine {}
+        Ok(())
+    }
+
+    /// Flushes every element and moves the source to `frame`. Returns the frame moved to.
+    ///
+    /// A finished pipeline can be run again after a seek.
+    pub async fn seek(&mut self, frame: u64) -> Result<u64, Error>
+    where
+        E::Source: Seekable,
+    {
+        if !self.initialized {
+            return Err(Error::NotInitialized);
+        }
+        let frame = seek(&mut self.notifier, &mut self.elements, frame).await?;
+        self.finished = false;
+        Ok(frame)
+    }
+
+    /// Like [`seek`](Self::seek), with the position in milliseconds.
+    pub async fn seek_ms(&mut self, ms: u64) -> Result<u64, Error>
+    where
+        E::Source: Seekable,
+    {
+        let info = self.elements.source_mut().get_out_info().ok_or(Error::NotInitialized)?;
+        self.seek(ms * info.sample_rate as u64 / 1000).await
+    }
+}
+
+/// A pipeline that allocates its databuses on the heap.
+#[cfg(feature = "std")]
+pub struct Pipeline<'e, E> {
+    elements: E,
+    databuses: std::vec::Vec<HeapSlot>,
+    links: [Link; MAX_PIPELINE_ELEMENTS],
+    notifier: Notifier<'e, MAX_PIPELINE_ELEMENTS>,
+    finished: bool,
+}
+
+#[cfg(feature = "std")]
+impl<'e, E: Chain> Pipeline<'e, E> {
+    /// Creates a new pipeline from a chain starting with a source.
+    pub fn new(elements: E) -> Self {
+        Self {
+            elements,
+            databuses: std::vec::Vec::new(),
+            links: [Link::default(); MAX_PIPELINE_ELEMENTS],
+            notifier: Notifier::new(),
+            finished: false,
+        }
+    }
+
+    /// Posts events about the elements to `events`.
+    pub fn with_events(mut self, events: &'e EventBus) -> Self {
+        self.notifier.events = Some(events);
+        self
+    }
+
+    pub fn elements(&self) -> &E {
+        &self.elements
+    }
+
+    pub fn elements_mut(&mut self) -> &mut E {
+        &mut self.elements
+    }
+
+    /// Initializes the elements, then allocates and registers the databuses.
+    pub async fn initialize(&mut self) -> Result<(), Error> {
+        let plan = Plan::new(&mut self.elements).await?;
+        self.databuses = plan.sizes[..plan.databuses].iter().map(|&size| HeapSlot::new_heap(size)).collect();
+        plan.register(&mut self.databuses);
+        self.links = plan.links;
+        self.notifier.initialized(E::LEN);
+        self.notifier.formats(E::LEN, |index| self.elements.out_info(index));
+        self.finished = false;
+        Ok(())
+    }
+
+    /// Processes every element once. Returns `Eof` once the pipeline has finished.
+    pub async fn step(&mut self) -> ProcessResult<Error> {
+        if self.databuses.is_empty() {
+            return Err(Error::NotInitialized);
+        }
+        if self.finished {
+            return Ok(Eof);
+        }
+        let status = step(&mut self.notifier, &mut self.elements, &self.links[..E::LEN], &self.databuses).await?;
+        self.finished = status == Eof;
+        Ok(status)
+    }
+
+    /// Runs the pipeline until it finishes.
+    pub async fn run(&mut self) -> Result<(), Error> {
+        while self.step().await? == Fine {}
+        Ok(())
+    }
+
+    /// Flushes every element and moves the source to `frame`. Returns the frame moved to.
+    ///
+    /// A finished pipeline can be run again after a seek.
+    pub async fn seek(&mut self, frame: u64) -> Result<u64, Error>
+    where
+        E::Source: Seekable,
+    {
+        if self.databuses.is_empty() {
+            return Err(Error::NotInitialized);
+        }
+        let frame = seek(&mut self.notifier, &mut self.elements, frame).await?;
+        self.finished = false;
+        Ok(frame)
+    }
+
+    /// Like [`seek`](Self::seek), with the position in milliseconds.
+    pub async fn seek_ms(&mut self, ms: u64) -> Result<u64, Error>
+    where
+        E::Source: Seekable,
+    {
+        let info = self.elements.source_mut().get_out_info().ok_or(Error::NotInitialized)?;
+        self.seek(ms * info.sample_rate as u64 / 1000).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::event::Event;
+    use crate::generator::SineWaveGenerator;
+    use crate::test_util::Collect;
+    use crate::transformer::format_converter::{Dither, FormatConverter, TargetFormat};
+    use crate::transformer::Gain;
+
+    fn sine(channels: u8, bits: u8) -> SineWaveGenerator {
+        SineWaveGenerator::new(Info::new(8000, channels, bits, Some(1000)), 100.0, 0.5, 64)
+    }
+
+    #[tokio::test]
+    async fn test_heap_pipeline_with_in_place_element() {
+        let mut pipeline = Pipeline::new((sine(1, 16), Gain::new(0.5, 64), Collect::default()));
+        assert!(matches!(pipeline.step().await, Err(Error::NotInitialized)));
+        pipeline.initialize().await.unwrap();
+        pipeline.run().await.unwrap();
+
+        let samples = &pipeline.elements().2.samples;
+        assert!(samples.len() >= 1000);
+        let peak = samples.iter().map(|s| s.unsigned_abs()).max().unwrap();
+        assert!((8000..=8192).contains(&peak), "{}", peak);
+        assert_eq!(pipeline.step().await.unwrap(), Eof);
+    }
+
+    #[tokio::test]
+    async fn test_static_pipeline() {
+        let converter = FormatConverter::new(TargetFormat::I16, Dither::None, 64);
+        let mut databuses = [HeapSlot::new_heap(512), HeapSlot::new_heap(512)];
+        let mut pipeline = StaticPipeline::new((sine(2, 32), converter, Collect::default()), &mut databuses);
+        pipeline.initialize().await.unwrap();
+        pipeline.run().await.unwrap();
+
+        let samples = &pipeline.elements().2.samples;
+        assert!(samples.len() >= 2000);
+        assert!(samples.chunks_exact(2).all(|frame| frame[0] == frame[1]));
+    }
+
+    #[tokio::test]
+    async fn test_seek() {
+        let mut pipeline = Pipeline::new((sine(1, 16), Gain::new(0.5, 64), Collect::default()));
+        assert!(matches!(pipeline.seek(0).await, Err(Error::NotInitialized)));
+        pipeline.initialize().await.unwrap();
+        pipeline.run().await.unwrap();
+        let played = pipeline.elements().2.samples.len();
+
+        // A finished pipeline plays on from the new position.
+        assert_eq!(pipeline.seek(900).await.unwrap(), 900);
+        pipeline.run().await.unwrap();
+        let samples = &pipeline.elements().2.samples;
+        assert!((100..=101).contains(&(samples.len() - played)), "{}", samples.len() - played);
+        assert_eq!(samples[played], samples[900]);
+
+        assert_eq!(pipeline.seek_ms(50).await.unwrap(), 400);
+        assert_eq!(pipeline.step().await.unwrap(), Fine);
+    }
+
+    #[tokio::test]
+    async fn test_events() {
+        let events = EventBus::new();
+        let converter = FormatConverter::new(TargetFormat::I16, Dither::None, 64);
+        let mut pipeline = Pipeline::new((sine(1, 32), converter, Collect::default())).with_events(&events);
+        pipeline.initialize().await.unwrap();
+
+        for element in 0..3 {
+            assert_eq!(events.try_receive(), Some(Event { element, kind: EventKind::State(StreamState::Initialized) }));
+        }
+        let event = events.try_receive().unwrap();
+        assert_eq!(event.element, 0);
+        assert!(matches!(event.kind, EventKind::Format(info) if info.bits_per_sample == 32));
+        let event = events.try_receive().unwrap();
+        assert_eq!(event.element, 1);
+        assert!(matches!(event.kind, EventKind::Format(info) if info.bits_per_sample == 16));
+        assert_eq!(events.try_receive(), None);
+
+        pipeline.run().await.unwrap();
+        for element in 0..3 {
+            assert_eq!(events.try_receive(), Some(Event { element, kind: EventKind::State(StreamState::Running) }));
+        }
+        assert_eq!(events.try_receive(), Some(Event { element: 0, kind: EventKind::Eof }));
+        for element in 0..3 {
+            assert_eq!(events.try_receive(), Some(Event { element, kind: EventKind::State(StreamState::Stopped) }));
+        }
+        assert_eq!(events.try_receive(), None);
+    }
+
+    #[tokio::test]
+    async fn test_progress_of_every_element() {
+        let events = EventBus::new();
+        let mut pipeline = Pipeline::new((sine(2, 16), Collect::default())).with_events(&events);
+        pipeline.initialize().await.unwrap();
+
+        assert_eq!(pipeline.step().await.unwrap(), Fine);
+        assert_eq!(events.try_progress(0), Some(64));
+        assert_eq!(events.try_progress(1), Some(64));
+
+        pipeline.run().await.unwrap();
+        let frames = pipeline.elements().1.samples.len() as u64 / 2;
+        assert!(frames >= 1000);
+        assert_eq!(events.try_progress(0), Some(frames));
+        assert_eq!(events.try_progress(1), Some(frames));
+    }
+
+    #[tokio::test]
+    async fn test_invalid_wiring() {
+        // A source without a consumer.
+        let mut pipeline = Pipeline::new((sine(1, 16), Gain::new(0.5, 64)));
+        assert!(matches!(pipeline.initialize().await, Err(Error::InvalidParameter)));
+
+        // Too few databuses.
+        let converter = FormatConverter::new(TargetFormat::I16, Dither::None, 64);
+        let mut databuses = [HeapSlot::new_heap(512)];
+        let mut pipeline = StaticPipeline::new((sine(1, 32), converter, Collect::default()), &mut databuses);
+        assert!(matches!(pipeline.initialize().await, Err(Error::InvalidParameter)));
+
+        // A databus smaller than the 64 frames of 16-bit samples the converter writes.
+        let converter = FormatConverter::new(TargetFormat::I16, Dither::None, 64);
+        let mut databuses = [HeapSlot::new_heap(512), HeapSlot::new_heap(64)];
+        let mut pipeline = StaticPipeline::new((sine(1, 32), converter, Collect::default()), &mut databuses);
+        assert!(matches!(pipeline.initialize().await, Err(Error::InvalidParameter)));
+    }
+}
@@ -0,0 +1,20 @@
+//! Constants shared by the WAV decoder and encoder.
+
+pub(crate) const WAVE_FORMAT_PCM: u16 = 0x0001;
+pub(crate) const WAVE_FORMAT_IEEE_FLOAT: u16 = 0x0003;
+pub(crate) const WAVE_FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+/// A RIFF or `data` size written by streaming encoders that do not know the final length.
+pub(crate) const UNKNOWN_SIZE: u32 = 0xFFFF_FFFF;
+
+/// The length of a `ds64` chunk without a chunk size table, including its header.
+///
+/// RF64 and BW64 files keep their 64-bit RIFF and `data` sizes and the sample count in a
+/// `ds64` chunk that directly follows the RIFF header; the 32-bit fields are `0xFFFFFFFF`.
+pub(crate) const DS64_CHUNK_LEN: usize = 8 + 28;
+
+/// The last 14 bytes shared by every `KSDATAFORMAT_SUBTYPE_*` GUID.
+/// The first two bytes of the sub-format GUID hold the plain format tag.
+pub(crate) const SUBFORMAT_GUID_TAIL: [u8; 14] = [
+    0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xAA, 0x00, 0x38, 0x9B, 0x71,
+];
@@ -0,0 +1,192 @@
+//! Reading and writing individual samples in the layouts described by [`Info`].
+//!
+//! Integer samples are exchanged as MSB-aligned `i32` (full scale is `i32::MIN..=i32::MAX`
+//! regardless of the container size) or as `f32` in the range `[-1.0, 1.0)`.
+
+use embedded_audio_driver::info::{Endianness, Info, SampleEncoding};
+
+pub(crate) const Q31_SCALE: f32 = 2_147_483_648.0;
+
+/// Encoder/decoder for a single sample layout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SampleCodec {
+    bytes: usize,
+    encoding: SampleEncoding,
+    endianness: Endianness,
+}
+
+impl SampleCodec {
+    /// Creates a codec for the sample layout of `info`.
+    ///
+    /// Returns `None` for layouts that cannot be handled: integer containers
+    /// wider than 32 bits and float containers other than 32 or 64 bits.
+    pub fn from_info(info: &Info) -> Option<Self> {
+        let supported = match info.encoding {
+            SampleEncoding::Signed | SampleEncoding::Unsigned => {
+                matches!(info.bits_per_sample, 8 | 16 | 24 | 32)
+            }
+            SampleEncoding::Float => matches!(info.bits_per_sample, 32 | 64),
+        };
+        if !supported {
+            return None;
+        }
+        Some(Self {
+            bytes: info.bits_per_sample as usize / 8,
+            encoding: info.encoding,
+            endianness: info.endianness,
+        })
+    }
+
+    /// The number of bytes each sample occupies.
+    pub fn bytes_per_sample(&self) -> usize {
+        self.bytes
+    }
+
+    /// Returns `true` if the samples are IEEE floating point.
+    pub fn is_float(&self) -> bool {
+        self.encoding == SampleEncoding::Float
+    }
+
+    /// Assembles the container bytes into an integer, most significant byte first.
+    #[inline]
+    fn read_raw(&self, src: &[u8]) -> u64 {
+        let src = &src[..self.bytes];
+        let mut raw = 0u64;
+        match self.endianness {
+            Endianness::Little => src.iter().rev().for_each(|b| raw = (raw << 8) | *b as u64),
+            Endianness::Big => src.iter().for_each(|b| raw = (raw << 8) | *b as u64),
+        }
+        raw
+    }
+
+    /// Splits the low `bytes` bytes of `raw` into the container.
+    #[inline]
+    fn write_raw(&self, dest: &mut [u8], raw: u64) {
+        let dest = &mut dest[..self.bytes];
+        let bytes = raw.to_le_bytes();
+        dest.copy_from_slice(&bytes[..self.bytes]);
+        if self.endianness == Endianness::Big {
+            dest.reverse();
+        }
+    }
+
+    /// Reads one sample as an MSB-aligned `i32`.
+    #[inline]
+    pub fn read_i32(&self, src: &[u8]) -> i32 {
+        let raw = self.read_raw(src);
+        match self.encoding {
+            SampleEncoding::Signed => ((raw as u32) << (32 - 8 * self.bytes)) as i32,
+            SampleEncoding::Unsigned => (((raw as u32) << (32 - 8 * self.bytes)) ^ 0x8000_0000) as i32,
+            SampleEncoding::Float => f32_to_q31(self.decode_float(raw)),
+        }
+    }
+
+    /// Writes one sample from an MSB-aligned `i32`, truncating the bits the container cannot hold.
+    #[inline]
+    pub fn write_i32(&self, dest: &mut [u8], value: i32) {
+        match self.encoding {
+            SampleEncoding::Signed => self.write_raw(dest, ((value as u32) >> (32 - 8 * self.bytes)) as u64),
+            SampleEncoding::Unsigned => {
+                self.write_raw(dest, (((value as u32) ^ 0x8000_0000) >> (32 - 8 * self.bytes)) as u64)
+            }
+            SampleEncoding::Float => self.encode_float(dest, value as f32 / Q31_SCALE),
+        }
+    }
+
+    /// Reads one sample as `f32`, integer full scale maps to `[-1.0, 1.0)`.
+    #[inline]
+    pub fn read_f32(&self, src: &[u8]) -> f32 {
+        match self.encoding {
+            SampleEncoding::Float => self.decode_float(self.read_raw(src)),
+            _ => self.read_i32(src) as f32 / Q31_SCALE,
+        }
+    }
+
+    /// Writes one sample from `f32`. Integer formats are rounded and clamped to full scale.
+    #[inline]
+    pub fn write_f32(&self, dest: &mut [u8], value: f32) {
+        match self.encoding {
+            SampleEncoding::Float => self.encode_float(dest, value),
+            _ => {
+                // Round at the container resolution before truncation in `write_i32`.
+                let half_lsb = (1i64 << (32 - 8 * self.bytes)) >> 1;
+                let scaled = (value as f64 * Q31_SCALE as f64) as i64 + half_lsb;
+                self.write_i32(dest, scaled.clamp(i32::MIN as i64, i32::MAX as i64) as i32)
+            }
+        }
+    }
+
+    #[inline]
+    fn decode_float(&self, raw: u64) -> f32 {
+        if self.bytes == 4 {
+            f32::from_bits(raw as u32)
+        } else {
+            f64::from_bits(raw) as f32
+        }
+    }
+
+    #[inline]
+    fn encode_float(&self, dest: &mut [u8], value: f32) {
+        if self.bytes == 4 {
+            self.write_raw(dest, value.to_bits() as u64)
+        } else {
+            self.write_raw(dest, (value as f64).to_bits())
+        }
+    }
+}
+
+/// Converts a float sample to an MSB-aligned `i32`, clamping to full scale.
+#[inline]
+pub fn f32_to_q31(value: f32) -> i32 {
+    (value as f64 * Q31_SCALE as f64).clamp(i32::MIN as f64, i32::MAX as f64) as i32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_integer_round_trip() {
+        let mut buf = [0u8; 4];
+
+        let codec = SampleCodec::from_info(&Info::new(48000, 1, 16, None)).unwrap();
+        codec.write_i32(&mut buf, 0x1234_5678);
+        assert_eq!(&buf[..2], &0x1234i16.to_le_bytes());
+        assert_eq!(codec.read_i32(&buf), 0x1234_0000);
+
+        let codec = SampleCodec::from_info(&Info::new(48000, 1, 24, None)).unwrap();
+        buf[..3].copy_from_slice(&[0x00, 0x00, 0x80]);
+        assert_eq!(codec.read_i32(&buf), i32::MIN);
+        assert_eq!(codec.read_f32(&buf), -1.0);
+
+        let codec = SampleCodec::from_info(&Info::new(48000, 1, 8, None)).unwrap();
+        codec.write_f32(&mut buf, 0.0);
+        assert_eq!(buf[0], 128);
+        codec.write_f32(&mut buf, 2.0);
+        assert_eq!(buf[0], 255);
+
+        let mut info = Info::new(48000, 1, 16, None);
+        info.set_endianness(Endianness::Big);
+        let codec = SampleCodec::from_info(&info).unwrap();
+        codec.write_i32(&mut buf, -2 << 16);
+        assert_eq!(&buf[..2], &(-2i16).to_be_bytes());
+        assert_eq!(codec.read_i32(&buf), -2 << 16);
+    }
+
+    #[test]
+    fn test_float_round_trip() {
+        let mut buf = [0u8; 8];
+
+        let codec = SampleCodec::from_info(&Info::new_float(48000, 1, 32, None)).unwrap();
+        codec.write_f32(&mut buf, -0.25);
+        assert_eq!(f32::from_le_bytes(buf[..4].try_into().unwrap()), -0.25);
+        assert_eq!(codec.read_i32(&buf), -1 << 29);
+
+        let codec = SampleCodec::from_info(&Info::new_float(48000, 1, 64, None)).unwrap();
+        codec.write_i32(&mut buf, 1 << 30);
+        assert_eq!(f64::from_le_bytes(buf), 0.5);
+        assert_eq!(codec.read_f32(&buf), 0.5);
+
+        assert!(SampleCodec::from_info(&Info::new(48000, 1, 64, None)).is_none());
+    }
+}
@@ -0,0 +1,522 @@
+use std::boxed::Box;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::Arc;
+
+use async_ringbuf::traits::{AsyncConsumer, Consumer, Observer, Producer, Split};
+use async_ringbuf::{AsyncHeapCons, AsyncHeapRb};
+use cpal::traits::{DeviceTrait, StreamTrait};
+use cpal::SizedSample;
+
+use embedded_audio_driver::databus::{Consumer as DatabusConsumer, Producer as DatabusProducer, Transformer as DatabusTransformer};
+use embedded_audio_driver::element::{BaseElement, ProcessResult, Eof, Fine};
+use embedded_audio_driver::info::Info;
+use embedded_audio_driver::payload::Position;
+use embedded_audio_driver::port::{InPlacePort, InPort, OutPort, PayloadSize, PortRequirements};
+use embedded_audio_driver::stream::{BaseStream, StreamState};
+use embedded_audio_driver::Error;
+use crate::event::{EventKind, EventSender};
+use super::cpal_output::{sample_encoding, Config};
+
+/// A cpal sample type that can be written as little-endian bytes.
+pub trait ToBytes<const N: usize> {
+    fn to_le_bytes(self) -> [u8; N];
+}
+
+macro_rules! impl_to_bytes {
+    ($($t:ty),*) => {
+        $(impl ToBytes<{ core::mem::size_of::<$t>() }> for $t {
+            fn to_le_bytes(self) -> [u8; core::mem::size_of::<$t>()] {
+                <$t>::to_le_bytes(self)
+            }
+        })*
+    };
+}
+
+impl_to_bytes!(i8, u8, i16, u16, i32, u32, i64, u64, f32, f64);
+
+/// The callback receiving captured samples on the audio thread.
+pub type InputCallback<T> = Box<dyn FnMut(&[T]) + Send + 'static>;
+
+/// Creates and controls the capture stream behind a [`CpalInputStream`].
+///
+/// [`DeviceBackend`] captures from a cpal device; tests can drive the callback directly.
+pub trait InputBackend<T> {
+    /// Builds a paused stream that delivers captured samples to `callback`.
+    fn build(&mut self, config: &cpal::StreamConfig, callback: InputCallback<T>) -> Result<(), Error>;
+
+    fn play(&mut self) -> Result<(), Error>;
+
+    fn pause(&mut self) -> Result<(), Error>;
+}
+
+/// Captures from a cpal input device.
+pub struct DeviceBackend {
+    device: cpal::Device,
+    stream: Option<cpal::Stream>,
+}
+
+impl DeviceBackend {
+    pub fn new(device: cpal::Device) -> Self {
+        Self { device, stream: None }
+    }
+}
+
+impl<T: SizedSample + Send + 'static> InputBackend<T> for DeviceBackend {
+    fn build(&mut self, config: &cpal::StreamConfig, mut callback: InputCallback<T>) -> Result<(), Error> {
+        let err_fn = |err| warn!("CpalInputStream: stream error: {}", err);
+        let stream = self
+            .device
+            .build_input_stream(config, move |data: &[T], _: &cpal::InputCallbackInfo| callback(data), err_fn, None)
+            .map_err(|_| Error::DeviceError)?;
+        stream.pause().map_err(|_| Error::DeviceError)?;
+        self.stream = Some(stream);
+        Ok(())
+    }
+
+    fn play(&mut self) -> Result<(), Error> {
+        let stream = self.stream.as_ref().ok_or(Error::NotInitialized)?;
+        stream.play().map_err(|_| Error::DeviceError)
+    }
+
+    fn pause(&mut self) -> Result<(), Error> {
+        let stream = self.stream.as_ref().ok_or(Error::NotInitialized)?;
+        stream.pause().map_err(|_| Error::DeviceError)
+    }
+}
+
+/// Counters of a [`CpalInputStream`] since it was initialized.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct InputStats {
+    /// The number of callbacks that found the ring buffer full.
+    pub overruns: u32,
+    /// The number of captured frames dropped because the ring buffer was full.
+    pub dropped_frames: u64,
+}
+
+/// State shared with the audio thread.
+#[derive(Default)]
+struct Shared {
+    overruns: AtomicU32,
+    dropped_frames: AtomicU64,
+}
+
+/// An input stream that captures audio data from a CPAL device.
+/// It acts as a source Element in the audio pipeline.
+///
+/// The cpal callback pushes captured samples into a ring buffer, from which `process` fills
+/// payloads of `frames_per_process` frames. Frames that do not fit into the ring buffer are
+/// dropped whole and counted as overruns, so the channel order is kept. After `stop`,
+/// `process` drains the ring buffer and ends the stream with a payload marked
+/// `Position::Last`.
+pub struct CpalInputStream<'e, T, const SIZE: usize, B = DeviceBackend>
+where
+    T: SizedSample + ToBytes<SIZE> + Send + Sync + 'static,
+    B: InputBackend<T>,
+{
+    backend: B,
+    cpal_config: cpal::StreamConfig,
+    rb_consumer: Option<AsyncHeapCons<T>>,
+    shared: Arc<Shared>,
+    reported_overruns: u32,
+    frames_read: u64,
+    events: Option<EventSender<'e>>,
+    info: Option<Info>,
+    is_first_chunk: bool,
+    state: StreamState,
+    config: Config,
+}
+
+impl<'e, T, const SIZE: usize> CpalInputStream<'e, T, SIZE, DeviceBackend>
+where
+    T: SizedSample + ToBytes<SIZE> + Send + Sync + 'static,
+{
+    pub fn new(config: Config, cpal_device: cpal::Device, cpal_config: cpal::StreamConfig) -> Self {
+        Self::with_backend(config, DeviceBackend::new(cpal_device), cpal_config)
+    }
+}
+
+impl<'e, T, const SIZE: usize, B> CpalInputStream<'e, T, SIZE, B>
+where
+    T: SizedSample + ToBytes<SIZE> + Send + Sync + 'static,
+    B: InputBackend<T>,
+{
+    /// Creates an input stream capturing through `backend`.
+    pub fn with_backend(config: Config, backend: B, cpal_config: cpal::StreamConfig) -> Self {
+        Self {
+            backend,
+            cpal_config,
+            rb_consumer: None,
+            shared: Arc::new(Shared::default()),
+            reported_overruns: 0,
+            frames_read: 0,
+            events: None,
+            info: None,
+            is_first_chunk: true,
+            state: StreamState::Uninitialized,
+            config,
+        }
+    }
+
+    /// Posts state changes, overruns and progress to `events`.
+    pub fn with_events(mut self, events: EventSender<'e>) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    /// Returns the overrun counters.
+    pub fn stats(&self) -> InputStats {
+        InputStats {
+            overruns: self.shared.overruns.load(Ordering::Relaxed),
+            dropped_frames: self.shared.dropped_frames.load(Ordering::Relaxed),
+        }
+    }
+
+    fn set_state(&mut self, state: StreamState) {
+        if self.state == state {
+            return;
+        }
+        self.state = state;
+        if let Some(events) = &self.events {
+            events.post(EventKind::State(state));
+        }
+    }
+}
+
+impl<T, const SIZE: usize, B> BaseElement for CpalInputStream<'_, T, SIZE, B>
+where
+    T: SizedSample + ToBytes<SIZE> + Send + Sync + 'static,
+    B: InputBackend<T>,
+{
+    type Error = Error;
+    type Info = Info;
+
+    fn get_in_info(&self) -> Option<Info> {
+        None // This is a source element.
+    }
+
+    fn get_out_info(&self) -> Option<Info> {
+        self.info
+    }
+
+    fn available(&self) -> u32 {
+        if let Some(consumer) = &self.rb_consumer {
+            (consumer.occupied_len() * SIZE) as u32
+        } else {
+            0
+        }
+    }
+
+    async fn initialize(
+        &mut self,
+        _upstream_info: Option<Self::Info>,
+    ) -> Result<PortRequirements, Self::Error> {
+        if self.state != StreamState::Uninitialized {
+            return Err(Error::InvalidState);
+        }
+        if SIZE != std::mem::size_of::<T>() || self.cpal_config.channels == 0 || self.cpal_config.channels > u8::MAX as u16 {
+            return Err(Error::InvalidParameter);
+        }
+
+        let mut info = Info::new(self.cpal_config.sample_rate.0, self.cpal_config.channels as u8, SIZE as u8 * 8, None);
+        info.set_encoding(sample_encoding::<T>());
+        if !info.vaild() {
+            return Err(Error::InvalidParameter);
+        }
+        let bytes_per_frame = info.get_alignment_bytes() as u16;
+        let payload_bytes = u16::try_from(self.config.frames_per_process)
+            .ok()
+            .and_then(|frames| bytes_per_frame.checked_mul(frames))
+            .ok_or(Error::InvalidParameter)?;
+        self.info = Some(info);
+
+        // --- Ring Buffer Initialization ---
+        // The ring buffer holds whole frames only.
+        let channels = info.channels as usize;
+        let rb_capacity_bytes = self.config.get_rb_capacity_bytes(&info).map_err(|_| Error::InvalidParameter)?;
+        let rb_capacity = rb_capacity_bytes / SIZE / channels * channels;
+        if rb_capacity == 0 {
+            return Err(Error::InvalidParameter);
+        }
+        let ring_buffer = AsyncHeapRb::<T>::new(rb_capacity);
+        let (mut producer, consumer) = ring_buffer.split();
+        self.rb_consumer = Some(consumer);
+
+        // --- CPAL Stream Initialization ---
+        let shared = Arc::clone(&self.shared);
+        let input_data_fn = move |data: &[T]| {
+            // A partial frame would shift the channels of every later payload.
+            let vacant = producer.vacant_len() / channels * channels;
+            let pushed = producer.push_slice(&data[..data.len().min(vacant)]);
+            let dropped_frames = (data.len() - pushed).div_ceil(channels);
+            if dropped_frames > 0 {
+                shared.overruns.fetch_add(1, Ordering::Relaxed);
+                shared.dropped_frames.fetch_add(dropped_frames as u64, Ordering::Relaxed);
+            }
+        };
+        self.backend.build(&self.cpal_config, Box::new(input_data_fn))?;
+        self.set_state(StreamState::Initialized);
+
+        Ok(PortRequirements::source(PayloadSize {
+            min: bytes_per_frame,
+            preferred: payload_bytes,
+        }))
+    }
+
+    async fn process<'a, C, P, TF>(
+        &mut self,
+        _in_port: &mut InPort<'a, C>,
+        out_port: &mut OutPort<'a, P>,
+        _inplace_port: &mut InPlacePort<'a, TF>,
+    ) -> ProcessResult<Self::Error>
+    where
+        C: DatabusConsumer<'a>,
+        P: DatabusProducer<'a>,
+        TF: DatabusTransformer<'a>,
+    {
+        let draining = match self.state {
+            StreamState::Running => false,
+            // The captured samples are still delivered after a stop.
+            StreamState::Stopped if !self.is_first_chunk || self.available() > 0 => true,
+            _ => return Err(Error::InvalidState),
+        };
+        let info = self.info.ok_or(Error::NotInitialized)?;
+        let consumer = self.rb_consumer.as_mut().ok_or(Error::NotInitialized)?;
+
+        let OutPort::Producer(databus) = out_port else {
+            return Err(Error::Unsupported);
+        };
+        let mut payload = databus.acquire_write().await;
+        let bytes_per_frame = info.get_alignment_bytes() as usize;
+        let capacity = payload.len() / bytes_per_frame * bytes_per_frame;
+
+        let mut written = 0;
+        while written < capacity {
+            let sample = if draining {
+                match consumer.try_pop() {
+                    Some(sample) => sample,
+                    None => break,
+                }
+            } else {
+                // `None` means the audio thread dropped its end of the ring buffer.
+                consumer.pop().await.ok_or(Error::DeviceError)?
+            };
+            payload[written..written + SIZE].copy_from_slice(&sample.to_le_bytes());
+            written += SIZE;
+        }
+        // A stop may cut a frame short.
+        written -= written % bytes_per_frame;
+        payload.set_valid_length(written);
+
+        let last = draining && consumer.is_empty();
+        let position = match (self.is_first_chunk, last) {
+            (true, true) => Position::Single,
+            (true, false) => Position::First,
+            (false, true) => Position::Last,
+            (false, false) => Position::Middle,
+        };
+        payload.set_position(position);
+        self.is_first_chunk = last;
+
+        self.frames_read += (written / bytes_per_frame) as u64;
+        if let Some(events) = &self.events {
+            let overruns = self.shared.overruns.load(Ordering::Relaxed);
+            if overruns != self.reported_overruns {
+                self.reported_overruns = overruns;
+                events.post(EventKind::Overrun);
+            }
+            events.progress(self.frames_read);
+        }
+
+        if last { Ok(Eof) } else { Ok(Fine) }
+    }
+}
+
+impl<T, const SIZE: usize, B> BaseStream for CpalInputStream<'_, T, SIZE, B>
+where
+    T: SizedSample + ToBytes<SIZE> + Send + Sync + 'static,
+    B: InputBackend<T>,
+{
+    /// Starts capturing. A stopped stream starts a new stream of payloads; samples captured
+    /// before the stop that were not read yet are discarded.
+    fn start(&mut self) -> Result<(), Self::Error> {
+        if self.state != StreamState::Initialized && self.state != StreamState::Stopped {
+            return Err(Error::InvalidState);
+        }
+        if let Some(consumer) = self.rb_consumer.as_mut() {
+            consumer.clear();
+        }
+        self.backend.play()?;
+        self.is_first_chunk = true;
+        self.set_state(StreamState::Running);
+        Ok(())
+    }
+
+    fn stop(&mut self) -> Result<(), Self::Error> {
+        self.backend.pause()?;
+        self.set_state(StreamState::Stopped);
+        Ok(())
+    }
+
+    fn pause(&mut self) -> Result<(), Self::Error> {
+        if self.state != StreamState::Running {
+            return Err(Error::InvalidState);
+        }
+        self.backend.pause()?;
+        self.set_state(StreamState::Paused);
+        Ok(())
+    }
+
+    fn resume(&mut self) -> Result<(), Self::Error> {
+        if self.state != StreamState::Paused {
+            return Err(Error::InvalidState);
+        }
+        self.backend.play()?;
+        self.set_state(StreamState::Running);
+        Ok(())
+    }
+
+    fn get_state(&self) -> StreamState {
+        self.state
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    use crate::databus::slot::HeapSlot;
+    use embedded_audio_driver::databus::{Consumer, Databus, Operation};
+
+    /// A backend whose callback is driven by the test.
+    #[derive(Clone, Default)]
+    struct MockBackend {
+        callback: Arc<Mutex<Option<InputCallback<i16>>>>,
+    }
+
+    impl MockBackend {
+        fn capture(&self, data: &[i16]) {
+            (self.callback.lock().unwrap().as_mut().unwrap())(data);
+        }
+    }
+
+    impl InputBackend<i16> for MockBackend {
+        fn build(&mut self, _config: &cpal::StreamConfig, callback: InputCallback<i16>) -> Result<(), Error> {
+            *self.callback.lock().unwrap() = Some(callback);
+            Ok(())
+        }
+
+        fn play(&mut self) -> Result<(), Error> {
+            Ok(())
+        }
+
+        fn pause(&mut self) -> Result<(), Error> {
+            Ok(())
+        }
+    }
+
+    fn stream(backend: MockBackend) -> CpalInputStream<'static, i16, 2, MockBackend> {
+        stream_with_config(backend, Config { rb_capacity: None, latency_ms: 10, frames_per_process: 4 })
+    }
+
+    fn stream_with_config(backend: MockBackend, config: Config) -> CpalInputStream<'static, i16, 2, MockBackend> {
+        let cpal_config = cpal::StreamConfig {
+            channels: 2,
+            sample_rate: cpal::SampleRate(8000),
+            buffer_size: cpal::BufferSize::Default,
+        };
+        CpalInputStream::with_backend(config, backend, cpal_config)
+    }
+
+    async fn read(
+        input: &mut CpalInputStream<'static, i16, 2, MockBackend>,
+        slot: &HeapSlot,
+    ) -> (Vec<i16>, Position, ProcessResult<Error>) {
+        let result = input.process(&mut InPort::new_none(), &mut slot.out_port(), &mut InPlacePort::new_none()).await;
+        let payload = slot.acquire_read().await;
+        let samples = payload.chunks_exact(2).map(|c| i16::from_le_bytes([c[0], c[1]])).collect();
+        (samples, payload.metadata.position, result)
+    }
+
+    #[tokio::test]
+    async fn test_capture_and_drain() {
+        let backend = MockBackend::default();
+        let mut input = stream(backend.clone());
+        let requirements = input.initialize(None).await.unwrap();
+        let info = input.get_out_info().unwrap();
+        assert_eq!((info.sample_rate, info.channels, info.bits_per_sample), (8000, 2, 16));
+
+        let mut slot = HeapSlot::new_heap(16);
+        slot.register(Operation::Produce, requirements.out.unwrap());
+        slot.register(Operation::Consume, requirements.out.unwrap());
+        assert!(matches!(
+            input.process(&mut InPort::new_none(), &mut slot.out_port(), &mut InPlacePort::new_none()).await,
+            Err(Error::InvalidState)
+        ));
+
+        input.start().unwrap();
+        backend.capture(&(0..12).collect::<Vec<i16>>());
+        assert_eq!(read(&mut input, &slot).await, ((0..8).collect(), Position::First, Ok(Fine)));
+
+        // The remaining frames are delivered after a stop, ending the stream.
+        input.stop().unwrap();
+        assert_eq!(read(&mut input, &slot).await, ((8..12).collect(), Position::Last, Ok(Eof)));
+    }
+
+    #[tokio::test]
+    async fn test_overruns() {
+        let backend = MockBackend::default();
+        let mut input = stream(backend.clone());
+        let requirements = input.initialize(None).await.unwrap();
+        let mut slot = HeapSlot::new_heap(16);
+        slot.register(Operation::Produce, requirements.out.unwrap());
+        slot.register(Operation::Consume, requirements.out.unwrap());
+        input.start().unwrap();
+
+        // 10 ms of stereo 8 kHz audio, doubled: 320 samples fit into the ring buffer.
+        backend.capture(&[1; 300]);
+        backend.capture(&[1; 30]);
+        assert_eq!(input.stats(), InputStats { overruns: 1, dropped_frames: 5 });
+        assert_eq!(input.available(), 640);
+
+        // Nothing captured before a restart is delivered after it.
+        input.stop().unwrap();
+        input.start().unwrap();
+        backend.capture(&[2; 8]);
+        assert_eq!(read(&mut input, &slot).await, (vec![2; 8], Position::First, Ok(Fine)));
+    }
+
+    #[tokio::test]
+    async fn test_overrun_keeps_channel_order() {
+        // 11 samples of capacity hold 5 stereo frames.
+        let backend = MockBackend::default();
+        let config = Config { rb_capacity: Some(22), latency_ms: 0, frames_per_process: 4 };
+        let mut input = stream_with_config(backend.clone(), config);
+        let requirements = input.initialize(None).await.unwrap();
+        let mut slot = HeapSlot::new_heap(16);
+        slot.register(Operation::Produce, requirements.out.unwrap());
+        slot.register(Operation::Consume, requirements.out.unwrap());
+        input.start().unwrap();
+
+        // Left samples are even, right samples odd.
+        backend.capture(&(0..8).collect::<Vec<i16>>());
+        backend.capture(&(8..14).collect::<Vec<i16>>());
+        assert_eq!(input.stats(), InputStats { overruns: 1, dropped_frames: 2 });
+        assert_eq!(read(&mut input, &slot).await, ((0..8).collect(), Position::First, Ok(Fine)));
+
+        backend.capture(&(20..26).collect::<Vec<i16>>());
+        assert_eq!(input.stats(), InputStats { overruns: 1, dropped_frames: 2 });
+        input.stop().unwrap();
+        assert_eq!(read(&mut input, &slot).await, (vec![8, 9, 20, 21, 22, 23, 24, 25], Position::Last, Ok(Eof)));
+    }
+
+    #[tokio::test]
+    async fn test_payload_size_overflow() {
+        // 20000 stereo 16-bit frames do not fit into a payload size.
+        let backend = MockBackend::default();
+        let config = Config { rb_capacity: None, latency_ms: 10, frames_per_process: 20000 };
+        let mut input = stream_with_config(backend.clone(), config);
+        assert!(matches!(input.initialize(None).await, Err(Error::InvalidParameter)));
+        assert!(backend.callback.lock().unwrap().is_none());
+    }
+}
@@ -1,19 +1,21 @@
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 
 use async_ringbuf::traits::{AsyncProducer, Consumer, Observer, Producer, Split};
 use async_ringbuf::{AsyncHeapRb, AsyncHeapProd, AsyncHeapCons};
 use cpal::traits::{DeviceTrait, StreamTrait};
-use cpal::SizedSample;
+use cpal::{FromSample, SampleFormat, SampleRate, SizedSample, SupportedStreamConfig, SupportedStreamConfigRange};
 
 use embedded_audio_driver::databus::{Consumer as DatabusConsumer, Producer as DatabusProducer, Transformer as DatabusTransformer};
 use embedded_audio_driver::element::{BaseElement, ProcessResult, Eof, Fine};
-use embedded_audio_driver::info::Info;
+use embedded_audio_driver::info::{Info, SampleEncoding};
 use embedded_audio_driver::payload::Position;
 use embedded_audio_driver::port::{InPlacePort, InPort, OutPort, PayloadSize, PortRequirements};
 use embedded_audio_driver::stream::{BaseStream, StreamState};
 use embedded_audio_driver::Error;
-use crate::utils::FromBytes;
-use crate::Channel;
+use crate::event::{EventKind, EventSender};
+use crate::sample::SampleCodec;
 
 #[derive(Debug)]
 pub struct Config {
@@ -48,7 +50,7 @@ impl std::error::Error for CapacityTooSmallError {}
 
 impl Config {
     /// Calculates the minimum required capacity for the ring buffer based on latency.
-    fn get_rb_min_capacity_bytes(&self, info: &Info) -> usize {
+    pub(super) fn get_rb_min_capacity_bytes(&self, info: &Info) -> usize {
         self.latency_ms
             * info.sample_rate as usize
             / 1000
@@ -56,7 +58,7 @@ impl Config {
     }
 
     /// Determines the final ring buffer capacity, ensuring it's sufficient.
-    fn get_rb_capacity_bytes(
+    pub(super) fn get_rb_capacity_bytes(
         &self,
         info: &Info,
     ) -> Result<usize, CapacityTooSmallError> {
@@ -77,47 +79,375 @@ impl Config {
     }
 }
 
+/// Why no device configuration can play an upstream format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigError {
+    /// The upstream samples cannot be decoded.
+    UnsupportedFormat,
+    /// The device supports no configuration with this many channels.
+    Channels(u8),
+    /// The device supports the channel count, but not at this sample rate.
+    SampleRate(u32),
+    /// The supported configurations could not be queried.
+    Device,
+}
+
+impl core::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::UnsupportedFormat => write!(f, "Upstream sample format cannot be decoded"),
+            Self::Channels(channels) => write!(f, "Device does not support {} channels", channels),
+            Self::SampleRate(rate) => write!(f, "Device does not support a sample rate of {} Hz", rate),
+            Self::Device => write!(f, "Device configurations could not be queried"),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl From<ConfigError> for Error {
+    fn from(error: ConfigError) -> Self {
+        match error {
+            ConfigError::Device => Error::DeviceError,
+            _ => Error::Unsupported,
+        }
+    }
+}
+
+/// Ranks how well the device sample format `format` plays samples described by `info`,
+/// higher is better. Returns `None` for formats the stream cannot produce.
+fn format_rank(format: SampleFormat, info: &Info) -> Option<u32> {
+    let (encoding, bits) = match format {
+        SampleFormat::I8 => (SampleEncoding::Signed, 8),
+        SampleFormat::I16 => (SampleEncoding::Signed, 16),
+        SampleFormat::I32 => (SampleEncoding::Signed, 32),
+        SampleFormat::I64 => (SampleEncoding::Signed, 64),
+        SampleFormat::U8 => (SampleEncoding::Unsigned, 8),
+        SampleFormat::U16 => (SampleEncoding::Unsigned, 16),
+        SampleFormat::U32 => (SampleEncoding::Unsigned, 32),
+        SampleFormat::U64 => (SampleEncoding::Unsigned, 64),
+        SampleFormat::F32 => (SampleEncoding::Float, 32),
+        SampleFormat::F64 => (SampleEncoding::Float, 64),
+        _ => return None,
+    };
+    if encoding == info.encoding && bits == info.bits_per_sample as u32 {
+        return Some(u32::MAX);
+    }
+    // Otherwise prefer float, then the widest integers.
+    Some(match encoding {
+        SampleEncoding::Float => 200 + bits,
+        SampleEncoding::Signed => 100 + bits,
+        SampleEncoding::Unsigned => bits,
+    })
+}
+
+/// Chooses the device configuration that plays `info` best: the channel count and sample
+/// rate must match, the sample format is the same as upstream if possible.
+pub fn choose_config(
+    configs: impl Iterator<Item = SupportedStreamConfigRange>,
+    info: &Info,
+) -> Result<SupportedStreamConfig, ConfigError> {
+    if SampleCodec::from_info(info).is_none() {
+        return Err(ConfigError::UnsupportedFormat);
+    }
+    let rate = SampleRate(info.sample_rate);
+    let mut channels_found = false;
+    let mut best: Option<(u32, SupportedStreamConfigRange)> = None;
+    for range in configs.filter(|range| range.channels() == info.channels as u16) {
+        channels_found = true;
+        if rate < range.min_sample_rate() || rate > range.max_sample_rate() {
+            continue;
+        }
+        let Some(rank) = format_rank(range.sample_format(), info) else {
+            continue;
+        };
+        if !matches!(best, Some((best_rank, _)) if best_rank >= rank) {
+            best = Some((rank, range));
+        }
+    }
+    match best {
+        Some((_, range)) => Ok(range.with_sample_rate(rate)),
+        None if channels_found => Err(ConfigError::SampleRate(info.sample_rate)),
+        None => Err(ConfigError::Channels(info.channels)),
+    }
+}
+
+/// Builds an output stream of the device sample type `T`.
+fn build_stream<T>(device: &cpal::Device, config: &cpal::StreamConfig, mut callback: OutputCallback) -> Result<cpal::Stream, Error>
+where
+    T: SizedSample + FromSample<i32>,
+{
+    let err_fn = |err| warn!("CpalOutputStream: stream error: {}", err);
+    let output_data_fn = move |data: &mut [T], info: &cpal::OutputCallbackInfo| {
+        let timestamp = info.timestamp();
+        callback.fill(data, timestamp.playback.duration_since(&timestamp.callback));
+    };
+    device
+        .build_output_stream(config, output_data_fn, err_fn, None)
+        .map_err(|_| Error::DeviceError)
+}
+
+/// Waits until the ring buffer behind `producer` is empty.
+async fn wait_drained(producer: &mut AsyncHeapProd<i32>) {
+    let capacity = producer.vacant_len() + producer.occupied_len();
+    producer.wait_vacant(capacity).await;
+}
+
+/// Returns the encoding of the cpal sample type `T`.
+pub(super) fn sample_encoding<T: SizedSample>() -> SampleEncoding {
+    if T::FORMAT.is_float() {
+        SampleEncoding::Float
+    } else if T::FORMAT.is_uint() {
+        SampleEncoding::Unsigned
+    } else {
+        SampleEncoding::Signed
+    }
+}
+
+/// State shared with the audio thread.
+///
+/// The audio thread only updates these atomics, so the callback never locks, allocates or
+/// performs I/O.
+#[derive(Default)]
+struct Shared {
+    /// The number of samples pushed into the ring buffer before the last flush. The audio
+    /// thread discards everything up to there at the start of its next callback.
+    flushed_samples: AtomicU64,
+    /// The number of callbacks that ran out of data.
+    underruns: AtomicU32,
+    /// The number of silent samples played because the ring buffer was empty.
+    silence_samples: AtomicU64,
+    /// The number of flushes carried out by the audio thread.
+    flushes: AtomicU32,
+    /// The number of upstream samples handed to the device, excluding the initial silence.
+    samples_played: AtomicU64,
+    /// The delay between the last callback and the playback of its first sample.
+    device_latency_us: AtomicU64,
+}
+
+/// Counters of a [`CpalOutputStream`] since it was initialized.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct OutputStats {
+    /// The number of callbacks that ran out of data.
+    pub underruns: u32,
+    /// The number of silent samples played to cover underruns.
+    pub silence_samples: u64,
+    /// The number of flushes that discarded the ring buffer.
+    pub flushes: u32,
+}
+
+/// The part of the stream that runs on the audio thread.
+///
+/// The ring buffer holds MSB-aligned `i32` samples, which are converted to the device
+/// sample type while playing.
+struct OutputCallback {
+    consumer: AsyncHeapCons<i32>,
+    shared: Arc<Shared>,
+    /// The initial silence still queued, which is not counted as played.
+    silence_remaining: usize,
+    /// The number of samples taken from the ring buffer so far.
+    samples_taken: u64,
+}
+
+impl OutputCallback {
+    /// Fills `data` from the ring buffer, playing silence after a flush or once it runs dry.
+    fn fill<T: SizedSample + FromSample<i32>>(&mut self, data: &mut [T], device_latency: Option<Duration>) {
+        if let Some(latency) = device_latency {
+            self.shared.device_latency_us.store(latency.as_micros() as u64, Ordering::Relaxed);
+        }
+
+        let flushed = self.shared.flushed_samples.load(Ordering::Acquire);
+        if flushed > self.samples_taken {
+            // Only the samples pushed before the flush are discarded.
+            let discarded = self.consumer.skip((flushed - self.samples_taken) as usize);
+            self.samples_taken += discarded as u64;
+            self.silence_remaining = self.silence_remaining.saturating_sub(discarded);
+            self.shared.flushes.fetch_add(1, Ordering::Relaxed);
+            data.fill(T::EQUILIBRIUM);
+            return;
+        }
+
+        let mut played = 0;
+        let mut silence = 0;
+
+        for sample in data.iter_mut() {
+            *sample = match self.consumer.try_pop() {
+                Some(s) => {
+                    self.samples_taken += 1;
+                    if self.silence_remaining > 0 {
+                        self.silence_remaining -= 1;
+                    } else {
+                        played += 1;
+                    }
+                    T::from_sample(s)
+                }
+                None => {
+                    silence += 1;
+                    T::EQUILIBRIUM
+                }
+            };
+        }
+        self.shared.samples_played.fetch_add(played, Ordering::Relaxed);
+
+        if silence > 0 {
+            // The input stream fell behind.
+            self.shared.underruns.fetch_add(1, Ordering::Relaxed);
+            self.shared.silence_samples.fetch_add(silence, Ordering::Relaxed);
+        }
+    }
+}
+
+/// A snapshot of the playback progress of a [`CpalOutputStream`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Playback {
+    /// Frames of upstream audio handed to the device so far.
+    pub frames_played: u64,
+    /// Frames queued in the ring buffer, including any initial silence not yet played.
+    pub buffered_frames: u64,
+    /// The delay between a callback and the playback of its first sample, as reported by
+    /// the device. Zero if the device reports no timestamps.
+    pub device_latency: Duration,
+    pub sample_rate: u32,
+}
+
+impl Playback {
+    /// The estimated time until a frame written now becomes audible.
+    pub fn latency(&self) -> Duration {
+        self.frames_to_duration(self.buffered_frames) + self.device_latency
+    }
+
+    /// The estimated number of frames that have become audible so far.
+    pub fn frames_audible(&self) -> u64 {
+        let in_device = self.device_latency.as_micros() as u64 * self.sample_rate as u64 / 1_000_000;
+        self.frames_played.saturating_sub(in_device)
+    }
+
+    fn frames_to_duration(&self, frames: u64) -> Duration {
+        if self.sample_rate == 0 {
+            return Duration::ZERO;
+        }
+        Duration::from_micros(frames * 1_000_000 / self.sample_rate as u64)
+    }
+}
+
 /// An output stream that sends audio data to a CPAL device.
 /// It acts as a sink Element in the audio pipeline.
-pub struct CpalOutputStream<T: SizedSample + FromBytes<SIZE> + Send + Sync + 'static, const SIZE: usize> {
+///
+/// `initialize` picks a device configuration with the channel count and sample rate of the
+/// upstream `Info`, preferring the upstream sample format (see [`choose_config`]). Samples
+/// are converted to the device sample format on the audio thread.
+///
+/// When `process` receives the final payload of the stream, it waits until the audio thread
+/// has played out the ring buffer before returning `Eof`, so stopping the stream afterwards
+/// does not cut off the tail. [`drain`](Self::drain) waits the same way on demand, while
+/// `flush` discards the queued samples.
+///
+/// Underruns, the silence played to cover them and flushes are counted by the audio thread
+/// and read through [`stats`](Self::stats). With an [`EventSender`] attached, the stream
+/// also posts its state changes and underruns, and reports the number of frames it has
+/// queued as progress.
+pub struct CpalOutputStream<'e> {
     cpal_device: cpal::Device,
-    cpal_config: cpal::StreamConfig,
+    cpal_config: Option<SupportedStreamConfig>,
     stream: Option<cpal::Stream>,
-    rb_producer: Option<AsyncHeapProd<T>>,
-    rb_consumer: Option<AsyncHeapCons<T>>,
-    flush_channel: Arc<Channel<bool, 1>>,
+    rb_producer: Option<AsyncHeapProd<i32>>,
+    codec: Option<SampleCodec>,
+    shared: Arc<Shared>,
+    /// The number of samples pushed into the ring buffer, including the initial silence.
+    samples_pushed: u64,
+    reported_underruns: u32,
+    frames_written: u64,
+    events: Option<EventSender<'e>>,
     info: Option<Info>,
     state: StreamState,
     config: Config,
-    _phantom: core::marker::PhantomData<T>,
 }
 
-impl<T: SizedSample + FromBytes<SIZE> + Send + Sync + 'static, const SIZE: usize>
-    CpalOutputStream<T, SIZE>
-{
-    pub fn new(
-        config: Config,
-        cpal_device: cpal::Device,
-        cpal_config: cpal::StreamConfig,
-    ) -> Self {
+impl<'e> CpalOutputStream<'e> {
+    pub fn new(config: Config, cpal_device: cpal::Device) -> Self {
         CpalOutputStream {
             cpal_device,
-            cpal_config,
+            cpal_config: None,
             stream: None,
             rb_producer: None,
-            rb_consumer: None,
-            flush_channel: Arc::new(Channel::new()),
+            codec: None,
+            shared: Arc::new(Shared::default()),
+            samples_pushed: 0,
+            reported_underruns: 0,
+            frames_written: 0,
+            events: None,
             info: None,
             state: StreamState::Uninitialized,
             config,
-            _phantom: core::marker::PhantomData,
+        }
+    }
+
+    /// Returns the device configuration chosen by `initialize`.
+    pub fn device_config(&self) -> Option<&SupportedStreamConfig> {
+        self.cpal_config.as_ref()
+    }
+
+    /// Chooses the device configuration for playing `info`, see [`choose_config`].
+    pub fn supported_config(&self, info: &Info) -> Result<SupportedStreamConfig, ConfigError> {
+        let configs = self.cpal_device.supported_output_configs().map_err(|_| ConfigError::Device)?;
+        choose_config(configs, info)
+    }
+
+    /// Posts state changes, underruns and progress to `events`.
+    pub fn with_events(mut self, events: EventSender<'e>) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    /// Returns how much audio has been played and how much is still queued.
+    pub fn playback(&self) -> Playback {
+        let (channels, sample_rate) = self.info.map_or((1, 0), |info| (info.channels.max(1) as u64, info.sample_rate));
+        let buffered_samples = self.rb_producer.as_ref().map_or(0, |producer| producer.occupied_len());
+        Playback {
+            frames_played: self.shared.samples_played.load(Ordering::Relaxed) / channels,
+            buffered_frames: buffered_samples as u64 / channels,
+            device_latency: Duration::from_micros(self.shared.device_latency_us.load(Ordering::Relaxed)),
+            sample_rate,
+        }
+    }
+
+    /// Waits until the audio thread has taken every queued sample from the ring buffer.
+    ///
+    /// Unlike `flush`, which discards the queued samples, this lets them play out. Returns
+    /// `Error::InvalidState` if samples are queued but the stream is not running.
+    pub async fn drain(&mut self) -> Result<(), Error> {
+        let producer = self.rb_producer.as_mut().ok_or(Error::NotInitialized)?;
+        if producer.is_empty() {
+            return Ok(());
+        }
+        if self.state != StreamState::Running {
+            return Err(Error::InvalidState);
+        }
+        wait_drained(producer).await;
+        Ok(())
+    }
+
+    /// Returns the underrun and flush counters.
+    pub fn stats(&self) -> OutputStats {
+        OutputStats {
+            underruns: self.shared.underruns.load(Ordering::Relaxed),
+            silence_samples: self.shared.silence_samples.load(Ordering::Relaxed),
+            flushes: self.shared.flushes.load(Ordering::Relaxed),
+        }
+    }
+
+    fn set_state(&mut self, state: StreamState) {
+        if self.state == state {
+            return;
+        }
+        self.state = state;
+        if let Some(events) = &self.events {
+            events.post(EventKind::State(state));
         }
     }
 }
 
-impl<T: SizedSample + FromBytes<SIZE> + Send + Sync + 'static, const SIZE: usize> BaseElement
-    for CpalOutputStream<T, SIZE>
-{
+impl BaseElement for CpalOutputStream<'_> {
     type Error = Error;
     type Info = Info;
 
@@ -130,15 +460,17 @@ impl<T: SizedSample + FromBytes<SIZE> + Send + Sync + 'static, const SIZE: usize
     }
 
     fn available(&self) -> u32 {
-        if let Some(producer) = &self.rb_producer {
-            (producer.vacant_len() * std::mem::size_of::<T>()) as u32
+        if let (Some(producer), Some(codec)) = (&self.rb_producer, &self.codec) {
+            (producer.vacant_len() * codec.bytes_per_sample()) as u32
         } else {
             0
         }
     }
 
+    /// Discards the queued samples. The audio thread drops the samples pushed before the flush
+    /// at the start of its next callback, so samples pushed afterwards are still played.
     async fn flush(&mut self) -> Result<(), Self::Error> {
-        self.flush_channel.send(true).await;
+        self.shared.flushed_samples.store(self.samples_pushed, Ordering::Release);
         Ok(())
     }
 
@@ -151,73 +483,68 @@ impl<T: SizedSample + FromBytes<SIZE> + Send + Sync + 'static, const SIZE: usize
         }
 
         let info = upstream_info.ok_or(Error::InvalidParameter)?;
+        if !info.vaild() {
+            return Err(Error::InvalidParameter);
+        }
+        let bytes_per_frame = info.get_alignment_bytes() as u16;
+        let payload_bytes = u16::try_from(self.config.frames_per_process)
+            .ok()
+            .and_then(|frames| bytes_per_frame.checked_mul(frames))
+            .ok_or(Error::InvalidParameter)?;
+        let cpal_config = self.supported_config(&info).map_err(|error| {
+            warn!("CpalOutputStream: {}", error);
+            Error::from(error)
+        })?;
+        // `choose_config` only accepts formats the codec can read.
+        let codec = SampleCodec::from_info(&info).ok_or(Error::Unsupported)?;
         self.info = Some(info);
+        self.codec = Some(codec);
 
         // --- Ring Buffer Initialization ---
         let rb_capacity_bytes = self.config.get_rb_capacity_bytes(&info).map_err(|_| Error::InvalidParameter)?;
-        let rb_capacity_samples = rb_capacity_bytes / std::mem::size_of::<T>();
-        let ring_buffer = AsyncHeapRb::<T>::new(rb_capacity_samples);
+        let ring_buffer = AsyncHeapRb::<i32>::new(rb_capacity_bytes / codec.bytes_per_sample());
         let (mut producer, consumer) = ring_buffer.split();
         
         // Pre-fill the buffer with silence to satisfy the initial latency requirement
-        let min_samples_to_fill = self.config.get_rb_min_capacity_bytes(&info) / std::mem::size_of::<T>();
+        let min_samples_to_fill = self.config.get_rb_min_capacity_bytes(&info) / codec.bytes_per_sample();
         for _ in 0..min_samples_to_fill {
             producer
-                .try_push(T::EQUILIBRIUM)
+                .try_push(0)
                 .map_err(|_| Error::BufferFull)?; // Should not fail on a new buffer
         }
+        self.samples_pushed = min_samples_to_fill as u64;
         self.rb_producer = Some(producer);
-        self.rb_consumer = Some(consumer);
-
 
         // --- CPAL Stream Initialization ---
-        let mut consumer = self.rb_consumer.take().expect("Consumer is only taken once during init");
-        let flush_receiver = Arc::clone(&self.flush_channel);
-        let err_fn = |err| eprintln!("[cpal_output] stream error: {}", err);
-
-        let output_data_fn = move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
-            let mut flushed_this_block = false;
-            let mut input_fell_behind = false;
-
-            for sample in data.iter_mut() {
-                if flushed_this_block {
-                    *sample = T::EQUILIBRIUM;
-                    continue;
-                }
-
-                if flush_receiver.try_receive().is_ok() {
-                    consumer.clear();
-                    flushed_this_block = true;
-                    *sample = T::EQUILIBRIUM;
-                    continue;
-                }
-
-                *sample = match consumer.try_pop() {
-                    Some(s) => s,
-                    None => {
-                        input_fell_behind = true;
-                        T::EQUILIBRIUM
-                    }
-                };
-            }
-
-            if input_fell_behind {
-                // Use a non-blocking logger or a more robust mechanism in real applications
-                eprintln!("[cpal_output] buffer underrun: input stream fell behind");
-            }
+        let callback = OutputCallback {
+            consumer,
+            shared: Arc::clone(&self.shared),
+            silence_remaining: min_samples_to_fill,
+            samples_taken: 0,
         };
-
-        let stream = self
-            .cpal_device
-            .build_output_stream(&self.cpal_config, output_data_fn, err_fn, None)
-            .map_err(|_| Error::DeviceError)?;
+        let device = &self.cpal_device;
+        let stream_config = cpal_config.config();
+        let stream = match cpal_config.sample_format() {
+            SampleFormat::I8 => build_stream::<i8>(device, &stream_config, callback),
+            SampleFormat::I16 => build_stream::<i16>(device, &stream_config, callback),
+            SampleFormat::I32 => build_stream::<i32>(device, &stream_config, callback),
+            SampleFormat::I64 => build_stream::<i64>(device, &stream_config, callback),
+            SampleFormat::U8 => build_stream::<u8>(device, &stream_config, callback),
+            SampleFormat::U16 => build_stream::<u16>(device, &stream_config, callback),
+            SampleFormat::U32 => build_stream::<u32>(device, &stream_config, callback),
+            SampleFormat::U64 => build_stream::<u64>(device, &stream_config, callback),
+            SampleFormat::F32 => build_stream::<f32>(device, &stream_config, callback),
+            SampleFormat::F64 => build_stream::<f64>(device, &stream_config, callback),
+            _ => Err(Error::Unsupported),
+        }?;
         
         self.stream = Some(stream);
-        self.state = StreamState::Initialized;
+        self.cpal_config = Some(cpal_config);
+        self.set_state(StreamState::Initialized);
 
         Ok(PortRequirements::sink(PayloadSize { 
-            min: SIZE as u16, 
-            preferred: SIZE as u16 * self.config.frames_per_process as u16,
+            min: bytes_per_frame, 
+            preferred: payload_bytes,
         }))
     }
 
@@ -237,29 +564,46 @@ impl<T: SizedSample + FromBytes<SIZE> + Send + Sync + 'static, const SIZE: usize
         }
 
         let producer = self.rb_producer.as_mut().ok_or(Error::NotInitialized)?;
+        let (Some(info), Some(codec)) = (self.info, self.codec) else {
+            return Err(Error::NotInitialized);
+        };
 
         if let InPort::Consumer(databus) = in_port {
             let payload = databus.acquire_read().await;
             
             let samples = payload
-                .chunks_exact(SIZE)
-                .map(|chunk| T::from_le_bytes(chunk.try_into().unwrap()));
+                .chunks_exact(codec.bytes_per_sample())
+                .map(|chunk| codec.read_i32(chunk));
             
             for sample in samples {
                 if producer.push(sample).await.is_err() {
                     // This error means the audio thread (consumer) has been dropped,
                     // which is a critical failure.
-                    self.state = StreamState::Stopped;
+                    self.set_state(StreamState::Stopped);
                     return Err(Error::DeviceError);
                 }
+                self.samples_pushed += 1;
             }
-            
-            match payload.metadata.position {
-                Position::Last | Position::Single => {
-                    self.state = StreamState::Stopped;
-                    Ok(Eof)
+
+            self.frames_written += (payload.len() / info.get_alignment_bytes() as usize) as u64;
+            if let Some(events) = &self.events {
+                let underruns = self.shared.underruns.load(Ordering::Relaxed);
+                if underruns != self.reported_underruns {
+                    self.reported_underruns = underruns;
+                    events.post(EventKind::Underrun);
                 }
-                _ => Ok(Fine),
+                events.progress(self.frames_written);
+            }
+            
+            let ended = matches!(payload.metadata.position, Position::Last | Position::Single);
+            drop(payload);
+            if ended {
+                // Report the end only once the final samples have been played.
+                self.drain().await?;
+                self.set_state(StreamState::Stopped);
+                Ok(Eof)
+            } else {
+                Ok(Fine)
             }
         } else {
             Err(Error::Unsupported)
@@ -267,16 +611,14 @@ impl<T: SizedSample + FromBytes<SIZE> + Send + Sync + 'static, const SIZE: usize
     }
 }
 
-impl<T: SizedSample + FromBytes<SIZE> + Send + Sync + 'static, const SIZE: usize> BaseStream
-    for CpalOutputStream<T, SIZE>
-{
+impl BaseStream for CpalOutputStream<'_> {
     fn start(&mut self) -> Result<(), Self::Error> {
         if self.state != StreamState::Initialized && self.state != StreamState::Stopped {
             return Err(Error::InvalidState);
         }
         if let Some(stream) = self.stream.as_ref() {
             stream.play().map_err(|_| Error::DeviceError)?;
-            self.state = StreamState::Running;
+            self.set_state(StreamState::Running);
             Ok(())
         } else {
             Err(Error::NotInitialized)
@@ -286,7 +628,7 @@ impl<T: SizedSample + FromBytes<SIZE> + Send + Sync + 'static, const SIZE: usize
     fn stop(&mut self) -> Result<(), Self::Error> {
         if let Some(stream) = self.stream.as_ref() {
             stream.pause().map_err(|_| Error::DeviceError)?;
-            self.state = StreamState::Stopped;
+            self.set_state(StreamState::Stopped);
             Ok(())
         } else {
             Err(Error::NotInitialized)
@@ -299,7 +641,7 @@ impl<T: SizedSample + FromBytes<SIZE> + Send + Sync + 'static, const SIZE: usize
         }
         if let Some(stream) = self.stream.as_ref() {
             stream.pause().map_err(|_| Error::DeviceError)?;
-            self.state = StreamState::Paused;
+            self.set_state(StreamState::Paused);
             Ok(())
         } else {
             Err(Error::NotInitialized)
@@ -312,7 +654,7 @@ impl<T: SizedSample + FromBytes<SIZE> + Send + Sync + 'static, const SIZE: usize
         }
         if let Some(stream) = self.stream.as_ref() {
             stream.play().map_err(|_| Error::DeviceError)?;
-            self.state = StreamState::Running;
+            self.set_state(StreamState::Running);
             Ok(())
         } else {
             Err(Error::NotInitialized)
@@ -323,3 +665,136 @@ impl<T: SizedSample + FromBytes<SIZE> + Send + Sync + 'static, const SIZE: usize
         self.state
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_callback_counts_underruns_and_flushes() {
+        let (mut producer, consumer) = AsyncHeapRb::<i32>::new(16).split();
+        let shared = Arc::new(Shared::default());
+        let mut callback = OutputCallback {
+            consumer,
+            shared: Arc::clone(&shared),
+            silence_remaining: 2,
+            samples_taken: 0,
+        };
+        for sample in [0, 0, 1, 2, 3, 4] {
+            producer.try_push(sample << 16).unwrap();
+        }
+
+        // Two samples of initial silence, then four played and two of silence.
+        let mut data = [9i16; 8];
+        callback.fill(&mut data, Some(Duration::from_millis(5)));
+        assert_eq!(data, [0, 0, 1, 2, 3, 4, 0, 0]);
+        assert_eq!(shared.samples_played.load(Ordering::Relaxed), 4);
+        assert_eq!(shared.device_latency_us.load(Ordering::Relaxed), 5000);
+
+        producer.try_push(5 << 16).unwrap();
+        shared.flushed_samples.store(7, Ordering::Release);
+        callback.fill(&mut data, None);
+        assert_eq!(data, [0; 8]);
+        assert_eq!(producer.occupied_len(), 0);
+
+        assert_eq!(shared.underruns.load(Ordering::Relaxed), 1);
+        assert_eq!(shared.silence_samples.load(Ordering::Relaxed), 2);
+        assert_eq!(shared.flushes.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn test_wait_drained() {
+        let (mut producer, consumer) = AsyncHeapRb::<i32>::new(16).split();
+        let mut callback = OutputCallback {
+            consumer,
+            shared: Arc::new(Shared::default()),
+            silence_remaining: 0,
+            samples_taken: 0,
+        };
+        for sample in 1..=6 {
+            producer.try_push(sample << 16).unwrap();
+        }
+
+        let mut data = [0i16; 4];
+        let play = async {
+            callback.fill(&mut data, None);
+            assert_eq!(data, [1, 2, 3, 4]);
+            tokio::task::yield_now().await;
+            callback.fill(&mut data, None);
+            assert_eq!(data, [5, 6, 0, 0]);
+        };
+        tokio::join!(wait_drained(&mut producer), play);
+        assert!(producer.is_empty());
+    }
+
+    #[test]
+    fn test_flush_keeps_samples_pushed_afterwards() {
+        let (mut producer, consumer) = AsyncHeapRb::<i32>::new(16).split();
+        let shared = Arc::new(Shared::default());
+        let mut callback = OutputCallback {
+            consumer,
+            shared: Arc::clone(&shared),
+            silence_remaining: 0,
+            samples_taken: 0,
+        };
+        for sample in 1..=4 {
+            producer.try_push(sample << 16).unwrap();
+        }
+
+        // Flush after the first four samples, then push the samples following a seek before
+        // the audio thread gets to run.
+        shared.flushed_samples.store(4, Ordering::Release);
+        for sample in 5..=7 {
+            producer.try_push(sample << 16).unwrap();
+        }
+        let mut data = [9i16; 4];
+        callback.fill(&mut data, None);
+        assert_eq!(data, [0; 4]);
+        callback.fill(&mut data, None);
+        assert_eq!(data, [5, 6, 7, 0]);
+        assert_eq!(shared.flushes.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn test_choose_config() {
+        use cpal::SupportedBufferSize;
+
+        let range = |channels, min, max, format| {
+            SupportedStreamConfigRange::new(channels, SampleRate(min), SampleRate(max), SupportedBufferSize::Unknown, format)
+        };
+        let configs = [
+            range(2, 8000, 48000, SampleFormat::I16),
+            range(2, 8000, 48000, SampleFormat::F32),
+            range(2, 44100, 44100, SampleFormat::I32),
+            range(1, 8000, 48000, SampleFormat::I16),
+        ];
+        let choose = |info: Info| choose_config(configs.iter().cloned(), &info);
+
+        // The upstream format if available, otherwise float.
+        let config = choose(Info::new(48000, 2, 16, None)).unwrap();
+        assert_eq!((config.channels(), config.sample_rate(), config.sample_format()), (2, SampleRate(48000), SampleFormat::I16));
+        assert_eq!(choose(Info::new(48000, 2, 24, None)).unwrap().sample_format(), SampleFormat::F32);
+        assert_eq!(choose(Info::new(44100, 2, 32, None)).unwrap().sample_format(), SampleFormat::I32);
+        assert_eq!(choose(Info::new(16000, 1, 8, None)).unwrap().sample_format(), SampleFormat::I16);
+
+        assert_eq!(choose(Info::new(48000, 6, 16, None)), Err(ConfigError::Channels(6)));
+        assert_eq!(choose(Info::new(96000, 2, 16, None)), Err(ConfigError::SampleRate(96000)));
+        assert_eq!(choose(Info::new(48000, 2, 12, None)), Err(ConfigError::UnsupportedFormat));
+    }
+
+    #[test]
+    fn test_playback_estimates() {
+        let playback = Playback {
+            frames_played: 4800,
+            buffered_frames: 2400,
+            device_latency: Duration::from_millis(10),
+            sample_rate: 48000,
+        };
+        assert_eq!(playback.latency(), Duration::from_millis(60));
+        assert_eq!(playback.frames_audible(), 4320);
+
+        let starting = Playback { frames_played: 100, ..playback };
+        assert_eq!(starting.frames_audible(), 0);
+        assert_eq!(Playback::default().latency(), Duration::ZERO);
+    }
+}
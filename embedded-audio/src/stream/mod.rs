@@ -1,5 +1,9 @@
 #[cfg(feature = "std")]
 pub mod cpal_output;
+#[cfg(feature = "std")]
+pub mod cpal_input;
 
 #[cfg(feature = "std")]
-pub use cpal_output::CpalOutputStream;
\ No newline at end of file
+pub use cpal_output::{ConfigError, CpalOutputStream, OutputStats, Playback};
+#[cfg(feature = "std")]
+pub use cpal_input::{CpalInputStream, InputStats};
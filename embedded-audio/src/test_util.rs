@@ -0,0 +1,220 @@
+//! Fixtures shared by the unit tests.
+
+use std::vec::Vec;
+
+use embedded_audio_driver::databus::{Consumer, Databus, Operation, Producer, Transformer};
+use embedded_audio_driver::element::{BaseElement, Eof, Fine, ProcessResult};
+use embedded_audio_driver::info::Info;
+use embedded_audio_driver::payload::Position;
+use embedded_audio_driver::port::{InPlacePort, InPort, OutPort, PayloadSize, PortRequirements};
+use embedded_audio_driver::Error;
+
+use crate::databus::slot::HeapSlot;
+
+/// A sample type the fixtures read and write as little-endian bytes.
+pub(crate) trait TestSample: Copy {
+    const BYTES: usize;
+
+    fn write(self, bytes: &mut [u8]);
+
+    fn read(bytes: &[u8]) -> Self;
+}
+
+macro_rules! impl_test_sample {
+    ($($sample:ty),+) => {
+        $(
+            impl TestSample for $sample {
+                const BYTES: usize = core::mem::size_of::<$sample>();
+
+                fn write(self, bytes: &mut [u8]) {
+                    bytes.copy_from_slice(&self.to_le_bytes());
+                }
+
+                fn read(bytes: &[u8]) -> Self {
+                    <$sample>::from_le_bytes(bytes.try_into().unwrap())
+                }
+            }
+        )+
+    };
+}
+
+impl_test_sample!(u8, i16, i32, f32);
+
+pub(crate) fn from_bytes<S: TestSample>(bytes: &[u8]) -> Vec<S> {
+    bytes.chunks_exact(S::BYTES).map(S::read).collect()
+}
+
+/// Creates a slot of `size.preferred` bytes, registered for producing and consuming.
+pub(crate) fn new_slot(size: PayloadSize) -> HeapSlot {
+    let mut slot = HeapSlot::new_heap(size.preferred as usize);
+    slot.register(Operation::Produce, size);
+    slot.register(Operation::Consume, size);
+    slot
+}
+
+/// Writes `samples` to `slot` as one payload.
+pub(crate) async fn write_samples<S: TestSample>(slot: &HeapSlot, samples: &[S], position: Position) {
+    let mut p = slot.acquire_write().await;
+    for (sample, chunk) in samples.iter().zip(p.chunks_exact_mut(S::BYTES)) {
+        sample.write(chunk);
+    }
+    p.set_valid_length(samples.len() * S::BYTES);
+    p.set_position(position);
+}
+
+/// Reads one payload of samples from `slot`.
+pub(crate) async fn read_samples<S: TestSample>(slot: &HeapSlot) -> (Vec<S>, Position) {
+    let r = slot.acquire_read().await;
+    (from_bytes(&r), r.metadata.position)
+}
+
+/// Runs one payload of `samples` through an in-place element. Returns the samples afterwards,
+/// the position of the payload and whether the element returned `Eof`.
+pub(crate) async fn run_in_place<S, E>(element: &mut E, samples: &[S], position: Position) -> (Vec<S>, Position, bool)
+where
+    S: TestSample,
+    E: BaseElement<Info = Info, Error = Error>,
+{
+    let size = samples.len() * S::BYTES;
+    let mut slot = HeapSlot::new_heap(size);
+    slot.register(Operation::InPlace, PayloadSize { min: 1, preferred: size as u16 });
+    write_samples(&slot, samples, position).await;
+    let result = element
+        .process(&mut InPort::new_none(), &mut OutPort::new_none(), &mut slot.in_place_port())
+        .await
+        .unwrap();
+
+    let (output, position) = read_samples(&slot).await;
+    (output, position, result == Eof)
+}
+
+/// Runs one payload of `samples` through an element that writes a separate payload of up to
+/// `out_size` bytes. Returns the output samples, their position and whether the element
+/// returned `Eof`.
+pub(crate) async fn run_out_of_place<S, E>(
+    element: &mut E,
+    samples: &[S],
+    position: Position,
+    out_size: usize,
+) -> (Vec<S>, Position, bool)
+where
+    S: TestSample,
+    E: BaseElement<Info = Info, Error = Error>,
+{
+    let in_slot = new_slot(PayloadSize { min: 1, preferred: (samples.len() * S::BYTES) as u16 });
+    let out_slot = new_slot(PayloadSize { min: 1, preferred: out_size as u16 });
+    write_samples(&in_slot, samples, position).await;
+    let result = element
+        .process(&mut in_slot.in_port(), &mut out_slot.out_port(), &mut InPlacePort::new_none())
+        .await
+        .unwrap();
+
+    let (output, position) = read_samples(&out_slot).await;
+    (output, position, result == Eof)
+}
+
+/// Runs `samples` through `element` in payloads of `chunk_samples` samples, marked `First` to
+/// `Last`. Elements asking for an in-place port run in place, others write separate payloads
+/// of up to `out_size` bytes. Returns the output and whether the last payload returned `Eof`;
+/// the payloads before it must not.
+pub(crate) async fn run_chunks<S, E>(
+    element: &mut E,
+    requirements: &PortRequirements,
+    samples: &[S],
+    chunk_samples: usize,
+    out_size: usize,
+) -> (Vec<S>, bool)
+where
+    S: TestSample,
+    E: BaseElement<Info = Info, Error = Error>,
+{
+    let chunks: Vec<&[S]> = samples.chunks(chunk_samples).collect();
+    let mut output = Vec::new();
+    let mut eof = false;
+    for (i, chunk) in chunks.iter().enumerate() {
+        assert!(!eof, "Eof before the last payload");
+        let position = match (i == 0, i == chunks.len() - 1) {
+            (true, true) => Position::Single,
+            (true, false) => Position::First,
+            (false, true) => Position::Last,
+            (false, false) => Position::Middle,
+        };
+        let (out, _, chunk_eof) = if requirements.in_place.is_some() {
+            run_in_place(element, chunk, position).await
+        } else {
+            run_out_of_place(element, chunk, position, out_size).await
+        };
+        output.extend(out);
+        eof = chunk_eof;
+    }
+    (output, eof)
+}
+
+/// Initializes `element` with `info` and runs `input` through it as a `Single` payload, with
+/// room for as many output frames as there are input frames. Returns the output.
+pub(crate) async fn run_single<E>(element: &mut E, info: Info, input: &[u8]) -> Vec<u8>
+where
+    E: BaseElement<Info = Info, Error = Error>,
+{
+    element.initialize(Some(info)).await.unwrap();
+    let frames = input.len() / info.get_alignment_bytes() as usize;
+    let out_size = frames * element.get_out_info().unwrap().get_alignment_bytes() as usize;
+    let (output, position, eof) = run_out_of_place(element, input, Position::Single, out_size).await;
+    assert!(eof && position == Position::Single);
+    output
+}
+
+/// A sink collecting 16-bit samples, up to `limit` if set.
+#[derive(Default)]
+pub(crate) struct Collect {
+    pub(crate) samples: Vec<i16>,
+    pub(crate) limit: Option<usize>,
+}
+
+impl BaseElement for Collect {
+    type Error = Error;
+    type Info = Info;
+
+    fn get_in_info(&self) -> Option<Info> {
+        None
+    }
+
+    fn get_out_info(&self) -> Option<Info> {
+        None
+    }
+
+    fn available(&self) -> u32 {
+        u32::MAX
+    }
+
+    async fn initialize(&mut self, upstream_info: Option<Info>) -> Result<PortRequirements, Error> {
+        let info = upstream_info.ok_or(Error::InvalidParameter)?;
+        let bytes_per_frame = info.get_alignment_bytes() as u16;
+        Ok(PortRequirements::sink(PayloadSize { min: bytes_per_frame, preferred: bytes_per_frame * 64 }))
+    }
+
+    async fn process<'a, C, P, T>(
+        &mut self,
+        in_port: &mut InPort<'a, C>,
+        _out_port: &mut OutPort<'a, P>,
+        _inplace_port: &mut InPlacePort<'a, T>,
+    ) -> ProcessResult<Error>
+    where
+        C: Consumer<'a>,
+        P: Producer<'a>,
+        T: Transformer<'a>,
+    {
+        let InPort::Consumer(consumer) = in_port else {
+            return Err(Error::Unsupported);
+        };
+        let payload = consumer.acquire_read().await;
+        self.samples.extend(from_bytes::<i16>(&payload));
+        if self.limit.is_some_and(|limit| self.samples.len() >= limit) {
+            return Ok(Eof);
+        }
+        match payload.metadata.position {
+            Position::Last | Position::Single => Ok(Eof),
+            _ => Ok(Fine),
+        }
+    }
+}
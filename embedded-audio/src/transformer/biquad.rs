@@ -0,0 +1,569 @@
+//! Biquad filters and an N-band parametric equalizer, Inplace Operation.
+//!
+//! Coefficients follow the RBJ "Audio EQ Cookbook". Float samples are filtered in `f32`
+//! (transposed direct form II). Integer samples use a fixed-point direct form I with Q4.27
+//! coefficients and a 64-bit accumulator: 8/16-bit samples are processed as Q15, 24/32-bit
+//! samples as Q31, so no FPU is needed in the sample loop while the coefficients are steady.
+//!
+//! Changing a band while running ramps the coefficients linearly over [`RAMP_FRAMES`] frames
+//! to avoid clicks. The ramp advances in `f32` once per frame, in equal steps; for integer
+//! samples the Q4.27 coefficients are converted from it again every frame until it ends.
+
+use core::f32::consts::PI;
+use libm::{cosf, powf, sinf, sqrtf};
+
+use embedded_audio_driver::databus::{Consumer, Producer, Transformer};
+use embedded_audio_driver::element::{BaseElement, Fine, ProcessResult};
+use embedded_audio_driver::info::Info;
+use embedded_audio_driver::port::{InPlacePort, InPort, OutPort, PayloadSize, PortRequirements};
+use embedded_audio_driver::Error;
+
+use crate::sample::SampleCodec;
+use super::MAX_CHANNELS;
+
+/// The number of frames over which coefficient changes are ramped, one step per frame.
+pub const RAMP_FRAMES: u32 = 128;
+
+const COEF_SHIFT: u32 = 27;
+const COEF_ONE: f32 = (1u32 << COEF_SHIFT) as f32;
+/// Coefficients must stay within the Q4.27 range for the fixed-point path.
+const COEF_LIMIT: f32 = 15.99;
+
+/// The response type of a biquad section.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FilterType {
+    LowPass,
+    HighPass,
+    /// Band-pass with 0 dB peak gain.
+    BandPass,
+    Notch,
+    /// Peaking EQ with the given gain in dB.
+    Peaking(f32),
+    /// Low shelf with the given gain in dB.
+    LowShelf(f32),
+    /// High shelf with the given gain in dB.
+    HighShelf(f32),
+}
+
+/// The parameters of one biquad section.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BandConfig {
+    pub filter: FilterType,
+    /// Centre or corner frequency in Hz.
+    pub frequency: f32,
+    /// Quality factor. `FRAC_1_SQRT_2` gives a Butterworth low/high-pass.
+    pub q: f32,
+}
+
+impl BandConfig {
+    pub fn new(filter: FilterType, frequency: f32, q: f32) -> Self {
+        Self { filter, frequency, q }
+    }
+}
+
+/// Normalized biquad coefficients (`a0 == 1`).
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Coefficients {
+    pub b0: f32,
+    pub b1: f32,
+    pub b2: f32,
+    pub a1: f32,
+    pub a2: f32,
+}
+
+impl Coefficients {
+    /// A pass-through section.
+    pub const IDENTITY: Self = Self { b0: 1.0, b1: 0.0, b2: 0.0, a1: 0.0, a2: 0.0 };
+
+    /// Designs the coefficients for `config` at `sample_rate`.
+    ///
+    /// Returns `Error::InvalidParameter` if the frequency is not between 0 and Nyquist,
+    /// or if `q` is not positive.
+    pub fn design(config: &BandConfig, sample_rate: u32) -> Result<Self, Error> {
+        let fs = sample_rate as f32;
+        let valid = config.frequency > 0.0 && config.frequency < fs / 2.0 && config.q > 0.0;
+        if !valid {
+            return Err(Error::InvalidParameter);
+        }
+
+        let w0 = 2.0 * PI * config.frequency / fs;
+        let (sin, cos) = (sinf(w0), cosf(w0));
+        let alpha = sin / (2.0 * config.q);
+        let amp = |gain_db: f32| powf(10.0, gain_db / 40.0);
+
+        let (b0, b1, b2, a0, a1, a2) = match config.filter {
+            FilterType::LowPass => {
+                let b = (1.0 - cos) / 2.0;
+                (b, 1.0 - cos, b, 1.0 + alpha, -2.0 * cos, 1.0 - alpha)
+            }
+            FilterType::HighPass => {
+                let b = (1.0 + cos) / 2.0;
+                (b, -(1.0 + cos), b, 1.0 + alpha, -2.0 * cos, 1.0 - alpha)
+            }
+            FilterType::BandPass => (alpha, 0.0, -alpha, 1.0 + alpha, -2.0 * cos, 1.0 - alpha),
+            FilterType::Notch => (1.0, -2.0 * cos, 1.0, 1.0 + alpha, -2.0 * cos, 1.0 - alpha),
+            FilterType::Peaking(gain_db) => {
+                let a = amp(gain_db);
+                (
+                    1.0 + alpha * a,
+                    -2.0 * cos,
+                    1.0 - alpha * a,
+                    1.0 + alpha / a,
+                    -2.0 * cos,
+                    1.0 - alpha / a,
+                )
+            }
+            FilterType::LowShelf(gain_db) => {
+                let a = amp(gain_db);
+                let k = 2.0 * sqrtf(a) * alpha;
+                (
+                    a * ((a + 1.0) - (a - 1.0) * cos + k),
+                    2.0 * a * ((a - 1.0) - (a + 1.0) * cos),
+                    a * ((a + 1.0) - (a - 1.0) * cos - k),
+                    (a + 1.0) + (a - 1.0) * cos + k,
+                    -2.0 * ((a - 1.0) + (a + 1.0) * cos),
+                    (a + 1.0) + (a - 1.0) * cos - k,
+                )
+            }
+            FilterType::HighShelf(gain_db) => {
+                let a = amp(gain_db);
+                let k = 2.0 * sqrtf(a) * alpha;
+                (
+                    a * ((a + 1.0) + (a - 1.0) * cos + k),
+                    -2.0 * a * ((a - 1.0) + (a + 1.0) * cos),
+                    a * ((a + 1.0) + (a - 1.0) * cos - k),
+                    (a + 1.0) - (a - 1.0) * cos + k,
+                    2.0 * ((a - 1.0) - (a + 1.0) * cos),
+                    (a + 1.0) - (a - 1.0) * cos - k,
+                )
+            }
+        };
+
+        Ok(Self {
+            b0: b0 / a0,
+            b1: b1 / a0,
+            b2: b2 / a0,
+            a1: a1 / a0,
+            a2: a2 / a0,
+        })
+    }
+
+    fn to_array(self) -> [f32; 5] {
+        [self.b0, self.b1, self.b2, self.a1, self.a2]
+    }
+
+    fn fits_fixed(&self) -> bool {
+        self.to_array().iter().all(|c| c.abs() < COEF_LIMIT)
+    }
+}
+
+/// Per-channel filter memory of one section.
+#[derive(Debug, Clone, Copy, Default)]
+struct SectionState {
+    /// Transposed direct form II state of the float path.
+    s1: f32,
+    s2: f32,
+    /// Direct form I history of the fixed-point path.
+    x1: i32,
+    x2: i32,
+    y1: i32,
+    y2: i32,
+}
+
+/// One band: its configuration and the (possibly ramping) coefficients.
+#[derive(Debug, Clone, Copy)]
+struct Band {
+    config: BandConfig,
+    current: [f32; 5],
+    step: [f32; 5],
+    target: [f32; 5],
+    fixed: [i32; 5],
+    ramp_remaining: u32,
+}
+
+impl Band {
+    fn new(config: BandConfig) -> Self {
+        let identity = Coefficients::IDENTITY.to_array();
+        let mut band = Self {
+            config,
+            current: identity,
+            step: [0.0; 5],
+            target: identity,
+            fixed: [0; 5],
+            ramp_remaining: 0,
+        };
+        band.update_fixed();
+        band
+    }
+
+    fn update_fixed(&mut self) {
+        for (fixed, coef) in self.fixed.iter_mut().zip(self.current) {
+            *fixed = (coef * COEF_ONE) as i32;
+        }
+    }
+
+    /// Jumps to `coefficients` immediately.
+    fn set(&mut self, coefficients: Coefficients) {
+        self.current = coefficients.to_array();
+        self.target = self.current;
+        self.ramp_remaining = 0;
+        self.update_fixed();
+    }
+
+    /// Starts a ramp towards `coefficients`.
+    fn ramp_to(&mut self, coefficients: Coefficients) {
+        self.target = coefficients.to_array();
+        for ((step, target), current) in self.step.iter_mut().zip(self.target).zip(self.current) {
+            *step = (target - current) / RAMP_FRAMES as f32;
+        }
+        self.ramp_remaining = RAMP_FRAMES;
+    }
+
+    /// Advances the ramp by one frame.
+    #[inline]
+    fn tick(&mut self, fixed: bool) {
+        if self.ramp_remaining == 0 {
+            return;
+        }
+        self.ramp_remaining -= 1;
+        if self.ramp_remaining == 0 {
+            self.current = self.target;
+        } else {
+            for (current, step) in self.current.iter_mut().zip(self.step) {
+                *current += step;
+            }
+        }
+        if fixed {
+            self.update_fixed();
+        }
+    }
+
+    #[inline]
+    fn process_f32(&self, state: &mut SectionState, x: f32) -> f32 {
+        let [b0, b1, b2, a1, a2] = self.current;
+        let y = b0 * x + state.s1;
+        state.s1 = b1 * x - a1 * y + state.s2;
+        state.s2 = b2 * x - a2 * y;
+        y
+    }
+
+    /// Filters one sample in the fixed-point domain; `min..=max` is the sample range.
+    #[inline]
+    fn process_fixed(&self, state: &mut SectionState, x: i32, min: i64, max: i64) -> i32 {
+        let [b0, b1, b2, a1, a2] = self.fixed;
+        let acc = (b0 as i64 * x as i64)
+            .saturating_add(b1 as i64 * state.x1 as i64)
+            .saturating_add(b2 as i64 * state.x2 as i64)
+            .saturating_sub(a1 as i64 * state.y1 as i64)
+            .saturating_sub(a2 as i64 * state.y2 as i64);
+        let y = (acc.saturating_add(1 << (COEF_SHIFT - 1)) >> COEF_SHIFT).clamp(min, max) as i32;
+        state.x2 = state.x1;
+        state.x1 = x;
+        state.y2 = state.y1;
+        state.y1 = y;
+        y
+    }
+}
+
+/// The arithmetic used for a sample format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Path {
+    Float,
+    Q15,
+    Q31,
+}
+
+/// An Element applying a cascade of `BANDS` biquad sections to every channel.
+pub struct Equalizer<const BANDS: usize> {
+    bands: [Band; BANDS],
+    state: [[SectionState; BANDS]; MAX_CHANNELS],
+    info: Option<Info>,
+    codec: Option<SampleCodec>,
+    path: Path,
+    frames_per_process: u16,
+}
+
+/// A single biquad filter.
+pub type Biquad = Equalizer<1>;
+
+impl<const BANDS: usize> Equalizer<BANDS> {
+    /// Creates a new equalizer.
+    ///
+    /// # Arguments
+    ///
+    /// * `bands` - The configuration of each section, applied in order.
+    pub fn new(bands: [BandConfig; BANDS], frames_per_process: u16) -> Self {
+        Self {
+            bands: bands.map(Band::new),
+            state: [[SectionState::default(); BANDS]; MAX_CHANNELS],
+            info: None,
+            codec: None,
+            path: Path::Float,
+            frames_per_process,
+        }
+    }
+
+    /// Returns the configuration of band `index`.
+    pub fn band(&self, index: usize) -> Option<BandConfig> {
+        self.bands.get(index).map(|band| band.config)
+    }
+
+    /// Changes band `index`.
+    ///
+    /// Once initialized, the new coefficients are reached after [`RAMP_FRAMES`] frames.
+    pub fn set_band(&mut self, index: usize, config: BandConfig) -> Result<(), Error> {
+        let band = self.bands.get_mut(index).ok_or(Error::InvalidParameter)?;
+        if let Some(info) = self.info {
+            let coefficients = Coefficients::design(&config, info.sample_rate)?;
+            if self.path != Path::Float && !coefficients.fits_fixed() {
+                return Err(Error::InvalidParameter);
+            }
+            band.ramp_to(coefficients);
+        }
+        band.config = config;
+        Ok(())
+    }
+
+    fn clear_state(&mut self) {
+        self.state = [[SectionState::default(); BANDS]; MAX_CHANNELS];
+    }
+
+    fn filter(&mut self, codec: SampleCodec, payload: &mut [u8], channels: usize) {
+        let bytes = codec.bytes_per_sample();
+        let fixed = self.path != Path::Float;
+
+        for frame in payload.chunks_exact_mut(bytes * channels) {
+            for (sample, state) in frame.chunks_exact_mut(bytes).zip(self.state.iter_mut()) {
+                match self.path {
+                    Path::Float => {
+                        let mut x = codec.read_f32(sample);
+                        for (band, state) in self.bands.iter().zip(state.iter_mut()) {
+                            x = band.process_f32(state, x);
+                        }
+                        codec.write_f32(sample, x);
+                    }
+                    Path::Q15 => {
+                        let mut x = codec.read_i32(sample) >> 16;
+                        for (band, state) in self.bands.iter().zip(state.iter_mut()) {
+                            x = band.process_fixed(state, x, i16::MIN as i64, i16::MAX as i64);
+                        }
+                        codec.write_i32(sample, x << 16);
+                    }
+                    Path::Q31 => {
+                        let mut x = codec.read_i32(sample);
+                        for (band, state) in self.bands.iter().zip(state.iter_mut()) {
+                            x = band.process_fixed(state, x, i32::MIN as i64, i32::MAX as i64);
+                        }
+                        codec.write_i32(sample, x);
+                    }
+                }
+            }
+            self.bands.iter_mut().for_each(|band| band.tick(fixed));
+        }
+    }
+}
+
+impl<const BANDS: usize> BaseElement for Equalizer<BANDS> {
+    type Error = Error;
+    type Info = Info;
+
+    fn get_in_info(&self) -> Option<Info> {
+        self.info
+    }
+
+    fn get_out_info(&self) -> Option<Info> {
+        self.info
+    }
+
+    async fn initialize(
+        &mut self,
+        upstream_info: Option<Info>,
+    ) -> Result<PortRequirements, Self::Error> {
+        let info = upstream_info.ok_or(Error::InvalidParameter)?;
+        if !info.vaild() {
+            return Err(Error::InvalidParameter);
+        }
+        if info.channels as usize > MAX_CHANNELS {
+            return Err(Error::Unsupported);
+        }
+        let codec = SampleCodec::from_info(&info).ok_or(Error::Unsupported)?;
+        self.path = match (codec.is_float(), info.bits_per_sample) {
+            (true, _) => Path::Float,
+            (false, 8 | 16) => Path::Q15,
+            (false, _) => Path::Q31,
+        };
+
+        for band in self.bands.iter_mut() {
+            let coefficients = Coefficients::design(&band.config, info.sample_rate)?;
+            if self.path != Path::Float && !coefficients.fits_fixed() {
+                return Err(Error::InvalidParameter);
+            }
+            band.set(coefficients);
+        }
+        self.clear_state();
+        self.codec = Some(codec);
+        self.info = Some(info);
+
+        let bytes_per_frame = info.get_alignment_bytes() as u16;
+        Ok(PortRequirements::new_in_place(PayloadSize {
+            min: bytes_per_frame,
+            preferred: bytes_per_frame * self.frames_per_process,
+        }))
+    }
+
+    fn available(&self) -> u32 {
+        u32::MAX
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        self.clear_state();
+        Ok(())
+    }
+
+    async fn reset(&mut self) -> Result<(), Self::Error> {
+        self.info = None;
+        self.codec = None;
+        self.clear_state();
+        Ok(())
+    }
+
+    async fn process<'a, C, P, T>(
+        &mut self,
+        _in_port: &mut InPort<'a, C>,
+        _out_port: &mut OutPort<'a, P>,
+        in_place_port: &mut InPlacePort<'a, T>,
+    ) -> ProcessResult<Self::Error>
+    where
+        C: Consumer<'a>,
+        P: Producer<'a>,
+        T: Transformer<'a>,
+    {
+        if let InPlacePort::Transformer(transformer) = in_place_port {
+            let mut payload = transformer.acquire_transform().await;
+            let codec = self.codec.ok_or(Error::NotInitialized)?;
+            let channels = self.info.ok_or(Error::NotInitialized)?.channels as usize;
+
+            self.filter(codec, &mut payload, channels);
+            Ok(Fine)
+        } else {
+            Err(Error::Unsupported)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::f32::consts::FRAC_1_SQRT_2;
+    use embedded_audio_driver::payload::Position;
+
+    use crate::test_util::run_in_place;
+
+    fn sine(frequency: f32, sample_rate: f32, amplitude: f32, n: usize) -> Vec<f32> {
+        (0..n).map(|i| amplitude * sinf(2.0 * PI * frequency * i as f32 / sample_rate)).collect()
+    }
+
+    fn peak(samples: &[f32]) -> f32 {
+        samples.iter().fold(0.0, |m, s| m.max(s.abs()))
+    }
+
+    /// Initializes `eq` with `info` and filters `data` as one payload.
+    async fn run_eq<const BANDS: usize>(eq: &mut Equalizer<BANDS>, info: Info, data: &mut [u8]) {
+        eq.initialize(Some(info)).await.unwrap();
+        let (out, _, _) = run_in_place(eq, data, Position::Single).await;
+        data.copy_from_slice(&out);
+    }
+
+    #[test]
+    fn test_design_responses() {
+        // DC and Nyquist gains of the cookbook filters
+        let dc = |c: &Coefficients| (c.b0 + c.b1 + c.b2) / (1.0 + c.a1 + c.a2);
+        let nyquist = |c: &Coefficients| (c.b0 - c.b1 + c.b2) / (1.0 - c.a1 + c.a2);
+
+        let lp = Coefficients::design(&BandConfig::new(FilterType::LowPass, 1000.0, FRAC_1_SQRT_2), 48000).unwrap();
+        assert!((dc(&lp) - 1.0).abs() < 1e-4 && nyquist(&lp).abs() < 1e-4);
+        let hp = Coefficients::design(&BandConfig::new(FilterType::HighPass, 1000.0, FRAC_1_SQRT_2), 48000).unwrap();
+        assert!(dc(&hp).abs() < 1e-4 && (nyquist(&hp) - 1.0).abs() < 1e-4);
+        let ls = Coefficients::design(&BandConfig::new(FilterType::LowShelf(6.0), 200.0, FRAC_1_SQRT_2), 48000).unwrap();
+        assert!((dc(&ls) - powf(10.0, 6.0 / 20.0)).abs() < 1e-2 && (nyquist(&ls) - 1.0).abs() < 1e-3);
+        let hs = Coefficients::design(&BandConfig::new(FilterType::HighShelf(-6.0), 5000.0, FRAC_1_SQRT_2), 48000).unwrap();
+        assert!((dc(&hs) - 1.0).abs() < 1e-3 && (nyquist(&hs) - powf(10.0, -6.0 / 20.0)).abs() < 1e-3);
+        let notch = Coefficients::design(&BandConfig::new(FilterType::Notch, 1000.0, 1.0), 48000).unwrap();
+        assert!((dc(&notch) - 1.0).abs() < 1e-4);
+
+        assert!(Coefficients::design(&BandConfig::new(FilterType::LowPass, 30000.0, 0.7), 48000).is_err());
+        assert!(Coefficients::design(&BandConfig::new(FilterType::LowPass, 1000.0, 0.0), 48000).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_float_and_fixed_low_pass() {
+        let rate = 48000;
+        let config = BandConfig::new(FilterType::LowPass, 1000.0, FRAC_1_SQRT_2);
+        let pass = sine(100.0, rate as f32, 0.5, 4800);
+        let stop = sine(10000.0, rate as f32, 0.5, 4800);
+
+        for signal in [&pass, &stop] {
+            // Float, stereo with the same signal on both channels
+            let mut data: Vec<u8> = signal.iter().flat_map(|s| [s.to_le_bytes(), s.to_le_bytes()]).flatten().collect();
+            let mut eq = Biquad::new([config], 4800);
+            run_eq(&mut eq, Info::new_float(rate, 2, 32, None), &mut data).await;
+            let left: Vec<f32> = data.chunks_exact(8).map(|c| f32::from_le_bytes(c[..4].try_into().unwrap())).collect();
+            let right: Vec<f32> = data.chunks_exact(8).map(|c| f32::from_le_bytes(c[4..].try_into().unwrap())).collect();
+            assert_eq!(left, right);
+            let float_peak = peak(&left[2400..]);
+
+            // Q15
+            let mut data: Vec<u8> = signal.iter().flat_map(|s| ((s * 32767.0) as i16).to_le_bytes()).collect();
+            let mut eq = Biquad::new([config], 4800);
+            run_eq(&mut eq, Info::new(rate, 1, 16, None), &mut data).await;
+            let q15: Vec<f32> = data.chunks_exact(2).map(|c| i16::from_le_bytes([c[0], c[1]]) as f32 / 32768.0).collect();
+            assert!((peak(&q15[2400..]) - float_peak).abs() < 2e-3);
+
+            // Q31 through a 24-bit container
+            let mut data: Vec<u8> = signal.iter().flat_map(|s| ((s * 8388607.0) as i32).to_le_bytes()[..3].to_vec()).collect();
+            let mut eq = Biquad::new([config], 4800);
+            run_eq(&mut eq, Info::new(rate, 1, 24, None), &mut data).await;
+            let q31: Vec<f32> = data
+                .chunks_exact(3)
+                .map(|c| (i32::from_le_bytes([0, c[0], c[1], c[2]]) >> 8) as f32 / 8388608.0)
+                .collect();
+            assert!((peak(&q31[2400..]) - float_peak).abs() < 1e-4);
+
+            if core::ptr::eq(signal, &pass) {
+                assert!(float_peak > 0.49);
+            } else {
+                assert!(float_peak < 0.01);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cascade_and_ramped_update() {
+        let rate = 48000;
+        let mut eq = Equalizer::<2>::new(
+            [
+                BandConfig::new(FilterType::Peaking(6.0), 1000.0, 1.0),
+                BandConfig::new(FilterType::Peaking(6.0), 1000.0, 1.0),
+            ],
+            4800,
+        );
+        let signal = sine(1000.0, rate as f32, 0.1, 9600);
+        let mut data: Vec<u8> = signal.iter().flat_map(|s| s.to_le_bytes()).collect();
+        run_eq(&mut eq, Info::new_float(rate, 1, 32, None), &mut data).await;
+        let out: Vec<f32> = data.chunks_exact(4).map(|c| f32::from_le_bytes(c.try_into().unwrap())).collect();
+        // Two +6 dB peaks at the centre frequency add up to +12 dB
+        assert!((peak(&out[4800..]) / 0.1 - powf(10.0, 12.0 / 20.0)).abs() < 0.02);
+
+        // Switch one band to a cut and check there is no jump between consecutive samples
+        eq.set_band(0, BandConfig::new(FilterType::Peaking(-6.0), 1000.0, 1.0)).unwrap();
+        let data: Vec<u8> = signal.iter().flat_map(|s| s.to_le_bytes()).collect();
+        let (data, _, _) = run_in_place(&mut eq, &data, Position::Single).await;
+        let out: Vec<f32> = data.chunks_exact(4).map(|c| f32::from_le_bytes(c.try_into().unwrap())).collect();
+        let max_step = out.windows(2).map(|w| (w[1] - w[0]).abs()).fold(0.0, f32::max);
+        // A 1 kHz sine at 0.4 changes by at most ~0.053 per sample
+        assert!(max_step < 0.06, "step {}", max_step);
+        assert!((peak(&out[4800..]) / 0.1 - 1.0).abs() < 0.02);
+        assert_eq!(eq.band(0).unwrap().filter, FilterType::Peaking(-6.0));
+        assert!(eq.set_band(2, BandConfig::new(FilterType::Notch, 1000.0, 1.0)).is_err());
+    }
+}
@@ -0,0 +1,345 @@
+//! A channel remapping, upmix and downmix processor.
+//!
+//! Every output channel is a weighted sum of the input channels, described by a [`MixMatrix`].
+//! Integer samples are mixed with Q16.16 coefficients on MSB-aligned samples, so unity
+//! coefficients pass samples through bit-exact; float samples are mixed in `f32`.
+//! Results exceeding full scale are clamped.
+
+use embedded_audio_driver::databus::{Consumer, Producer, Transformer};
+use embedded_audio_driver::element::{BaseElement, Eof, Fine, ProcessResult};
+use embedded_audio_driver::info::Info;
+use embedded_audio_driver::payload::Position;
+use embedded_audio_driver::port::{InPlacePort, InPort, OutPort, PayloadSize, PortRequirements};
+use embedded_audio_driver::Error;
+
+use crate::sample::SampleCodec;
+use super::MAX_CHANNELS;
+
+const FIXED_POINT_SHIFT: u32 = 16;
+const FIXED_POINT_ONE: f32 = (1 << FIXED_POINT_SHIFT) as f32;
+
+/// -3 dB, the ITU-R BS.775 downmix weight for the centre and surround channels.
+const MINUS_3DB: f32 = core::f32::consts::FRAC_1_SQRT_2;
+
+/// WAVE channel mask bits.
+const SPEAKER_FRONT_LEFT: u32 = 0x1;
+const SPEAKER_FRONT_RIGHT: u32 = 0x2;
+const SPEAKER_FRONT_CENTER: u32 = 0x4;
+
+/// Common channel layouts conversions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MixPreset {
+    /// Duplicates a mono channel to left and right.
+    MonoToStereo,
+    /// Averages left and right into a single channel.
+    StereoToMono,
+    /// ITU-R BS.775 downmix of 5.1 (FL, FR, FC, LFE, BL, BR) to stereo. The LFE channel is dropped.
+    Surround51ToStereo,
+}
+
+/// A gain matrix mapping `in_channels` input channels to `out_channels` output channels.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MixMatrix {
+    in_channels: u8,
+    out_channels: u8,
+    /// `coeffs[out][in]`
+    coeffs: [[f32; MAX_CHANNELS]; MAX_CHANNELS],
+    channel_mask: u32,
+}
+
+impl MixMatrix {
+    /// Creates a matrix from row-major coefficients, one row of `in_channels` gains per output channel.
+    ///
+    /// Returns `Error::InvalidParameter` if a channel count is zero or exceeds [`MAX_CHANNELS`],
+    /// or if `coeffs` does not hold `in_channels * out_channels` values.
+    pub fn new(in_channels: u8, out_channels: u8, coeffs: &[f32]) -> Result<Self, Error> {
+        let (ins, outs) = (in_channels as usize, out_channels as usize);
+        if ins == 0 || outs == 0 || ins > MAX_CHANNELS || outs > MAX_CHANNELS || coeffs.len() != ins * outs {
+            return Err(Error::InvalidParameter);
+        }
+
+        let mut matrix = [[0.0; MAX_CHANNELS]; MAX_CHANNELS];
+        for (row, gains) in matrix.iter_mut().zip(coeffs.chunks_exact(ins)) {
+            row[..ins].copy_from_slice(gains);
+        }
+        Ok(Self {
+            in_channels,
+            out_channels,
+            coeffs: matrix,
+            channel_mask: 0,
+        })
+    }
+
+    /// Sets the channel mask reported in the output `Info`. 0 means unspecified.
+    pub fn with_channel_mask(mut self, channel_mask: u32) -> Self {
+        self.channel_mask = channel_mask;
+        self
+    }
+
+    pub fn in_channels(&self) -> u8 {
+        self.in_channels
+    }
+
+    pub fn out_channels(&self) -> u8 {
+        self.out_channels
+    }
+
+    /// The gain from input channel `input` to output channel `output`.
+    pub fn gain(&self, output: usize, input: usize) -> f32 {
+        self.coeffs[output][input]
+    }
+}
+
+impl From<MixPreset> for MixMatrix {
+    fn from(preset: MixPreset) -> Self {
+        let (matrix, mask) = match preset {
+            MixPreset::MonoToStereo => (
+                MixMatrix::new(1, 2, &[1.0, 1.0]),
+                SPEAKER_FRONT_LEFT | SPEAKER_FRONT_RIGHT,
+            ),
+            MixPreset::StereoToMono => (
+                MixMatrix::new(2, 1, &[0.5, 0.5]),
+                SPEAKER_FRONT_CENTER,
+            ),
+            MixPreset::Surround51ToStereo => (
+                MixMatrix::new(6, 2, &[
+                    1.0, 0.0, MINUS_3DB, 0.0, MINUS_3DB, 0.0,
+                    0.0, 1.0, MINUS_3DB, 0.0, 0.0, MINUS_3DB,
+                ]),
+                SPEAKER_FRONT_LEFT | SPEAKER_FRONT_RIGHT,
+            ),
+        };
+        matrix.unwrap().with_channel_mask(mask)
+    }
+}
+
+/// An Element that remaps, upmixes or downmixes interleaved channels.
+pub struct ChannelMixer {
+    matrix: MixMatrix,
+    fixed_coeffs: [[i32; MAX_CHANNELS]; MAX_CHANNELS],
+    in_info: Option<Info>,
+    out_info: Option<Info>,
+    codec: Option<SampleCodec>,
+    frames_per_process: u16,
+}
+
+impl ChannelMixer {
+    /// Creates a new channel mixer.
+    ///
+    /// # Arguments
+    ///
+    /// * `matrix` - The mix matrix, either a [`MixPreset`] or a custom [`MixMatrix`].
+    pub fn new(matrix: impl Into<MixMatrix>, frames_per_process: u16) -> Self {
+        let matrix = matrix.into();
+        let mut fixed_coeffs = [[0; MAX_CHANNELS]; MAX_CHANNELS];
+        for (fixed_row, row) in fixed_coeffs.iter_mut().zip(matrix.coeffs.iter()) {
+            for (fixed, gain) in fixed_row.iter_mut().zip(row.iter()) {
+                *fixed = (gain * FIXED_POINT_ONE) as i32;
+            }
+        }
+        Self {
+            matrix,
+            fixed_coeffs,
+            in_info: None,
+            out_info: None,
+            codec: None,
+            frames_per_process,
+        }
+    }
+
+    pub fn matrix(&self) -> &MixMatrix {
+        &self.matrix
+    }
+
+    /// Mixes `frames` frames from `src` into `dest`.
+    fn mix(&self, codec: SampleCodec, src: &[u8], dest: &mut [u8], frames: usize) {
+        let bytes = codec.bytes_per_sample();
+        let ins = self.matrix.in_channels as usize;
+        let outs = self.matrix.out_channels as usize;
+
+        let src = src.chunks_exact(ins * bytes);
+        let dest = dest.chunks_exact_mut(outs * bytes);
+        for (src, dest) in src.zip(dest).take(frames) {
+            let mut frame = [0i32; MAX_CHANNELS];
+            let mut frame_f32 = [0f32; MAX_CHANNELS];
+            for (c, sample) in src.chunks_exact(bytes).enumerate() {
+                if codec.is_float() {
+                    frame_f32[c] = codec.read_f32(sample);
+                } else {
+                    frame[c] = codec.read_i32(sample);
+                }
+            }
+
+            for o in 0..outs {
+                let out = &mut dest[o * bytes..];
+                if codec.is_float() {
+                    let row = &self.matrix.coeffs[o][..ins];
+                    let sum: f32 = row.iter().zip(&frame_f32).map(|(g, x)| g * x).sum();
+                    codec.write_f32(out, sum);
+                } else {
+                    let row = &self.fixed_coeffs[o][..ins];
+                    let sum: i64 = row.iter().zip(&frame).map(|(g, x)| *g as i64 * *x as i64).sum();
+                    let sum = (sum >> FIXED_POINT_SHIFT).clamp(i32::MIN as i64, i32::MAX as i64);
+                    codec.write_i32(out, sum as i32);
+                }
+            }
+        }
+    }
+}
+
+impl BaseElement for ChannelMixer {
+    type Error = Error;
+    type Info = Info;
+
+    fn get_in_info(&self) -> Option<Info> {
+        self.in_info
+    }
+
+    fn get_out_info(&self) -> Option<Info> {
+        self.out_info
+    }
+
+    fn available(&self) -> u32 {
+        u32::MAX
+    }
+
+    async fn initialize(
+        &mut self,
+        upstream_info: Option<Info>,
+    ) -> Result<PortRequirements, Self::Error> {
+        let in_info = upstream_info.ok_or(Error::InvalidParameter)?;
+        if !in_info.vaild() || in_info.channels != self.matrix.in_channels {
+            return Err(Error::InvalidParameter);
+        }
+        self.codec = Some(SampleCodec::from_info(&in_info).ok_or(Error::Unsupported)?);
+
+        let mut out_info = in_info;
+        out_info.channels = self.matrix.out_channels;
+        out_info.channel_mask = self.matrix.channel_mask;
+        self.in_info = Some(in_info);
+        self.out_info = Some(out_info);
+
+        let in_frame = in_info.get_alignment_bytes() as u16;
+        let out_frame = out_info.get_alignment_bytes() as u16;
+        let mut requirements = PortRequirements::sink(PayloadSize {
+            min: in_frame,
+            preferred: in_frame * self.frames_per_process,
+        });
+        requirements.out = Some(PayloadSize {
+            min: out_frame,
+            preferred: out_frame * self.frames_per_process,
+        });
+        Ok(requirements)
+    }
+
+    async fn reset(&mut self) -> Result<(), Self::Error> {
+        self.in_info = None;
+        self.out_info = None;
+        self.codec = None;
+        Ok(())
+    }
+
+    async fn process<'a, C, P, T>(
+        &mut self,
+        in_port: &mut InPort<'a, C>,
+        out_port: &mut OutPort<'a, P>,
+        _inplace_port: &mut InPlacePort<'a, T>,
+    ) -> ProcessResult<Self::Error>
+    where
+        C: Consumer<'a>,
+        P: Producer<'a>,
+        T: Transformer<'a>,
+    {
+        let (InPort::Consumer(consumer), OutPort::Producer(producer)) = (in_port, out_port) else {
+            return Err(Error::Unsupported);
+        };
+        let codec = self.codec.ok_or(Error::NotInitialized)?;
+        let in_info = self.in_info.ok_or(Error::NotInitialized)?;
+        let out_info = self.out_info.ok_or(Error::NotInitialized)?;
+
+        let in_payload = consumer.acquire_read().await;
+        let mut out_payload = producer.acquire_write().await;
+
+        let frames = in_payload.len() / in_info.get_alignment_bytes() as usize;
+        let out_len = frames * out_info.get_alignment_bytes() as usize;
+        if out_len > out_payload.len() {
+            out_payload.set_valid_length(0);
+            return Err(Error::BufferFull);
+        }
+
+        self.mix(codec, &in_payload, &mut out_payload, frames);
+
+        let position = in_payload.metadata.position;
+        out_payload.set_valid_length(out_len);
+        out_payload.set_position(position);
+
+        match position {
+            Position::Last | Position::Single => Ok(Eof),
+            _ => Ok(Fine),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::run_single;
+
+    #[tokio::test]
+    async fn test_presets() {
+        // Mono -> stereo, 32-bit passes through bit-exact
+        let mut mixer = ChannelMixer::new(MixPreset::MonoToStereo, 64);
+        let input: Vec<u8> = [0x1234_5679i32, -7].iter().flat_map(|s| s.to_le_bytes()).collect();
+        let output = run_single(&mut mixer, Info::new(48000, 1, 32, None), &input).await;
+        let samples: Vec<i32> = output.chunks_exact(4).map(|c| i32::from_le_bytes(c.try_into().unwrap())).collect();
+        assert_eq!(samples, [0x1234_5679, 0x1234_5679, -7, -7]);
+        let out_info = mixer.get_out_info().unwrap();
+        assert_eq!(out_info.channels, 2);
+        assert_eq!(out_info.channel_mask, SPEAKER_FRONT_LEFT | SPEAKER_FRONT_RIGHT);
+
+        // Stereo -> mono, unsigned 8-bit averages around the 128 midpoint
+        let mut mixer = ChannelMixer::new(MixPreset::StereoToMono, 64);
+        let output = run_single(&mut mixer, Info::new(48000, 2, 8, None), &[255, 255, 128, 0, 200, 100]).await;
+        assert_eq!(output, [255, 64, 150]);
+        assert_eq!(mixer.get_out_info().unwrap().channels, 1);
+
+        // 5.1 -> stereo, float
+        let mut mixer = ChannelMixer::new(MixPreset::Surround51ToStereo, 64);
+        let frame = [0.5f32, -0.25, 0.2, 1.0, 0.1, 0.0];
+        let input: Vec<u8> = frame.iter().flat_map(|s| s.to_le_bytes()).collect();
+        let output = run_single(&mut mixer, Info::new_float(48000, 6, 32, Some(1)), &input).await;
+        let samples: Vec<f32> = output.chunks_exact(4).map(|c| f32::from_le_bytes(c.try_into().unwrap())).collect();
+        assert!((samples[0] - (0.5 + 0.3 * MINUS_3DB)).abs() < 1e-6);
+        assert!((samples[1] - (-0.25 + 0.2 * MINUS_3DB)).abs() < 1e-6);
+        assert_eq!(mixer.get_out_info().unwrap().num_frames, Some(1));
+    }
+
+    #[tokio::test]
+    async fn test_custom_matrix() {
+        // Swap left and right, and add a third channel with the attenuated sum, 24-bit.
+        let matrix = MixMatrix::new(2, 3, &[0.0, 1.0, 1.0, 0.0, 0.5, 0.5]).unwrap();
+        let mut mixer = ChannelMixer::new(matrix, 64);
+        let input = [
+            0x00, 0x00, 0x40, // 0x400000
+            0x00, 0x00, 0xC0, // -0x400000
+            0xFF, 0xFF, 0x7F, // full scale
+            0xFF, 0xFF, 0x7F,
+        ];
+        let output = run_single(&mut mixer, Info::new(48000, 2, 24, None), &input).await;
+        let samples: Vec<i32> = output
+            .chunks_exact(3)
+            .map(|c| i32::from_le_bytes([0, c[0], c[1], c[2]]) >> 8)
+            .collect();
+        assert_eq!(samples, [-0x400000, 0x400000, 0, 0x7FFFFF, 0x7FFFFF, 0x7FFFFF]);
+        assert_eq!(mixer.get_out_info().unwrap().channel_mask, 0);
+
+        // Channel count mismatch and invalid matrices
+        let mut mixer = ChannelMixer::new(MixPreset::StereoToMono, 64);
+        assert!(matches!(
+            mixer.initialize(Some(Info::new(48000, 1, 16, None))).await,
+            Err(Error::InvalidParameter)
+        ));
+        assert!(MixMatrix::new(2, 2, &[1.0; 3]).is_err());
+        assert!(MixMatrix::new(9, 1, &[1.0; 9]).is_err());
+    }
+}
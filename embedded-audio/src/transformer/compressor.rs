@@ -0,0 +1,663 @@
+//! A dynamic range compressor, Inplace Operation, and a look-ahead brickwall limiter, which
+//! runs out of place.
+//!
+//! Both modes use stereo-linked detection: the loudest channel of each frame drives a single
+//! gain applied to all channels, so the stereo image does not shift. Samples are processed as
+//! `f32` on every format [`Gain`](super::Gain) supports.
+//!
+//! The limiter delays the audio by its look-ahead (plus 4 frames with true-peak detection) so
+//! the gain is already down when a peak arrives. Unlike the compressor it therefore reads and
+//! writes separate payloads, which keeps the frame count exact: the silence the delay line
+//! starts out with is not emitted, and the frames still held in it are flushed when the `Last`
+//! payload arrives, so the output has exactly as many frames as the input. `flush` discards
+//! the delayed frames.
+
+use core::f32::consts::PI;
+use libm::{cosf, sinf};
+
+use embedded_audio_driver::databus::{Consumer, Producer, Transformer};
+use embedded_audio_driver::element::{BaseElement, Eof, Fine, ProcessResult};
+use embedded_audio_driver::info::Info;
+use embedded_audio_driver::payload::Position;
+use embedded_audio_driver::port::{InPlacePort, InPort, OutPort, PayloadSize, PortRequirements};
+use embedded_audio_driver::Error;
+
+use crate::sample::SampleCodec;
+use super::gain::{db_to_linear, linear_to_db, Gain};
+use super::time_coefficient;
+use super::MAX_CHANNELS;
+
+/// The longest supported limiter look-ahead in frames.
+pub const MAX_LOOKAHEAD_FRAMES: usize = 256;
+
+/// True-peak detection oversampling factor and interpolation filter length.
+const TP_FACTOR: usize = 4;
+const TP_TAPS: usize = 8;
+
+/// Parameters of the feed-forward compressor.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CompressorConfig {
+    /// Level above which gain reduction starts, in dBFS.
+    pub threshold_db: f32,
+    /// Input/output slope above the threshold, e.g. 4.0 for 4:1. Must be at least 1.
+    pub ratio: f32,
+    /// Width of the soft knee around the threshold in dB. 0 gives a hard knee.
+    pub knee_db: f32,
+    pub attack_ms: f32,
+    pub release_ms: f32,
+    /// Gain applied after compression, in dB.
+    pub makeup_db: f32,
+}
+
+impl Default for CompressorConfig {
+    fn default() -> Self {
+        Self {
+            threshold_db: -18.0,
+            ratio: 4.0,
+            knee_db: 6.0,
+            attack_ms: 5.0,
+            release_ms: 100.0,
+            makeup_db: 0.0,
+        }
+    }
+}
+
+impl CompressorConfig {
+    /// The static output level for an input level, both in dB.
+    pub fn transfer(&self, level_db: f32) -> f32 {
+        let over = level_db - self.threshold_db;
+        let slope = 1.0 / self.ratio - 1.0;
+        if 2.0 * over < -self.knee_db {
+            level_db
+        } else if self.knee_db > 0.0 && 2.0 * over.abs() <= self.knee_db {
+            let x = over + self.knee_db / 2.0;
+            level_db + slope * x * x / (2.0 * self.knee_db)
+        } else {
+            self.threshold_db + over / self.ratio
+        }
+    }
+
+    fn is_valid(&self) -> bool {
+        self.ratio >= 1.0 && self.knee_db >= 0.0 && self.attack_ms >= 0.0 && self.release_ms >= 0.0
+    }
+}
+
+/// Parameters of the look-ahead limiter.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LimiterConfig {
+    /// The maximum output level in dBFS.
+    pub ceiling_db: f32,
+    /// How far ahead peaks are detected. Also the added latency.
+    pub lookahead_ms: f32,
+    pub release_ms: f32,
+    /// Detect inter-sample peaks with 4x oversampling.
+    pub true_peak: bool,
+}
+
+impl Default for LimiterConfig {
+    fn default() -> Self {
+        Self {
+            ceiling_db: -1.0,
+            lookahead_ms: 2.0,
+            release_ms: 50.0,
+            true_peak: true,
+        }
+    }
+}
+
+/// The operating mode of a [`Compressor`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DynamicsMode {
+    Compressor(CompressorConfig),
+    Limiter(LimiterConfig),
+}
+
+/// A fixed-capacity ring buffer.
+struct Ring<T: Copy, const N: usize> {
+    data: [T; N],
+    pos: usize,
+}
+
+impl<T: Copy, const N: usize> Ring<T, N> {
+    fn new(value: T) -> Self {
+        Self { data: [value; N], pos: 0 }
+    }
+
+    /// Stores `value` and returns the one stored `len` pushes ago.
+    #[inline]
+    fn push(&mut self, value: T, len: usize) -> T {
+        let old = self.data[self.pos];
+        self.data[self.pos] = value;
+        self.pos = if self.pos + 1 >= len { 0 } else { self.pos + 1 };
+        old
+    }
+
+    fn fill(&mut self, value: T) {
+        self.data = [value; N];
+        self.pos = 0;
+    }
+}
+
+/// Sliding-window minimum over the last `len` values (monotonic queue).
+struct MinHold {
+    values: [f32; MAX_LOOKAHEAD_FRAMES + 1],
+    stamps: [u32; MAX_LOOKAHEAD_FRAMES + 1],
+    head: usize,
+    count: usize,
+    now: u32,
+}
+
+impl MinHold {
+    fn new() -> Self {
+        Self {
+            values: [0.0; MAX_LOOKAHEAD_FRAMES + 1],
+            stamps: [0; MAX_LOOKAHEAD_FRAMES + 1],
+            head: 0,
+            count: 0,
+            now: 0,
+        }
+    }
+
+    fn clear(&mut self) {
+        self.head = 0;
+        self.count = 0;
+        self.now = 0;
+    }
+
+    #[inline]
+    fn push(&mut self, value: f32, len: usize) -> f32 {
+        const CAP: usize = MAX_LOOKAHEAD_FRAMES + 1;
+        // Drop values that left the window, then larger values that can never be the minimum.
+        while self.count > 0 && self.now.wrapping_sub(self.stamps[self.head]) >= len as u32 {
+            self.head = (self.head + 1) % CAP;
+            self.count -= 1;
+        }
+        while self.count > 0 && self.values[(self.head + self.count - 1) % CAP] >= value {
+            self.count -= 1;
+        }
+        let tail = (self.head + self.count) % CAP;
+        self.values[tail] = value;
+        self.stamps[tail] = self.now;
+        self.count += 1;
+        self.now = self.now.wrapping_add(1);
+        self.values[self.head]
+    }
+}
+
+/// An Element that compresses or limits the dynamic range of a signal in-place.
+pub struct Compressor {
+    mode: DynamicsMode,
+    info: Option<Info>,
+    codec: Option<SampleCodec>,
+    frames_per_process: u16,
+    /// Smoothed gain reduction in dB (compressor) or linear gain (limiter).
+    envelope: f32,
+    attack_coef: f32,
+    release_coef: f32,
+    makeup: f32,
+    ceiling: f32,
+    /// Limiter state.
+    lookahead: usize,
+    delay_len: usize,
+    /// The number of frames in the delay line that came from the input, up to `delay_len`.
+    delayed: usize,
+    delay: Ring<[f32; MAX_CHANNELS], { MAX_LOOKAHEAD_FRAMES + TP_TAPS }>,
+    hold: MinHold,
+    average: Ring<f32, MAX_LOOKAHEAD_FRAMES>,
+    average_sum: f32,
+    tp_table: [[f32; TP_TAPS]; TP_FACTOR],
+    tp_history: [[f32; TP_TAPS]; MAX_CHANNELS],
+}
+
+impl Compressor {
+    /// Creates a new compressor.
+    pub fn new(config: CompressorConfig, frames_per_process: u16) -> Self {
+        Self::new_with_mode(DynamicsMode::Compressor(config), frames_per_process)
+    }
+
+    /// Creates a new look-ahead limiter.
+    pub fn new_limiter(config: LimiterConfig, frames_per_process: u16) -> Self {
+        Self::new_with_mode(DynamicsMode::Limiter(config), frames_per_process)
+    }
+
+    pub fn new_with_mode(mode: DynamicsMode, frames_per_process: u16) -> Self {
+        Self {
+            mode,
+            info: None,
+            codec: None,
+            frames_per_process,
+            envelope: 0.0,
+            attack_coef: 0.0,
+            release_coef: 0.0,
+            makeup: 1.0,
+            ceiling: 1.0,
+            lookahead: 0,
+            delay_len: 1,
+            delayed: 0,
+            delay: Ring::new([0.0; MAX_CHANNELS]),
+            hold: MinHold::new(),
+            average: Ring::new(1.0),
+            average_sum: 0.0,
+            tp_table: [[0.0; TP_TAPS]; TP_FACTOR],
+            tp_history: [[0.0; TP_TAPS]; MAX_CHANNELS],
+        }
+    }
+
+    pub fn mode(&self) -> DynamicsMode {
+        self.mode
+    }
+
+    /// The current gain reduction in dB, 0 or negative.
+    pub fn gain_reduction_db(&self) -> f32 {
+        match self.mode {
+            DynamicsMode::Compressor(_) => self.envelope,
+            DynamicsMode::Limiter(_) => linear_to_db(self.envelope).min(0.0),
+        }
+    }
+
+    /// The latency added by the element in frames.
+    pub fn latency_frames(&self) -> usize {
+        match self.mode {
+            DynamicsMode::Compressor(_) => 0,
+            DynamicsMode::Limiter(_) => self.delay_len,
+        }
+    }
+
+    fn clear_state(&mut self) {
+        self.envelope = match self.mode {
+            DynamicsMode::Compressor(_) => 0.0,
+            DynamicsMode::Limiter(_) => 1.0,
+        };
+        self.delay.fill([0.0; MAX_CHANNELS]);
+        self.delayed = 0;
+        self.hold.clear();
+        self.average.fill(1.0);
+        self.average_sum = self.lookahead as f32;
+        self.tp_history = [[0.0; TP_TAPS]; MAX_CHANNELS];
+    }
+
+    /// Fills the true-peak interpolation filter: one windowed-sinc row per fractional phase.
+    fn build_tp_table(&mut self) {
+        let half = (TP_TAPS / 2) as f32;
+        for (phase, row) in self.tp_table.iter_mut().enumerate() {
+            let frac = phase as f32 / TP_FACTOR as f32;
+            for (k, coef) in row.iter_mut().enumerate() {
+                // Row `phase` interpolates between history taps `half - 1` and `half`.
+                let d = k as f32 - (half - 1.0) - frac;
+                let x = PI * d;
+                let sinc = if x.abs() < 1e-6 { 1.0 } else { sinf(x) / x };
+                let window = 0.5 + 0.5 * cosf(PI * d / half);
+                *coef = sinc * window;
+            }
+        }
+    }
+
+    /// Estimates the peak of `channels` channels of `frame`. With true-peak detection the
+    /// estimate covers the frame `TP_TAPS / 2` frames back and the inter-sample peaks after it.
+    #[inline]
+    fn detect(&mut self, frame: &[f32], channels: usize, true_peak: bool) -> f32 {
+        let mut peak = 0.0f32;
+        for (c, &x) in frame[..channels].iter().enumerate() {
+            if true_peak {
+                let history = &mut self.tp_history[c];
+                history.copy_within(1.., 0);
+                history[TP_TAPS - 1] = x;
+                for row in self.tp_table.iter() {
+                    let y: f32 = row.iter().zip(history.iter()).map(|(h, s)| h * s).sum();
+                    peak = peak.max(y.abs());
+                }
+            } else {
+                peak = peak.max(x.abs());
+            }
+        }
+        peak
+    }
+
+    /// Computes the compressor gain for one frame and updates the envelope.
+    #[inline]
+    fn compressor_gain(&mut self, config: &CompressorConfig, peak: f32) -> f32 {
+        let level = linear_to_db(peak);
+        let target = config.transfer(level) - level;
+        // Attack when more reduction is needed, release otherwise.
+        let coef = if target < self.envelope { self.attack_coef } else { self.release_coef };
+        self.envelope = target + coef * (self.envelope - target);
+        db_to_linear(self.envelope) * self.makeup
+    }
+
+    /// Computes the limiter gain for the frame leaving the delay line.
+    #[inline]
+    fn limiter_gain(&mut self, peak: f32) -> f32 {
+        let required = if peak > self.ceiling { self.ceiling / peak } else { 1.0 };
+        // Hold the lowest requirement over the look-ahead, then smooth it with a moving
+        // average of the same length, so the gain is fully down when the peak leaves the delay.
+        let held = self.hold.push(required, self.lookahead + 1);
+        let old = self.average.push(held, self.lookahead);
+        self.average_sum += held - old;
+        let smoothed = (self.average_sum / self.lookahead as f32).min(1.0);
+
+        self.envelope = if smoothed < self.envelope {
+            smoothed
+        } else {
+            smoothed + self.release_coef * (self.envelope - smoothed)
+        };
+        self.envelope
+    }
+
+    /// Compresses `payload` in place.
+    fn compress_frames(&mut self, config: &CompressorConfig, codec: SampleCodec, payload: &mut [u8], channels: usize) {
+        let bytes = codec.bytes_per_sample();
+        for frame_bytes in payload.chunks_exact_mut(bytes * channels) {
+            let mut frame = [0.0f32; MAX_CHANNELS];
+            for (value, sample) in frame.iter_mut().zip(frame_bytes.chunks_exact(bytes)) {
+                *value = codec.read_f32(sample);
+            }
+
+            let peak = self.detect(&frame, channels, false);
+            let gain = self.compressor_gain(config, peak);
+            for (value, sample) in frame.iter().zip(frame_bytes.chunks_exact_mut(bytes)) {
+                codec.write_f32(sample, value * gain);
+            }
+        }
+    }
+
+    /// Pushes one frame through the limiter and writes the frame leaving the delay line to
+    /// `out`, unless it is part of the initial silence. Returns the number of bytes written.
+    fn limit_frame(&mut self, config: &LimiterConfig, codec: SampleCodec, frame: &[f32; MAX_CHANNELS], channels: usize, out: &mut [u8]) -> usize {
+        let peak = self.detect(frame, channels, config.true_peak);
+        let gain = self.limiter_gain(peak);
+        let delayed = self.delay.push(*frame, self.delay_len);
+        if self.delayed < self.delay_len {
+            self.delayed += 1;
+            return 0;
+        }
+
+        let bytes = codec.bytes_per_sample();
+        for (value, sample) in delayed.iter().zip(out[..bytes * channels].chunks_exact_mut(bytes)) {
+            // Guards against rounding in the running average.
+            codec.write_f32(sample, (value * gain).clamp(-self.ceiling, self.ceiling));
+        }
+        bytes * channels
+    }
+
+    /// Limits `input` into `output`. With `is_last` the delayed frames are flushed as well.
+    /// Returns the number of bytes written.
+    fn limit_frames(&mut self, config: &LimiterConfig, codec: SampleCodec, input: &[u8], output: &mut [u8], channels: usize, is_last: bool) -> Result<usize, Error> {
+        let bytes = codec.bytes_per_sample();
+        let frame_bytes = bytes * channels;
+        let in_frames = input.len() / frame_bytes;
+        // The first `delay_len` frames pushed after a clear only move the initial silence out.
+        let tail_pushes = if is_last && self.delayed + in_frames > 0 { self.delay_len } else { 0 };
+        let out_frames = (in_frames + tail_pushes).saturating_sub(self.delay_len - self.delayed);
+        if out_frames * frame_bytes > output.len() {
+            return Err(Error::BufferFull);
+        }
+
+        let mut written = 0;
+        for frame_in in input.chunks_exact(frame_bytes) {
+            let mut frame = [0.0f32; MAX_CHANNELS];
+            for (value, sample) in frame.iter_mut().zip(frame_in.chunks_exact(bytes)) {
+                *value = codec.read_f32(sample);
+            }
+            written += self.limit_frame(config, codec, &frame, channels, &mut output[written..]);
+        }
+        if is_last && self.delayed > 0 {
+            // Push silence until every input frame has left the delay line.
+            for _ in 0..self.delay_len {
+                written += self.limit_frame(config, codec, &[0.0; MAX_CHANNELS], channels, &mut output[written..]);
+            }
+        }
+        Ok(written)
+    }
+}
+
+impl BaseElement for Compressor {
+    type Error = Error;
+    type Info = Info;
+
+    fn get_in_info(&self) -> Option<Info> {
+        self.info
+    }
+
+    fn get_out_info(&self) -> Option<Info> {
+        self.info
+    }
+
+    async fn initialize(
+        &mut self,
+        upstream_info: Option<Info>,
+    ) -> Result<PortRequirements, Self::Error> {
+        let info = upstream_info.ok_or(Error::InvalidParameter)?;
+        if !Gain::is_supported(&info) || info.channels as usize > MAX_CHANNELS {
+            return Err(Error::Unsupported);
+        }
+        self.codec = Some(SampleCodec::from_info(&info).ok_or(Error::Unsupported)?);
+
+        match self.mode {
+            DynamicsMode::Compressor(config) => {
+                if !config.is_valid() {
+                    return Err(Error::InvalidParameter);
+                }
+                self.attack_coef = time_coefficient(config.attack_ms, info.sample_rate);
+                self.release_coef = time_coefficient(config.release_ms, info.sample_rate);
+                self.makeup = db_to_linear(config.makeup_db);
+                self.lookahead = 0;
+                self.delay_len = 1;
+            }
+            DynamicsMode::Limiter(config) => {
+                let frames = (config.lookahead_ms * info.sample_rate as f32 / 1000.0) as usize;
+                if config.lookahead_ms < 0.0 || config.release_ms < 0.0 || frames > MAX_LOOKAHEAD_FRAMES {
+                    return Err(Error::InvalidParameter);
+                }
+                self.lookahead = frames.max(1);
+                // The true-peak detector reports a frame `TP_TAPS / 2` frames late.
+                let detector_delay = if config.true_peak { TP_TAPS / 2 } else { 0 };
+                self.delay_len = self.lookahead + detector_delay;
+                self.release_coef = time_coefficient(config.release_ms, info.sample_rate);
+                self.ceiling = db_to_linear(config.ceiling_db);
+                self.build_tp_table();
+            }
+        }
+        self.clear_state();
+        self.info = Some(info);
+
+        let bytes_per_frame = info.get_alignment_bytes() as u16;
+        let size = PayloadSize {
+            min: bytes_per_frame,
+            preferred: bytes_per_frame * self.frames_per_process,
+        };
+        match self.mode {
+            DynamicsMode::Compressor(_) => Ok(PortRequirements::new_in_place(size)),
+            DynamicsMode::Limiter(_) => {
+                // The last payload also carries the frames left in the delay line.
+                let with_tail = |frames: u16| ((frames as usize + self.delay_len) * bytes_per_frame as usize).min(u16::MAX as usize) as u16;
+                let mut requirements = PortRequirements::sink(size);
+                requirements.out = Some(PayloadSize {
+                    min: with_tail(1),
+                    preferred: with_tail(self.frames_per_process),
+                });
+                Ok(requirements)
+            }
+        }
+    }
+
+    fn available(&self) -> u32 {
+        u32::MAX
+    }
+
+    /// Clears the detector state. The frames held in the limiter's delay line are discarded.
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        self.clear_state();
+        Ok(())
+    }
+
+    async fn reset(&mut self) -> Result<(), Self::Error> {
+        self.info = None;
+        self.codec = None;
+        self.clear_state();
+        Ok(())
+    }
+
+    async fn process<'a, C, P, T>(
+        &mut self,
+        in_port: &mut InPort<'a, C>,
+        out_port: &mut OutPort<'a, P>,
+        in_place_port: &mut InPlacePort<'a, T>,
+    ) -> ProcessResult<Self::Error>
+    where
+        C: Consumer<'a>,
+        P: Producer<'a>,
+        T: Transformer<'a>,
+    {
+        let codec = self.codec.ok_or(Error::NotInitialized)?;
+        let channels = self.info.ok_or(Error::NotInitialized)?.channels as usize;
+
+        match (self.mode, in_place_port, in_port, out_port) {
+            (DynamicsMode::Compressor(config), InPlacePort::Transformer(transformer), _, _) => {
+                let mut payload = transformer.acquire_transform().await;
+                self.compress_frames(&config, codec, &mut payload, channels);
+                Ok(Fine)
+            }
+            (DynamicsMode::Limiter(config), _, InPort::Consumer(consumer), OutPort::Producer(producer)) => {
+                let in_payload = consumer.acquire_read().await;
+                let mut out_payload = producer.acquire_write().await;
+                let position = in_payload.metadata.position;
+                let is_last = matches!(position, Position::Last | Position::Single);
+
+                match self.limit_frames(&config, codec, &in_payload, &mut out_payload, channels, is_last) {
+                    Ok(written) => out_payload.set_valid_length(written),
+                    Err(err) => {
+                        out_payload.set_valid_length(0);
+                        return Err(err);
+                    }
+                }
+                out_payload.set_position(position);
+
+                if is_last {
+                    self.clear_state();
+                    Ok(Eof)
+                } else {
+                    Ok(Fine)
+                }
+            }
+            _ => Err(Error::Unsupported),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{run_chunks, TestSample};
+
+    /// Feeds `samples` through `element` in payloads of `chunk_frames` frames and returns the
+    /// output. The compressor runs in place, the limiter with separate payloads.
+    async fn run<S: TestSample>(element: &mut Compressor, info: Info, samples: &[S], chunk_frames: usize) -> Vec<S> {
+        let requirements = element.initialize(Some(info)).await.unwrap();
+        let out_size = (chunk_frames + element.latency_frames()) * info.get_alignment_bytes() as usize;
+        let chunk_samples = chunk_frames * info.channels as usize;
+        run_chunks(element, &requirements, samples, chunk_samples, out_size).await.0
+    }
+
+    async fn run_f32(element: &mut Compressor, info: Info, samples: &[f32]) -> Vec<f32> {
+        run(element, info, samples, samples.len() / info.channels as usize).await
+    }
+
+    #[test]
+    fn test_transfer_curve() {
+        let config = CompressorConfig { threshold_db: -20.0, ratio: 4.0, knee_db: 10.0, ..Default::default() };
+        assert_eq!(config.transfer(-40.0), -40.0);
+        assert_eq!(config.transfer(0.0), -15.0);
+        // Within the knee the curve is continuous at both ends.
+        assert!((config.transfer(-25.0) - -25.0).abs() < 1e-4);
+        assert!((config.transfer(-15.0) - -18.75).abs() < 1e-4);
+        assert!(config.transfer(-20.0) < -20.0);
+    }
+
+    #[tokio::test]
+    async fn test_compressor_linked_steady_state() {
+        let config = CompressorConfig {
+            threshold_db: -20.0,
+            ratio: 4.0,
+            knee_db: 0.0,
+            attack_ms: 1.0,
+            release_ms: 10.0,
+            makeup_db: 6.0,
+        };
+        let mut compressor = Compressor::new(config, 64);
+        // Left at 0 dBFS, right at -20 dBFS: both get the gain computed from the left channel.
+        let samples: Vec<f32> = (0..4800).flat_map(|_| [1.0, 0.1]).collect();
+        let out = run_f32(&mut compressor, Info::new_float(48000, 2, 32, None), &samples).await;
+
+        // 0 dB in -> -15 dB out, +6 dB makeup -> -9 dB, i.e. a gain of -9 dB on both channels.
+        let expected = db_to_linear(-9.0);
+        let last = &out[out.len() - 2..];
+        assert!((last[0] - expected).abs() < 1e-3, "{:?}", last);
+        assert!((last[1] - expected * 0.1).abs() < 1e-4, "{:?}", last);
+        assert!((compressor.gain_reduction_db() - -15.0).abs() < 1e-2);
+        // The attack is not instantaneous.
+        assert!(out[0] > last[0]);
+    }
+
+    #[tokio::test]
+    async fn test_limiter_never_exceeds_ceiling() {
+        let config = LimiterConfig { ceiling_db: -6.0, lookahead_ms: 1.0, release_ms: 20.0, true_peak: false };
+        let mut limiter = Compressor::new_limiter(config, 64);
+        let ceiling = db_to_linear(-6.0);
+
+        // Quiet signal with a sudden full-scale burst.
+        let samples: Vec<f32> = (0..2000)
+            .map(|i| {
+                let amplitude = if (1000..1100).contains(&i) { 1.0 } else { 0.1 };
+                amplitude * sinf(2.0 * PI * 1000.0 * i as f32 / 48000.0)
+            })
+            .collect();
+        let info = Info::new_float(48000, 1, 32, None);
+        let out = run_f32(&mut limiter, info, &samples).await;
+
+        // The delay is compensated: the output lines up with the input.
+        assert_eq!(limiter.latency_frames(), 48);
+        assert_eq!(out.len(), samples.len());
+        // The gain was already reduced before the burst: the output is not just clipped.
+        let burst = &out[1000..1100];
+        assert!(burst.iter().all(|s| s.abs() <= ceiling + 1e-6));
+        // The gain came down before the burst.
+        assert!(out[999].abs() < samples[999].abs());
+        // Quiet parts before the burst are untouched.
+        for i in 0..900 {
+            assert!((out[i] - samples[i]).abs() < 1e-6);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_true_peak_limiter_integer() {
+        // A full-scale sine at fs/4 with a 45 degree phase offset: every sample is at
+        // 0.707 of the true peak, which only the oversampling detector sees.
+        let config = LimiterConfig { ceiling_db: -1.0, true_peak: true, ..Default::default() };
+        let mut limiter = Compressor::new_limiter(config, 64);
+        let samples: Vec<i16> = (0..4000)
+            .map(|i| (32767.0 * sinf(PI / 2.0 * i as f32 + PI / 4.0)) as i16)
+            .collect();
+        let output = run(&mut limiter, Info::new(48000, 1, 16, None), &samples, samples.len()).await;
+        let out: Vec<f32> = output.iter().map(|&s| s as f32 / 32768.0).collect();
+
+        // The reconstructed peak (sample value / 0.707) stays at the ceiling.
+        let peak = out[3000..].iter().fold(0.0f32, |m, s| m.max(s.abs())) / core::f32::consts::FRAC_1_SQRT_2;
+        assert!(peak <= db_to_linear(-1.0) * 1.02, "{}", peak);
+        assert!(peak >= db_to_linear(-1.0) * 0.9, "{}", peak);
+    }
+
+    #[tokio::test]
+    async fn test_limiter_keeps_frame_count() {
+        let config = LimiterConfig { ceiling_db: -1.0, lookahead_ms: 1.0, release_ms: 20.0, true_peak: true };
+        let info = Info::new_float(48000, 2, 32, None);
+        // Below the ceiling the limiter passes the input through, delay compensated.
+        let samples: Vec<f32> = (0..2 * 1000).map(|i| 0.5 * sinf(i as f32 * 0.01)).collect();
+
+        for (frames, chunk_frames) in [(1000, 64), (1000, 1000), (10, 64)] {
+            let mut limiter = Compressor::new_limiter(config, 64);
+            let out = run(&mut limiter, info, &samples[..frames * 2], chunk_frames).await;
+            assert_eq!(out.len(), frames * 2);
+            assert!(out.iter().zip(&samples).all(|(o, s)| (o - s).abs() < 1e-6));
+        }
+    }
+}
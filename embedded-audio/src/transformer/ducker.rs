@@ -0,0 +1,336 @@
+//! Sidechain ducking of a background input by a priority input.
+//!
+//! A [`Ducker`] is a two-input [`Mixer`]: input 0 is the priority source (e.g. a voice prompt),
+//! input 1 the background (e.g. music). While the priority level is above the threshold, the
+//! background is attenuated by `depth_db`; once the priority source has been quiet for the hold
+//! time, the background returns to its own gain. The priority source is never attenuated.
+
+use embedded_audio_driver::databus::{Consumer, Producer, Transformer};
+use embedded_audio_driver::element::{BaseElement, ProcessResult};
+use embedded_audio_driver::info::Info;
+use embedded_audio_driver::port::{InPlacePort, InPort, OutPort, PortRequirements};
+use embedded_audio_driver::Error;
+
+use crate::sample::Q31_SCALE;
+use super::gain::{db_to_linear, linear_to_db};
+use super::mixer::{Mixer, MixerControl, DEFAULT_FIFO_SAMPLES};
+use super::{time_coefficient, MAX_CHANNELS};
+
+/// The release time of the priority level detector.
+const DETECTOR_RELEASE_MS: f32 = 10.0;
+
+/// The mixer input carrying the priority source.
+pub const PRIORITY_INPUT: usize = 0;
+/// The mixer input carrying the background source.
+pub const BACKGROUND_INPUT: usize = 1;
+
+/// Parameters of a [`Ducker`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DuckerConfig {
+    /// Priority level above which the background is ducked, in dBFS.
+    pub threshold_db: f32,
+    /// Attenuation of the background while ducked, in dB (e.g. -18.0).
+    pub depth_db: f32,
+    /// How fast the background is ducked once the priority level exceeds the threshold, in ms.
+    /// The gain moves toward full ducking with this time constant, covering about 63% of the
+    /// way in `attack_ms`. 0 ducks at once.
+    pub attack_ms: f32,
+    /// How long the priority level must stay below the threshold before the background recovers.
+    pub hold_ms: f32,
+    /// How fast the background recovers to its own gain after the hold time, in ms, as a time
+    /// constant like `attack_ms`. 0 recovers at once.
+    pub release_ms: f32,
+}
+
+impl Default for DuckerConfig {
+    fn default() -> Self {
+        Self {
+            threshold_db: -40.0,
+            depth_db: -18.0,
+            attack_ms: 10.0,
+            hold_ms: 200.0,
+            release_ms: 400.0,
+        }
+    }
+}
+
+impl DuckerConfig {
+    fn is_valid(&self) -> bool {
+        self.depth_db <= 0.0 && self.attack_ms >= 0.0 && self.hold_ms >= 0.0 && self.release_ms >= 0.0
+    }
+}
+
+/// Level detector and gain smoother driven by the priority input.
+struct Sidechain {
+    level: f32,
+    gain: f32,
+    ducking: bool,
+    depth: f32,
+    hold_frames: u32,
+    hold_remaining: u32,
+    detector_coef: f32,
+    attack_coef: f32,
+    release_coef: f32,
+}
+
+impl Sidechain {
+    fn clear(&mut self) {
+        self.level = 0.0;
+        self.gain = 1.0;
+        self.ducking = false;
+        self.hold_remaining = 0;
+    }
+
+    /// Updates the state with one priority frame and returns the background gain for it.
+    #[inline]
+    fn next_gain(&mut self, frame: &[i32; MAX_CHANNELS], threshold_db: f32) -> f32 {
+        let peak = frame.iter().map(|s| s.unsigned_abs()).max().unwrap_or(0) as f32 / Q31_SCALE;
+        self.level = if peak > self.level {
+            peak
+        } else {
+            peak + self.detector_coef * (self.level - peak)
+        };
+
+        if linear_to_db(self.level) >= threshold_db {
+            self.ducking = true;
+            self.hold_remaining = self.hold_frames;
+        } else if self.hold_remaining > 0 {
+            self.hold_remaining -= 1;
+        } else {
+            self.ducking = false;
+        }
+
+        let target = if self.ducking { self.depth } else { 1.0 };
+        let coef = if target < self.gain { self.attack_coef } else { self.release_coef };
+        self.gain = target + coef * (self.gain - target);
+        self.gain
+    }
+}
+
+/// An Element that mixes a priority and a background input, ducking the background while the
+/// priority input is active.
+pub struct Ducker<'c, const FIFO: usize = DEFAULT_FIFO_SAMPLES> {
+    mixer: Mixer<'c, 2, FIFO>,
+    config: DuckerConfig,
+    sidechain: Sidechain,
+}
+
+impl<'c, const FIFO: usize> Ducker<'c, FIFO> {
+    /// Creates a new ducker with both inputs at unity gain.
+    pub fn new(config: DuckerConfig, frames_per_process: u16) -> Self {
+        Self {
+            mixer: Mixer::new([1.0, 1.0], frames_per_process),
+            config,
+            sidechain: Sidechain {
+                level: 0.0,
+                gain: 1.0,
+                ducking: false,
+                depth: 1.0,
+                hold_frames: 0,
+                hold_remaining: 0,
+                detector_coef: 0.0,
+                attack_coef: 0.0,
+                release_coef: 0.0,
+            },
+        }
+    }
+
+    /// Attaches a mixer control handle, e.g. to change the gain of either input or to remove
+    /// the priority input between prompts.
+    pub fn with_control(mut self, control: &'c MixerControl) -> Self {
+        self.mixer = self.mixer.with_control(control);
+        self
+    }
+
+    pub fn config(&self) -> DuckerConfig {
+        self.config
+    }
+
+    /// Returns `true` while the priority input holds the background down.
+    pub fn is_ducking(&self) -> bool {
+        self.sidechain.ducking
+    }
+
+    /// The current attenuation of the background, in dB (positive while ducked).
+    pub fn gain_reduction_db(&self) -> f32 {
+        -linear_to_db(self.sidechain.gain)
+    }
+
+    /// Mixes one output payload from `inputs[PRIORITY_INPUT]` and `inputs[BACKGROUND_INPUT]`.
+    ///
+    /// Behaves like [`Mixer::process_inputs`]; either input may end or be removed on its own.
+    pub async fn process_inputs<'a, C, P>(
+        &mut self,
+        inputs: &mut [InPort<'a, C>],
+        out_port: &mut OutPort<'a, P>,
+    ) -> ProcessResult<Error>
+    where
+        C: Consumer<'a>,
+        P: Producer<'a>,
+    {
+        let sidechain = &mut self.sidechain;
+        let threshold_db = self.config.threshold_db;
+        self.mixer
+            .mix_inputs(inputs, out_port, |frames, gains| {
+                let duck = sidechain.next_gain(&frames[PRIORITY_INPUT], threshold_db);
+                gains[BACKGROUND_INPUT] = (gains[BACKGROUND_INPUT] as f32 * duck) as i32;
+            })
+            .await
+    }
+}
+
+impl<const FIFO: usize> BaseElement for Ducker<'_, FIFO> {
+    type Error = Error;
+    type Info = Info;
+
+    fn get_in_info(&self) -> Option<Info> {
+        self.mixer.get_in_info()
+    }
+
+    fn get_out_info(&self) -> Option<Info> {
+        self.mixer.get_out_info()
+    }
+
+    fn available(&self) -> u32 {
+        u32::MAX
+    }
+
+    /// Initializes the ducker with the `Info` shared by both inputs.
+    async fn initialize(
+        &mut self,
+        upstream_info: Option<Info>,
+    ) -> Result<PortRequirements, Self::Error> {
+        if !self.config.is_valid() {
+            return Err(Error::InvalidParameter);
+        }
+        let requirements = self.mixer.initialize(upstream_info).await?;
+
+        let rate = upstream_info.ok_or(Error::InvalidParameter)?.sample_rate;
+        let sidechain = &mut self.sidechain;
+        sidechain.depth = db_to_linear(self.config.depth_db);
+        sidechain.detector_coef = time_coefficient(DETECTOR_RELEASE_MS, rate);
+        sidechain.attack_coef = time_coefficient(self.config.attack_ms, rate);
+        sidechain.release_coef = time_coefficient(self.config.release_ms, rate);
+        sidechain.hold_frames = (self.config.hold_ms * rate as f32 / 1000.0) as u32;
+        sidechain.clear();
+        Ok(requirements)
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        self.sidechain.clear();
+        self.mixer.flush().await
+    }
+
+    async fn reset(&mut self) -> Result<(), Self::Error> {
+        self.sidechain.clear();
+        self.mixer.reset().await
+    }
+
+    /// Passes a single input through as the priority source. Use [`Ducker::process_inputs`]
+    /// to duck a background input.
+    async fn process<'a, C, P, T>(
+        &mut self,
+        in_port: &mut InPort<'a, C>,
+        out_port: &mut OutPort<'a, P>,
+        _inplace_port: &mut InPlacePort<'a, T>,
+    ) -> ProcessResult<Self::Error>
+    where
+        C: Consumer<'a>,
+        P: Producer<'a>,
+        T: Transformer<'a>,
+    {
+        self.process_inputs(core::slice::from_mut(in_port), out_port).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{new_slot, read_samples, write_samples};
+    use embedded_audio_driver::payload::Position;
+
+    #[tokio::test]
+    async fn test_duck_hold_and_release() {
+        let config = DuckerConfig {
+            threshold_db: -30.0,
+            depth_db: -20.0,
+            attack_ms: 0.0,
+            hold_ms: 10.0,
+            release_ms: 0.0,
+        };
+        let mut ducker = Ducker::<1024>::new(config, 512);
+        let requirements = ducker.initialize(Some(Info::new(8000, 1, 16, None))).await.unwrap();
+        let voice = new_slot(requirements.in_.unwrap());
+        let music = new_slot(requirements.in_.unwrap());
+        let out = new_slot(requirements.out.unwrap());
+        let mut inputs = [voice.in_port(), music.in_port()];
+
+        // No prompt: the music plays at its own gain.
+        write_samples::<i16>(&voice, &[0; 500], Position::First).await;
+        write_samples::<i16>(&music, &[1000; 500], Position::First).await;
+        ducker.process_inputs(&mut inputs, &mut out.out_port()).await.unwrap();
+        assert!(read_samples::<i16>(&out).await.0.iter().all(|&s| s == 1000));
+        assert!(!ducker.is_ducking());
+
+        // A prompt at -10 dBFS ducks the music by 20 dB right away.
+        write_samples::<i16>(&voice, &[10000; 500], Position::Middle).await;
+        write_samples::<i16>(&music, &[1000; 500], Position::Middle).await;
+        ducker.process_inputs(&mut inputs, &mut out.out_port()).await.unwrap();
+        assert!(read_samples::<i16>(&out).await.0.iter().all(|&s| (s - 10100).abs() <= 1));
+        assert!(ducker.is_ducking());
+        assert!((ducker.gain_reduction_db() - 20.0).abs() < 0.01);
+
+        // After the prompt, the music stays down for the hold time plus the detector release.
+        write_samples::<i16>(&voice, &[0; 500], Position::Middle).await;
+        write_samples::<i16>(&music, &[1000; 500], Position::Middle).await;
+        ducker.process_inputs(&mut inputs, &mut out.out_port()).await.unwrap();
+        let mixed = read_samples::<i16>(&out).await.0;
+        assert!(mixed[..80].iter().all(|&s| (s - 100).abs() <= 1));
+        assert!(mixed[400..].iter().all(|&s| s == 1000));
+        assert!(!ducker.is_ducking());
+    }
+
+    #[tokio::test]
+    async fn test_attack_and_release_ramps() {
+        let config = DuckerConfig {
+            threshold_db: -30.0,
+            depth_db: -20.0,
+            attack_ms: 5.0,
+            hold_ms: 0.0,
+            release_ms: 20.0,
+        };
+        let mut ducker = Ducker::<1024>::new(config, 512);
+        let requirements = ducker.initialize(Some(Info::new_float(8000, 1, 32, None))).await.unwrap();
+        let voice = new_slot(requirements.in_.unwrap());
+        let music = new_slot(requirements.in_.unwrap());
+        let out = new_slot(requirements.out.unwrap());
+
+        // A 200-frame prompt over constant music.
+        let prompt: Vec<f32> = (0..500).map(|i| if i < 200 { 0.5 } else { 0.0 }).collect();
+        write_samples::<f32>(&voice, &prompt, Position::Middle).await;
+        write_samples::<f32>(&music, &[0.25; 500], Position::Middle).await;
+        ducker.process_inputs(&mut [voice.in_port(), music.in_port()], &mut out.out_port()).await.unwrap();
+        let mixed = read_samples::<f32>(&out).await.0;
+
+        // The music fades down over the 40-frame attack...
+        assert!(mixed[0] > 0.5 + 0.25 * 0.95, "{}", mixed[0]);
+        assert!((mixed[199] - (0.5 + 0.25 * 0.106)).abs() < 0.002, "{}", mixed[199]);
+        // ...and is on its way back up once the detector falls below the threshold.
+        assert!(mixed[499] > 0.025 && mixed[499] < 0.25, "{}", mixed[499]);
+        assert!(mixed[499] > mixed[400]);
+        assert!(!ducker.is_ducking());
+    }
+
+    #[tokio::test]
+    async fn test_invalid_config() {
+        let config = DuckerConfig {
+            depth_db: 6.0,
+            ..Default::default()
+        };
+        let mut ducker = Ducker::<1024>::new(config, 64);
+        assert!(matches!(
+            ducker.initialize(Some(Info::new(48000, 2, 16, None))).await,
+            Err(Error::InvalidParameter)
+        ));
+    }
+}
@@ -0,0 +1,418 @@
+//! Fade-in and fade-out driven by the payload `Position`, Inplace Operation.
+//!
+//! The fade-in starts at every `First` (or `Single`) payload. The fade-out ends on the last
+//! frame of the stream: it is placed using `Info::num_frames` when known, otherwise it covers
+//! the end of the `Last` payload only. A `Last` payload also ends the fade-out of a stream
+//! cut short. After a `flush`, as done by a seek, the stream no longer starts at frame 0, so
+//! `Info::num_frames` is ignored until the next `First` payload that follows no flush. A [`FadeControl`] can also request a fade-out after which
+//! the stream is stopped: the payload is truncated at the end of the fade, marked `Last`, and
+//! `process` returns `Eof`. The next `First` payload starts a new stream and clears the stop.
+
+use core::f32::consts::FRAC_PI_2;
+use libm::{powf, sinf};
+
+use embedded_audio_driver::databus::{Consumer, Producer, Transformer};
+use embedded_audio_driver::element::{BaseElement, Eof, Fine, ProcessResult};
+use embedded_audio_driver::info::Info;
+use embedded_audio_driver::payload::Position;
+use embedded_audio_driver::port::{InPlacePort, InPort, OutPort, PayloadSize, PortRequirements};
+use embedded_audio_driver::Error;
+
+use crate::sample::SampleCodec;
+use crate::Signal;
+use super::Gain;
+
+/// The dynamic range of the exponential curve.
+const EXPONENTIAL_RANGE_DB: f32 = 60.0;
+
+/// The shape of a fade.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FadeCurve {
+    #[default]
+    Linear,
+    /// Linear in dB over 60 dB, perceived as an even fade.
+    Exponential,
+    /// Quarter sine, keeps the summed power constant when crossfading.
+    EqualPower,
+}
+
+impl FadeCurve {
+    /// The fade-in gain at `progress` in `[0, 1]`. Fade-outs use `1 - progress`.
+    pub fn gain(&self, progress: f32) -> f32 {
+        let p = progress.clamp(0.0, 1.0);
+        match self {
+            FadeCurve::Linear => p,
+            FadeCurve::Exponential => {
+                let floor = powf(10.0, -EXPONENTIAL_RANGE_DB / 20.0);
+                (powf(10.0, EXPONENTIAL_RANGE_DB * (p - 1.0) / 20.0) - floor) / (1.0 - floor)
+            }
+            FadeCurve::EqualPower => sinf(p * FRAC_PI_2),
+        }
+    }
+}
+
+/// A handle for stopping a running [`Fade`] with a fade-out from another task.
+pub struct FadeControl {
+    stop: Signal<()>,
+}
+
+impl FadeControl {
+    pub const fn new() -> Self {
+        Self { stop: Signal::new() }
+    }
+
+    /// Requests a fade-out, after which the stream ends.
+    pub fn fade_out_and_stop(&self) {
+        self.stop.signal(());
+    }
+}
+
+impl Default for FadeControl {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// An Element applying fade-in at the start and fade-out at the end of a stream.
+pub struct Fade<'c> {
+    curve: FadeCurve,
+    fade_in_ms: u32,
+    fade_out_ms: u32,
+    info: Option<Info>,
+    codec: Option<SampleCodec>,
+    control: Option<&'c FadeControl>,
+    fade_in_frames: u64,
+    fade_out_frames: u64,
+    /// Frames processed since the start of the stream.
+    position: u64,
+    /// Whether `position` counts from the first frame of the stream. Cleared by a flush.
+    from_start: bool,
+    /// Set by a flush until the next payload arrives.
+    flushed: bool,
+    /// Frames left in a requested fade-out-then-stop, if any.
+    stop_remaining: Option<u64>,
+    stopped: bool,
+    frames_per_process: u16,
+}
+
+impl<'c> Fade<'c> {
+    /// Creates a new fade element.
+    ///
+    /// # Arguments
+    ///
+    /// * `fade_in_ms` - The fade-in duration at the start of the stream. 0 disables it.
+    /// * `fade_out_ms` - The fade-out duration at the end of the stream or on stop.
+    /// * `curve` - The shape of both fades.
+    pub fn new(fade_in_ms: u32, fade_out_ms: u32, curve: FadeCurve, frames_per_process: u16) -> Self {
+        Self {
+            curve,
+            fade_in_ms,
+            fade_out_ms,
+            info: None,
+            codec: None,
+            control: None,
+            fade_in_frames: 0,
+            fade_out_frames: 0,
+            position: 0,
+            from_start: true,
+            flushed: false,
+            stop_remaining: None,
+            stopped: false,
+            frames_per_process,
+        }
+    }
+
+    /// Attaches a control handle for fade-out-then-stop requests.
+    pub fn with_control(mut self, control: &'c FadeControl) -> Self {
+        self.control = Some(control);
+        self
+    }
+
+    /// Starts a fade-out, after which the stream ends.
+    pub fn fade_out_and_stop(&mut self) {
+        if self.stop_remaining.is_none() && !self.stopped {
+            self.stop_remaining = Some(self.fade_out_frames);
+        }
+    }
+
+    /// Returns `true` once a fade-out-then-stop has completed.
+    pub fn is_stopped(&self) -> bool {
+        self.stopped
+    }
+
+    /// The gain for the frame at `position`, where the stream (or payload) is known to end
+    /// after `end` frames.
+    #[inline]
+    fn frame_gain(&self, position: u64, end: Option<u64>) -> f32 {
+        let mut gain = 1.0;
+        if position < self.fade_in_frames {
+            gain *= self.curve.gain(position as f32 / self.fade_in_frames as f32);
+        }
+        if let Some(end) = end {
+            let remaining = end.saturating_sub(position + 1);
+            if remaining < self.fade_out_frames {
+                gain *= self.curve.gain(remaining as f32 / self.fade_out_frames as f32);
+            }
+        }
+        gain
+    }
+
+    /// Applies the fades to the payload and returns the number of valid bytes.
+    fn apply(&mut self, codec: SampleCodec, payload: &mut [u8], channels: usize, is_last: bool) -> usize {
+        let bytes = codec.bytes_per_sample();
+        let frame_bytes = bytes * channels;
+        let frames = (payload.len() / frame_bytes) as u64;
+
+        // Prefer the stream length; fall back to the end of the `Last` payload, which may also
+        // end the stream early.
+        let total = self.info.and_then(|info| info.num_frames).filter(|_| self.from_start);
+        let end = match total {
+            Some(total) if is_last => Some(total.min(self.position + frames)),
+            Some(total) => Some(total),
+            None if is_last => Some(self.position + frames),
+            None => None,
+        };
+
+        let mut valid = payload.len();
+        for (i, frame) in payload.chunks_exact_mut(frame_bytes).enumerate() {
+            let mut gain = self.frame_gain(self.position, end);
+            if let Some(remaining) = self.stop_remaining {
+                if remaining == 0 {
+                    self.stopped = true;
+                    self.stop_remaining = None;
+                    valid = i * frame_bytes;
+                    break;
+                }
+                gain *= self.curve.gain((remaining - 1) as f32 / self.fade_out_frames as f32);
+                self.stop_remaining = Some(remaining - 1);
+            }
+
+            if gain < 1.0 {
+                for sample in frame.chunks_exact_mut(bytes) {
+                    let value = codec.read_f32(sample);
+                    codec.write_f32(sample, value * gain);
+                }
+            }
+            self.position += 1;
+        }
+        if self.stop_remaining == Some(0) {
+            self.stopped = true;
+            self.stop_remaining = None;
+        }
+        valid
+    }
+}
+
+impl BaseElement for Fade<'_> {
+    type Error = Error;
+    type Info = Info;
+
+    fn get_in_info(&self) -> Option<Info> {
+        self.info
+    }
+
+    fn get_out_info(&self) -> Option<Info> {
+        self.info
+    }
+
+    async fn initialize(
+        &mut self,
+        upstream_info: Option<Info>,
+    ) -> Result<PortRequirements, Self::Error> {
+        let info = upstream_info.ok_or(Error::InvalidParameter)?;
+        if !Gain::is_supported(&info) {
+            return Err(Error::Unsupported);
+        }
+        self.codec = Some(SampleCodec::from_info(&info).ok_or(Error::Unsupported)?);
+        self.fade_in_frames = self.fade_in_ms as u64 * info.sample_rate as u64 / 1000;
+        self.fade_out_frames = self.fade_out_ms as u64 * info.sample_rate as u64 / 1000;
+        self.position = 0;
+        self.from_start = true;
+        self.flushed = false;
+        self.stop_remaining = None;
+        self.stopped = false;
+        self.info = Some(info);
+
+        let bytes_per_frame = info.get_alignment_bytes() as u16;
+        Ok(PortRequirements::new_in_place(PayloadSize {
+            min: bytes_per_frame,
+            preferred: bytes_per_frame * self.frames_per_process,
+        }))
+    }
+
+    fn available(&self) -> u32 {
+        u32::MAX
+    }
+
+    /// Restarts the fades. The stream continues elsewhere, so its length no longer marks the
+    /// end of the fade-out.
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        self.position = 0;
+        self.from_start = false;
+        self.flushed = true;
+        self.stop_remaining = None;
+        self.stopped = false;
+        Ok(())
+    }
+
+    async fn reset(&mut self) -> Result<(), Self::Error> {
+        self.info = None;
+        self.codec = None;
+        self.flush().await?;
+        self.from_start = true;
+        self.flushed = false;
+        Ok(())
+    }
+
+    async fn process<'a, C, P, T>(
+        &mut self,
+        _in_port: &mut InPort<'a, C>,
+        _out_port: &mut OutPort<'a, P>,
+        in_place_port: &mut InPlacePort<'a, T>,
+    ) -> ProcessResult<Self::Error>
+    where
+        C: Consumer<'a>,
+        P: Producer<'a>,
+        T: Transformer<'a>,
+    {
+        if let InPlacePort::Transformer(transformer) = in_place_port {
+            let mut payload = transformer.acquire_transform().await;
+            let codec = self.codec.ok_or(Error::NotInitialized)?;
+            let channels = self.info.ok_or(Error::NotInitialized)?.channels as usize;
+
+            let position = payload.metadata.position;
+            if matches!(position, Position::First | Position::Single) {
+                // The first payload after a flush continues the stream where a seek left it.
+                self.from_start = !self.flushed;
+                self.position = 0;
+                self.stop_remaining = None;
+                self.stopped = false;
+            }
+            self.flushed = false;
+            if self.control.is_some_and(|control| control.stop.try_take().is_some()) {
+                self.fade_out_and_stop();
+            }
+            if self.stopped {
+                payload.set_valid_length(0);
+                payload.set_position(Position::Last);
+                return Ok(Eof);
+            }
+
+            let is_last = matches!(position, Position::Last | Position::Single);
+            let valid = self.apply(codec, &mut payload, channels, is_last);
+            if self.stopped {
+                payload.set_valid_length(valid);
+                payload.set_position(if position == Position::First { Position::Single } else { Position::Last });
+                return Ok(Eof);
+            }
+            Ok(Fine)
+        } else {
+            Err(Error::Unsupported)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::run_in_place;
+
+    #[test]
+    fn test_curves() {
+        for curve in [FadeCurve::Linear, FadeCurve::Exponential, FadeCurve::EqualPower] {
+            assert_eq!(curve.gain(0.0), 0.0);
+            assert!((curve.gain(1.0) - 1.0).abs() < 1e-6);
+            assert!(curve.gain(0.3) < curve.gain(0.6));
+        }
+        assert!((FadeCurve::EqualPower.gain(0.5) - core::f32::consts::FRAC_1_SQRT_2).abs() < 1e-6);
+        assert!(FadeCurve::Exponential.gain(0.5) < 0.05);
+    }
+
+    #[tokio::test]
+    async fn test_fade_in_and_out_with_known_length() {
+        // 10 ms at 1 kHz is 10 frames; the stream is 30 frames in three payloads.
+        let mut fade = Fade::new(10, 10, FadeCurve::Linear, 64);
+        fade.initialize(Some(Info::new(1000, 1, 16, Some(30)))).await.unwrap();
+
+        let (first, _, _) = run_in_place(&mut fade, &[1000i16; 10], Position::First).await;
+        assert_eq!(first, [0, 100, 200, 300, 400, 500, 600, 700, 800, 900]);
+        // The fade-out starts in the middle payload, before the `Last` payload arrives.
+        let (middle, _, _) = run_in_place(&mut fade, &[1000i16; 12], Position::Middle).await;
+        assert_eq!(middle[..10], [1000; 10]);
+        assert_eq!(middle[10..], [900, 800]);
+        let (last, _, eof) = run_in_place(&mut fade, &[1000i16; 8], Position::Last).await;
+        assert_eq!(last, [700, 600, 500, 400, 300, 200, 100, 0]);
+        assert!(!eof);
+
+        // A new stream fades in again.
+        let (first, _, _) = run_in_place(&mut fade, &[1000i16; 2], Position::First).await;
+        assert_eq!(first, [0, 100]);
+    }
+
+    #[tokio::test]
+    async fn test_fade_out_in_last_payload_without_length() {
+        let mut fade = Fade::new(0, 4, FadeCurve::Linear, 64);
+        fade.initialize(Some(Info::new(1000, 1, 16, None))).await.unwrap();
+
+        let (out, _, _) = run_in_place(&mut fade, &[1000i16; 6], Position::First).await;
+        assert_eq!(out, [1000; 6]);
+        let (out, _, _) = run_in_place(&mut fade, &[1000i16; 6], Position::Last).await;
+        assert_eq!(out, [1000, 1000, 750, 500, 250, 0]);
+    }
+
+    #[tokio::test]
+    async fn test_fade_out_and_stop() {
+        let control = FadeControl::new();
+        let mut fade = Fade::new(0, 4, FadeCurve::Linear, 64).with_control(&control);
+        fade.initialize(Some(Info::new(1000, 1, 16, None))).await.unwrap();
+
+        let (out, _, eof) = run_in_place(&mut fade, &[1000i16; 3], Position::First).await;
+        assert_eq!(out, [1000; 3]);
+        assert!(!eof);
+
+        control.fade_out_and_stop();
+        let (out, position, eof) = run_in_place(&mut fade, &[1000i16; 3], Position::Middle).await;
+        assert_eq!(out, [750, 500, 250]);
+        assert!(!eof && position == Position::Middle);
+
+        // The fade ends in this payload: it is cut after the last faded frame.
+        let (out, position, eof) = run_in_place(&mut fade, &[1000i16; 3], Position::Middle).await;
+        assert_eq!(out, [0]);
+        assert!(eof && position == Position::Last);
+        assert!(fade.is_stopped());
+
+        // A new stream plays again.
+        let (out, position, eof) = run_in_place(&mut fade, &[1000i16; 3], Position::First).await;
+        assert_eq!(out, [1000; 3]);
+        assert!(!eof && position == Position::First);
+        assert!(!fade.is_stopped());
+    }
+
+    #[tokio::test]
+    async fn test_fade_out_after_seek() {
+        let mut fade = Fade::new(0, 4, FadeCurve::Linear, 64);
+        fade.initialize(Some(Info::new(1000, 1, 16, Some(30)))).await.unwrap();
+        let (out, _, _) = run_in_place(&mut fade, &[1000i16; 10], Position::First).await;
+        assert_eq!(out, [1000; 10]);
+
+        // A seek to frame 20 flushes the fade; the rest of the stream arrives as one payload.
+        fade.flush().await.unwrap();
+        let (out, _, _) = run_in_place(&mut fade, &[1000i16; 10], Position::Single).await;
+        assert_eq!(out, [1000, 1000, 1000, 1000, 1000, 1000, 750, 500, 250, 0]);
+
+        // Starting over without a flush uses the stream length again.
+        let (out, _, _) = run_in_place(&mut fade, &[1000i16; 27], Position::First).await;
+        assert_eq!(out[24..], [1000, 1000, 750]);
+    }
+
+    #[tokio::test]
+    async fn test_fade_out_on_truncated_stream() {
+        // The stream claims 30 frames but ends after 10.
+        let mut fade = Fade::new(0, 4, FadeCurve::Linear, 64);
+        fade.initialize(Some(Info::new(1000, 1, 16, Some(30)))).await.unwrap();
+        let (out, _, _) = run_in_place(&mut fade, &[1000i16; 6], Position::First).await;
+        assert_eq!(out, [1000; 6]);
+        let (out, _, _) = run_in_place(&mut fade, &[1000i16; 4], Position::Last).await;
+        assert_eq!(out, [750, 500, 250, 0]);
+    }
+}
@@ -0,0 +1,332 @@
+//! A sample format converter.
+//!
+//! Converts between unsigned 8-bit, signed 16-bit, packed 24-bit, 24-bit in 32,
+//! signed 32-bit and 32-bit float samples. The input may be any format readable by
+//! [`SampleCodec`], the output is always little-endian.
+//!
+//! Integer conversions are done on MSB-aligned `i32` samples, so widening is exact and
+//! narrowing rounds to nearest, optionally with TPDF dither.
+
+use embedded_audio_driver::databus::{Consumer, Producer, Transformer};
+use embedded_audio_driver::element::{BaseElement, Eof, Fine, ProcessResult};
+use embedded_audio_driver::info::{Endianness, Info, SampleEncoding};
+use embedded_audio_driver::payload::Position;
+use embedded_audio_driver::port::{InPlacePort, InPort, OutPort, PayloadSize, PortRequirements};
+use embedded_audio_driver::Error;
+
+use crate::sample::{SampleCodec, Q31_SCALE};
+
+/// The output sample formats of a [`FormatConverter`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TargetFormat {
+    /// Unsigned 8-bit, silence at 128.
+    U8,
+    /// Signed 16-bit.
+    I16,
+    /// Signed 24-bit packed in 3 bytes.
+    I24,
+    /// Signed 24-bit MSB-aligned in a 4-byte container (low byte zero).
+    I24In32,
+    /// Signed 32-bit.
+    I32,
+    /// 32-bit IEEE float.
+    F32,
+}
+
+impl TargetFormat {
+    /// Returns `info` with its sample layout replaced by this format.
+    pub fn apply(self, mut info: Info) -> Info {
+        let (bits, valid_bits, encoding) = match self {
+            TargetFormat::U8 => (8, 8, SampleEncoding::Unsigned),
+            TargetFormat::I16 => (16, 16, SampleEncoding::Signed),
+            TargetFormat::I24 => (24, 24, SampleEncoding::Signed),
+            TargetFormat::I24In32 => (32, 24, SampleEncoding::Signed),
+            TargetFormat::I32 => (32, 32, SampleEncoding::Signed),
+            TargetFormat::F32 => (32, 32, SampleEncoding::Float),
+        };
+        info.bits_per_sample = bits;
+        info.valid_bits_per_sample = valid_bits;
+        info.encoding = encoding;
+        info.endianness = Endianness::Little;
+        info
+    }
+}
+
+/// Dithering applied when the output has less resolution than the input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dither {
+    /// Plain rounding to the nearest output value.
+    None,
+    /// Triangular probability density dither of ±1 output LSB.
+    Tpdf,
+}
+
+/// A small xorshift generator, good enough for dither noise.
+struct XorShift32(u32);
+
+impl XorShift32 {
+    #[inline]
+    fn next(&mut self) -> u32 {
+        let mut x = self.0;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.0 = x;
+        x
+    }
+}
+
+/// An Element that converts samples from the upstream format to a [`TargetFormat`].
+///
+/// The number of frames is unchanged, so each input payload produces one output payload.
+/// The output databus must hold as many frames as the input databus.
+pub struct FormatConverter {
+    target: TargetFormat,
+    dither: Dither,
+    in_info: Option<Info>,
+    out_info: Option<Info>,
+    in_codec: Option<SampleCodec>,
+    out_codec: Option<SampleCodec>,
+    /// Output quantization step on the MSB-aligned `i32` scale, `None` for float output.
+    out_lsb: Option<i64>,
+    dither_active: bool,
+    rng: XorShift32,
+    frames_per_process: u16,
+}
+
+impl FormatConverter {
+    /// Creates a new format converter.
+    ///
+    /// # Arguments
+    ///
+    /// * `target` - The output sample format.
+    /// * `dither` - The dither to apply when reducing bit depth.
+    pub fn new(target: TargetFormat, dither: Dither, frames_per_process: u16) -> Self {
+        Self {
+            target,
+            dither,
+            in_info: None,
+            out_info: None,
+            in_codec: None,
+            out_codec: None,
+            out_lsb: None,
+            dither_active: false,
+            rng: XorShift32(0x2545_F491),
+            frames_per_process,
+        }
+    }
+
+    /// Quantizes an MSB-aligned sample to the output resolution.
+    #[inline]
+    fn quantize(&mut self, value: i64, lsb: i64) -> i32 {
+        let mut value = value;
+        if self.dither_active {
+            // The difference of two uniform values is triangular in (-lsb, lsb).
+            let r1 = (self.rng.next() as i64) & (lsb - 1);
+            let r2 = (self.rng.next() as i64) & (lsb - 1);
+            value += r1 - r2;
+        }
+        value = (value + (lsb >> 1)) & !(lsb - 1);
+        value.clamp(i32::MIN as i64, i32::MAX as i64 & !(lsb - 1)) as i32
+    }
+
+    /// Converts `samples` samples from `src` into `dest`.
+    fn convert(&mut self, src: &[u8], dest: &mut [u8], samples: usize) {
+        let in_codec = self.in_codec.unwrap();
+        let out_codec = self.out_codec.unwrap();
+        let in_bytes = in_codec.bytes_per_sample();
+        let out_bytes = out_codec.bytes_per_sample();
+
+        let src = src.chunks_exact(in_bytes);
+        let dest = dest.chunks_exact_mut(out_bytes);
+        for (src, dest) in src.zip(dest).take(samples) {
+            match self.out_lsb {
+                Some(lsb) => {
+                    let value = if in_codec.is_float() {
+                        // Keep the sub-LSB part of float input for rounding and dither.
+                        (in_codec.read_f32(src) as f64 * Q31_SCALE as f64) as i64
+                    } else {
+                        in_codec.read_i32(src) as i64
+                    };
+                    let quantized = self.quantize(value, lsb);
+                    out_codec.write_i32(dest, quantized);
+                }
+                None => out_codec.write_f32(dest, in_codec.read_f32(src)),
+            }
+        }
+    }
+}
+
+impl BaseElement for FormatConverter {
+    type Error = Error;
+    type Info = Info;
+
+    fn get_in_info(&self) -> Option<Info> {
+        self.in_info
+    }
+
+    fn get_out_info(&self) -> Option<Info> {
+        self.out_info
+    }
+
+    fn available(&self) -> u32 {
+        u32::MAX
+    }
+
+    async fn initialize(
+        &mut self,
+        upstream_info: Option<Info>,
+    ) -> Result<PortRequirements, Self::Error> {
+        let in_info = upstream_info.ok_or(Error::InvalidParameter)?;
+        if !in_info.vaild() {
+            return Err(Error::InvalidParameter);
+        }
+        let out_info = self.target.apply(in_info);
+
+        let in_codec = SampleCodec::from_info(&in_info).ok_or(Error::Unsupported)?;
+        let out_codec = SampleCodec::from_info(&out_info).ok_or(Error::Unsupported)?;
+
+        // Dither only when integer output has less resolution than the input.
+        let in_resolution = if in_info.is_float() { 32 } else { in_info.valid_bits_per_sample };
+        let reduces_depth = !out_info.is_float()
+            && (in_info.is_float() || out_info.valid_bits_per_sample < in_resolution);
+        self.out_lsb = if out_info.is_float() {
+            None
+        } else {
+            Some(1i64 << (32 - out_info.valid_bits_per_sample))
+        };
+        self.dither_active = self.dither == Dither::Tpdf && reduces_depth;
+
+        self.in_codec = Some(in_codec);
+        self.out_codec = Some(out_codec);
+        self.in_info = Some(in_info);
+        self.out_info = Some(out_info);
+
+        let in_frame = in_info.get_alignment_bytes() as u16;
+        let out_frame = out_info.get_alignment_bytes() as u16;
+        let mut requirements = PortRequirements::sink(PayloadSize {
+            min: in_frame,
+            preferred: in_frame * self.frames_per_process,
+        });
+        requirements.out = Some(PayloadSize {
+            min: out_frame,
+            preferred: out_frame * self.frames_per_process,
+        });
+        Ok(requirements)
+    }
+
+    async fn reset(&mut self) -> Result<(), Self::Error> {
+        self.in_info = None;
+        self.out_info = None;
+        self.in_codec = None;
+        self.out_codec = None;
+        self.out_lsb = None;
+        self.dither_active = false;
+        Ok(())
+    }
+
+    async fn process<'a, C, P, T>(
+        &mut self,
+        in_port: &mut InPort<'a, C>,
+        out_port: &mut OutPort<'a, P>,
+        _inplace_port: &mut InPlacePort<'a, T>,
+    ) -> ProcessResult<Self::Error>
+    where
+        C: Consumer<'a>,
+        P: Producer<'a>,
+        T: Transformer<'a>,
+    {
+        let (InPort::Consumer(consumer), OutPort::Producer(producer)) = (in_port, out_port) else {
+            return Err(Error::Unsupported);
+        };
+        let in_info = self.in_info.ok_or(Error::NotInitialized)?;
+        let out_info = self.out_info.ok_or(Error::NotInitialized)?;
+
+        let in_payload = consumer.acquire_read().await;
+        let mut out_payload = producer.acquire_write().await;
+
+        let frames = in_payload.len() / in_info.get_alignment_bytes() as usize;
+        let out_len = frames * out_info.get_alignment_bytes() as usize;
+        if out_len > out_payload.len() {
+            out_payload.set_valid_length(0);
+            return Err(Error::BufferFull);
+        }
+
+        self.convert(&in_payload, &mut out_payload, frames * in_info.channels as usize);
+
+        let position = in_payload.metadata.position;
+        out_payload.set_valid_length(out_len);
+        out_payload.set_position(position);
+
+        match position {
+            Position::Last | Position::Single => Ok(Eof),
+            _ => Ok(Fine),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::run_single;
+
+    #[tokio::test]
+    async fn test_i24_to_i16_rounding() {
+        let mut converter = FormatConverter::new(TargetFormat::I16, Dither::None, 64);
+        let input = [
+            0x80, 0x34, 0x12, // 0x123480 rounds up to 0x1235
+            0x7F, 0x34, 0x12, // 0x12347F rounds down to 0x1234
+            0xFF, 0xFF, 0x7F, // Full scale must not overflow
+            0x00, 0x00, 0x80, // Negative full scale
+        ];
+        let output = run_single(&mut converter, Info::new(48000, 2, 24, None), &input).await;
+
+        let samples: Vec<i16> = output.chunks_exact(2).map(|c| i16::from_le_bytes([c[0], c[1]])).collect();
+        assert_eq!(samples, [0x1235, 0x1234, i16::MAX, i16::MIN]);
+
+        let out_info = converter.get_out_info().unwrap();
+        assert_eq!(out_info.bits_per_sample, 16);
+        assert_eq!(out_info.channels, 2);
+        assert_eq!(out_info.encoding, SampleEncoding::Signed);
+    }
+
+    #[tokio::test]
+    async fn test_widening_and_float() {
+        // u8 -> I24In32: exact and MSB-aligned
+        let mut converter = FormatConverter::new(TargetFormat::I24In32, Dither::Tpdf, 64);
+        let output = run_single(&mut converter, Info::new(48000, 1, 8, None), &[128, 255, 0]).await;
+        let samples: Vec<i32> = output.chunks_exact(4).map(|c| i32::from_le_bytes(c.try_into().unwrap())).collect();
+        assert_eq!(samples, [0, 127 << 24, i32::MIN]);
+        let out_info = converter.get_out_info().unwrap();
+        assert_eq!((out_info.bits_per_sample, out_info.valid_bits_per_sample), (32, 24));
+
+        // i16 -> f32
+        let mut converter = FormatConverter::new(TargetFormat::F32, Dither::None, 64);
+        let input: Vec<u8> = [16384i16, -32768].iter().flat_map(|s| s.to_le_bytes()).collect();
+        let output = run_single(&mut converter, Info::new(48000, 1, 16, None), &input).await;
+        let samples: Vec<f32> = output.chunks_exact(4).map(|c| f32::from_le_bytes(c.try_into().unwrap())).collect();
+        assert_eq!(samples, [0.5, -1.0]);
+        assert!(converter.get_out_info().unwrap().is_float());
+
+        // f32 -> u8, clamped
+        let mut converter = FormatConverter::new(TargetFormat::U8, Dither::None, 64);
+        let input: Vec<u8> = [0.0f32, 1.5, -1.0].iter().flat_map(|s| s.to_le_bytes()).collect();
+        let output = run_single(&mut converter, Info::new_float(48000, 1, 32, None), &input).await;
+        assert_eq!(output, [128, 255, 0]);
+    }
+
+    #[tokio::test]
+    async fn test_tpdf_dither() {
+        // A constant between two output codes must be spread over neighbouring codes
+        // while staying within ±1 LSB and keeping its mean.
+        let mut converter = FormatConverter::new(TargetFormat::I16, Dither::Tpdf, 1024);
+        let input: Vec<u8> = (0..1024).flat_map(|_| (1000i32 << 16 | 0x4000).to_le_bytes()).collect();
+        let output = run_single(&mut converter, Info::new(48000, 1, 32, None), &input).await;
+
+        let samples: Vec<i16> = output.chunks_exact(2).map(|c| i16::from_le_bytes([c[0], c[1]])).collect();
+        assert!(samples.iter().all(|s| (999..=1002).contains(s)));
+        assert!(samples.iter().any(|s| *s != samples[0]), "dither should vary the output");
+        let mean = samples.iter().map(|s| *s as f32).sum::<f32>() / samples.len() as f32;
+        assert!((mean - 1000.25).abs() < 0.1, "mean {} should stay close to the input", mean);
+    }
+}
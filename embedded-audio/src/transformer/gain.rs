@@ -1,6 +1,8 @@
 //! An audio gain processor, Inplace Operation. 
 //! currently using Q16.16 data type, with manual SIMD on x86 or aarch64 (aarch64 untested).
-//! 
+//!
+//! The gain can be changed while running, either directly or from another task through a
+//! [`GainControl`]. Changes are ramped linearly per frame over a configurable time.
 //! 
 //! TODO: Optimize code to let the compiler auto-vectorize as much as possible.
 //! TODO: Use DSP instructions (e.g., CMSIS-DSP) on Cortex-M and RISC-V embedded platforms.
@@ -10,24 +12,116 @@ use core::arch::aarch64::*;
 #[cfg(target_arch = "x86_64")]
 use core::arch::x86_64::*;
 use core::mem;
+use libm::{log10f, powf};
 
 use embedded_audio_driver::databus::{Consumer, Producer, Transformer};
 use embedded_audio_driver::element::{BaseElement, Fine, ProcessResult};
-use embedded_audio_driver::info::Info;
+use embedded_audio_driver::info::{Endianness, Info, SampleEncoding};
 use embedded_audio_driver::port::{InPlacePort, InPort, OutPort, PayloadSize, PortRequirements};
 use embedded_audio_driver::Error;
 
+use crate::Channel;
+
 // Fixed-point gain representation (Q16.16 format)
 type FixedGain = i32;
 
 const FIXED_POINT_SHIFT: u32 = 16;
 const FIXED_POINT_ONE: FixedGain = 1 << FIXED_POINT_SHIFT;
 
+/// Extra fractional bits of the ramp accumulator, so slow ramps don't stall.
+const RAMP_SHIFT: u32 = 16;
+
+/// The default duration of a gain ramp.
+pub const DEFAULT_RAMP_MS: u32 = 20;
+
+/// The number of commands a [`GainControl`] can queue.
+pub const GAIN_COMMAND_QUEUE: usize = 8;
+
 #[inline]
 fn float_to_fixed(gain: f32) -> FixedGain {
     (gain * FIXED_POINT_ONE as f32) as FixedGain
 }
 
+/// Converts a gain in dB to a linear factor.
+#[inline]
+pub fn db_to_linear(db: f32) -> f32 {
+    powf(10.0, db / 20.0)
+}
+
+/// Converts a linear factor to dB. Values at or below -120 dB return -120 dB.
+#[inline]
+pub fn linear_to_db(value: f32) -> f32 {
+    if value <= 1e-6 {
+        -120.0
+    } else {
+        20.0 * log10f(value)
+    }
+}
+
+/// A command sent to a [`Gain`] through its [`GainControl`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GainCommand {
+    /// Sets the target gain as a linear factor.
+    Linear(f32),
+    /// Sets the target gain in dB.
+    Db(f32),
+    /// Ramps to silence, keeping the target gain for `Unmute`.
+    Mute,
+    /// Ramps back to the target gain.
+    Unmute,
+    /// Sets the duration of subsequent ramps in milliseconds. 0 switches instantly.
+    RampTime(u32),
+}
+
+/// A handle for changing the gain of a running [`Gain`] element from another task.
+///
+/// Commands are queued and applied at the start of the next `process` call.
+pub struct GainControl {
+    commands: Channel<GainCommand, GAIN_COMMAND_QUEUE>,
+}
+
+impl GainControl {
+    pub const fn new() -> Self {
+        Self {
+            commands: Channel::new(),
+        }
+    }
+
+    /// Queues a command, waiting if the queue is full.
+    pub async fn send(&self, command: GainCommand) {
+        self.commands.send(command).await
+    }
+
+    /// Queues a command, returning `Error::BufferFull` if the queue is full.
+    pub fn try_send(&self, command: GainCommand) -> Result<(), Error> {
+        self.commands.try_send(command).map_err(|_| Error::BufferFull)
+    }
+
+    /// Sets the target gain as a linear factor.
+    pub async fn set_gain(&self, gain: f32) {
+        self.send(GainCommand::Linear(gain)).await
+    }
+
+    /// Sets the target gain in dB.
+    pub async fn set_gain_db(&self, db: f32) {
+        self.send(GainCommand::Db(db)).await
+    }
+
+    pub async fn mute(&self) {
+        self.send(GainCommand::Mute).await
+    }
+
+    pub async fn unmute(&self) {
+        self.send(GainCommand::Unmute).await
+    }
+}
+
+impl Default for GainControl {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 /// A trait to abstract over different audio sample formats for processing.
 trait Sample: Sized + Copy {
     /// The number of bytes this sample type occupies.
@@ -54,6 +148,21 @@ impl Sample for i32 {
     }
 }
 
+impl Sample for i8 {
+    #[inline]
+    fn apply_gain_fixed(self, gain: FixedGain) -> Self {
+        let result = (self as i64 * gain as i64) >> FIXED_POINT_SHIFT;
+        result.clamp(i8::MIN as i64, i8::MAX as i64) as i8
+    }
+}
+
+impl Sample for f32 {
+    #[inline]
+    fn apply_gain_fixed(self, gain: FixedGain) -> Self {
+        self * (gain as f32 / FIXED_POINT_ONE as f32)
+    }
+}
+
 impl Sample for u8 {
     #[inline]
     fn apply_gain_fixed(self, gain: FixedGain) -> Self {
@@ -96,9 +205,19 @@ fn process_24bit_fixed(payload: &mut [u8], gain: FixedGain) {
 }
 
 /// An Element that applies gain to an audio signal in-place.
-pub struct Gain {
+pub struct Gain<'c> {
     info: Option<Info>,
     fixed_gain: FixedGain,
+    control: Option<&'c GainControl>,
+    /// The gain to ramp to when not muted, linear.
+    target_gain: f32,
+    muted: bool,
+    ramp_ms: u32,
+    /// Ramp state: the current gain in Q16.(16 + RAMP_SHIFT) and its per-frame step.
+    ramp_gain: i64,
+    ramp_step: i64,
+    ramp_target: FixedGain,
+    ramp_remaining: u32,
     bytes_per_frame: u8,
     frames_per_process: u16,
     #[cfg(target_arch = "x86_64")]
@@ -107,7 +226,7 @@ pub struct Gain {
     use_neon: bool,
 }
 
-impl Gain {
+impl<'c> Gain<'c> {
     /// Creates a new Gain element.
     ///
     /// # Arguments
@@ -127,6 +246,14 @@ impl Gain {
         Self {
             info: None,
             fixed_gain: gain,
+            control: None,
+            target_gain: gain as f32 / FIXED_POINT_ONE as f32,
+            muted: false,
+            ramp_ms: DEFAULT_RAMP_MS,
+            ramp_gain: 0,
+            ramp_step: 0,
+            ramp_target: gain,
+            ramp_remaining: 0,
             bytes_per_frame: 0,
             frames_per_process,
             #[cfg(target_arch = "x86_64")]
@@ -136,6 +263,140 @@ impl Gain {
         }
     }
 
+    /// Attaches a control handle. Queued commands are applied on every `process` call.
+    pub fn with_control(mut self, control: &'c GainControl) -> Self {
+        self.control = Some(control);
+        self
+    }
+
+    /// Sets the duration of gain ramps in milliseconds. 0 switches instantly.
+    pub fn with_ramp_time(mut self, ramp_ms: u32) -> Self {
+        self.ramp_ms = ramp_ms;
+        self
+    }
+
+    /// Sets the target gain as a linear factor, ramping to it once initialized.
+    pub fn set_gain(&mut self, gain: f32) {
+        self.target_gain = gain;
+        self.update_target();
+    }
+
+    /// Sets the target gain in dB, ramping to it once initialized.
+    pub fn set_gain_db(&mut self, db: f32) {
+        self.set_gain(db_to_linear(db));
+    }
+
+    /// Mutes or unmutes, ramping like a gain change.
+    pub fn set_muted(&mut self, muted: bool) {
+        self.muted = muted;
+        self.update_target();
+    }
+
+    pub fn set_ramp_time(&mut self, ramp_ms: u32) {
+        self.ramp_ms = ramp_ms;
+    }
+
+    /// The target gain as a linear factor, regardless of mute.
+    pub fn gain(&self) -> f32 {
+        self.target_gain
+    }
+
+    pub fn is_muted(&self) -> bool {
+        self.muted
+    }
+
+    /// Returns `true` while a gain change is being ramped.
+    pub fn is_ramping(&self) -> bool {
+        self.ramp_remaining > 0
+    }
+
+    fn apply_command(&mut self, command: GainCommand) {
+        match command {
+            GainCommand::Linear(gain) => self.set_gain(gain),
+            GainCommand::Db(db) => self.set_gain_db(db),
+            GainCommand::Mute => self.set_muted(true),
+            GainCommand::Unmute => self.set_muted(false),
+            GainCommand::RampTime(ramp_ms) => self.set_ramp_time(ramp_ms),
+        }
+    }
+
+    /// Starts a ramp from the current gain to the effective target.
+    fn update_target(&mut self) {
+        let target = if self.muted { 0 } else { float_to_fixed(self.target_gain) };
+        let frames = match self.info {
+            Some(info) => (self.ramp_ms as u64 * info.sample_rate as u64 / 1000) as u32,
+            None => 0,
+        };
+
+        let current = if self.ramp_remaining > 0 {
+            self.ramp_gain
+        } else {
+            (self.fixed_gain as i64) << RAMP_SHIFT
+        };
+        if frames == 0 || (target == self.fixed_gain && self.ramp_remaining == 0) {
+            self.fixed_gain = target;
+            self.ramp_remaining = 0;
+            return;
+        }
+        self.ramp_gain = current;
+        self.ramp_step = (((target as i64) << RAMP_SHIFT) - current) / frames as i64;
+        self.ramp_remaining = frames;
+        self.ramp_target = target;
+    }
+
+    /// Applies `gain` to every sample of `data`.
+    fn apply_gain(&self, data: &mut [u8], info: &Info, gain: FixedGain) -> Result<(), Error> {
+        match (info.encoding, info.bits_per_sample) {
+            // Use SIMD or NEON only on 16-bit as a demo, since this is `embedded-audio`.
+            (SampleEncoding::Signed, 16) => {
+                #[cfg(target_arch = "x86_64")]
+                {
+                    if self.use_sse2 {
+                        unsafe {
+                            process_simd_i16_sse2(data, gain);
+                        }
+                    } else {
+                        process_scalar::<i16>(data, gain);
+                    }
+                }
+                #[cfg(target_arch = "aarch64")]
+                {
+                    if self.use_neon {
+                        unsafe {
+                            process_simd_i16_neon(data, gain);
+                        }
+                    } else {
+                        process_scalar::<i16>(data, gain);
+                    }
+                }
+                #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+                {
+                    process_scalar::<i16>(data, gain);
+                }
+            }
+            (SampleEncoding::Signed, 32) => process_scalar::<i32>(data, gain),
+            (SampleEncoding::Signed, 24) => process_24bit_fixed(data, gain),
+            (SampleEncoding::Signed, 8) => process_scalar::<i8>(data, gain),
+            (SampleEncoding::Unsigned, 8) => process_scalar::<u8>(data, gain),
+            (SampleEncoding::Float, 32) => process_scalar::<f32>(data, gain),
+            _ => return Err(Error::Unsupported),
+        }
+        Ok(())
+    }
+
+    /// Returns `true` if `info` describes a sample format this element can process:
+    /// little-endian unsigned 8-bit, signed 8/16/24/32-bit, or 32-bit float.
+    pub fn is_supported(info: &Info) -> bool {
+        if info.endianness != Endianness::Little {
+            return false;
+        }
+        match info.encoding {
+            SampleEncoding::Unsigned => info.bits_per_sample == 8,
+            SampleEncoding::Signed => [8, 16, 24, 32].contains(&info.bits_per_sample),
+            SampleEncoding::Float => info.bits_per_sample == 32,
+        }
+    }
+
     /// Returns whether SIMD instructions are being used
     #[cfg(target_arch = "x86_64")]
     pub fn is_using_simd(&self) -> bool {
@@ -153,7 +414,7 @@ impl Gain {
     }
 }
 
-impl BaseElement for Gain {
+impl BaseElement for Gain<'_> {
     type Error = Error;
     type Info = Info;
 
@@ -171,10 +432,11 @@ impl BaseElement for Gain {
     ) -> Result<PortRequirements, Self::Error>
     {
         let info = upstream_info.ok_or(Error::InvalidParameter)?;
-        if ![8, 16, 24, 32].contains(&info.bits_per_sample) {
+        if !Self::is_supported(&info) {
             return Err(Error::Unsupported)
         }
         self.info = Some(info);
+        self.ramp_remaining = 0;
         
         self.bytes_per_frame = self.info.unwrap().get_alignment_bytes();
         
@@ -203,39 +465,26 @@ impl BaseElement for Gain {
             let mut payload = transformer.acquire_transform().await;
             let info = self.info.ok_or(Error::NotInitialized)?;
 
-            match info.bits_per_sample {
-                // Use SIMD or NEON only on 16-bit as a demo, since this is `embedded-audio`.
-                16 => {
-                    #[cfg(target_arch = "x86_64")]
-                    {
-                        if self.use_sse2 {
-                            unsafe {
-                                process_simd_i16_sse2(&mut payload, self.fixed_gain);
-                            }
-                        } else {
-                            process_scalar::<i16>(&mut payload, self.fixed_gain);
-                        }
-                    }
-                    #[cfg(target_arch = "aarch64")]
-                    {
-                        if self.use_neon {
-                            unsafe {
-                                process_simd_i16_neon(&mut payload, self.fixed_gain);
-                            }
-                        } else {
-                            process_scalar::<i16>(&mut payload, self.fixed_gain);
-                        }
-                    }
-                    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
-                    {
-                        process_scalar::<i16>(&mut payload, self.fixed_gain);
-                    }
+            if let Some(control) = self.control {
+                while let Ok(command) = control.commands.try_receive() {
+                    self.apply_command(command);
+                }
+            }
+
+            let bytes_per_frame = self.bytes_per_frame as usize;
+            let ramp_frames = (payload.len() / bytes_per_frame).min(self.ramp_remaining as usize);
+            let (ramp, rest) = payload.split_at_mut(ramp_frames * bytes_per_frame);
+            for frame in ramp.chunks_exact_mut(bytes_per_frame) {
+                self.ramp_remaining -= 1;
+                if self.ramp_remaining == 0 {
+                    self.fixed_gain = self.ramp_target;
+                } else {
+                    self.ramp_gain += self.ramp_step;
+                    self.fixed_gain = (self.ramp_gain >> RAMP_SHIFT) as FixedGain;
                 }
-                32 => process_scalar::<i32>(&mut payload, self.fixed_gain),
-                8 => process_scalar::<u8>(&mut payload, self.fixed_gain),
-                24 => process_24bit_fixed(&mut payload, self.fixed_gain),
-                _ => return Err(Error::Unsupported),
+                self.apply_gain(frame, &info, self.fixed_gain)?;
             }
+            self.apply_gain(rest, &info, self.fixed_gain)?;
 
             Ok(Fine)
         } else {
@@ -318,6 +567,8 @@ unsafe fn process_simd_i16_neon(payload: &mut [u8], gain: FixedGain) {
 mod tests {
     use super::*;
     use crate::databus::slot::HeapSlot;
+    use crate::test_util::run_in_place;
+    use embedded_audio_driver::payload::Position;
     use embedded_audio_driver::{
         databus::{Operation, Databus}, info::Info, port::{InPort, OutPort}
     };
@@ -480,4 +731,82 @@ mod tests {
         assert!(gain.get_out_info().is_some());
         assert_eq!(gain.get_out_info().unwrap(), info);
     }
+
+    #[tokio::test]
+    async fn test_gain_sample_formats() {
+        // 32-bit float is scaled, not treated as a 32-bit integer.
+        let info = Info::new_float(48000, 1, 32, None);
+        let mut gain = Gain::new(0.5, 64);
+        let requirements = gain.initialize(Some(info)).await.unwrap();
+
+        let mut slot = HeapSlot::new_heap(8);
+        slot.register(Operation::InPlace, requirements.in_place.unwrap());
+        slot.register(Operation::Produce, requirements.in_place.unwrap());
+        slot.register(Operation::Consume, requirements.in_place.unwrap());
+        {
+            let mut p = slot.acquire_write().await;
+            p[0..4].copy_from_slice(&0.8f32.to_le_bytes());
+            p[4..8].copy_from_slice(&(-0.25f32).to_le_bytes());
+            p.set_valid_length(8);
+        }
+
+        let mut in_port = InPort::new_none();
+        let mut out_port = OutPort::new_none();
+        let mut in_place_port = slot.in_place_port();
+        gain.process(&mut in_port, &mut out_port, &mut in_place_port).await.unwrap();
+
+        let r = slot.acquire_read().await;
+        assert_eq!(f32::from_le_bytes(r[0..4].try_into().unwrap()), 0.4);
+        assert_eq!(f32::from_le_bytes(r[4..8].try_into().unwrap()), -0.125);
+
+        // Unsupported layouts are rejected during initialization.
+        let mut big_endian = Info::new(48000, 1, 16, None);
+        big_endian.set_endianness(Endianness::Big);
+        assert!(matches!(Gain::new(1.0, 64).initialize(Some(big_endian)).await, Err(Error::Unsupported)));
+
+        let mut unsigned_16 = Info::new(48000, 1, 16, None);
+        unsigned_16.set_encoding(SampleEncoding::Unsigned);
+        assert!(matches!(Gain::new(1.0, 64).initialize(Some(unsigned_16)).await, Err(Error::Unsupported)));
+    }
+
+    #[tokio::test]
+    async fn test_control_ramp_and_mute() {
+        let control = GainControl::new();
+        // 10 ms at 1 kHz is a 10 frame ramp
+        let mut gain = Gain::new(1.0, 64).with_control(&control).with_ramp_time(10);
+        gain.initialize(Some(Info::new(1000, 1, 16, None))).await.unwrap();
+
+        control.mute().await;
+        let out = run_in_place(&mut gain, &[10000i16; 16], Position::Middle).await.0;
+        assert!(out.windows(2).all(|w| w[1] <= w[0]), "{:?}", out);
+        assert!(out[0] < 10000 && out[0] > 8500);
+        assert_eq!(out[9..], [0; 7]);
+        assert!(gain.is_muted() && !gain.is_ramping());
+
+        // Commands are applied in order: the gain change is kept while muted.
+        control.try_send(GainCommand::Db(-6.0206)).unwrap();
+        control.try_send(GainCommand::RampTime(0)).unwrap();
+        control.try_send(GainCommand::Unmute).unwrap();
+        let out = run_in_place(&mut gain, &[10000i16; 4], Position::Middle).await.0;
+        assert!(out.iter().all(|s| (s - 5000).abs() <= 1), "{:?}", out);
+        assert!((gain.gain() - 0.5).abs() < 1e-4);
+
+        // A ramp spans several payloads.
+        gain.set_ramp_time(10);
+        gain.set_gain(1.5);
+        let first = run_in_place(&mut gain, &[10000i16; 4], Position::Middle).await.0;
+        assert!(gain.is_ramping());
+        let second = run_in_place(&mut gain, &[10000i16; 8], Position::Middle).await.0;
+        assert!(first.iter().chain(&second).collect::<Vec<_>>().windows(2).all(|w| w[1] >= w[0]));
+        assert_eq!(second[5..], [15000; 3]);
+    }
+
+    #[tokio::test]
+    async fn test_control_queue_full() {
+        let control = GainControl::new();
+        for _ in 0..GAIN_COMMAND_QUEUE {
+            control.try_send(GainCommand::Linear(1.0)).unwrap();
+        }
+        assert!(matches!(control.try_send(GainCommand::Mute), Err(Error::BufferFull)));
+    }
 }
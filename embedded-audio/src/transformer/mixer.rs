@@ -0,0 +1,529 @@
+//! A multi-input mixer.
+//!
+//! [`Mixer::process_inputs`] reads one payload from every connected input that is running low,
+//! queues its samples in a per-input FIFO, and writes the weighted sum of all inputs to the
+//! output. Inputs may deliver payloads of different sizes: the output only advances as far as
+//! every running input has data. An input that reached `Last` keeps contributing its queued
+//! samples, then silence, and is disconnected once drained. The output ends when all inputs
+//! have finished.
+//!
+//! Samples are mixed as MSB-aligned `i32` with Q16.16 gains and saturated to full scale.
+//! All inputs must carry the `Info` the mixer was initialized with.
+
+use embedded_audio_driver::databus::{Consumer, Producer, Transformer};
+use embedded_audio_driver::element::{BaseElement, Eof, Fine, ProcessResult};
+use embedded_audio_driver::info::Info;
+use embedded_audio_driver::payload::Position;
+use embedded_audio_driver::port::{InPlacePort, InPort, OutPort, PayloadSize, PortRequirements};
+use embedded_audio_driver::Error;
+
+use crate::sample::SampleCodec;
+use crate::Channel;
+use super::{Gain, MAX_CHANNELS};
+
+const FIXED_POINT_SHIFT: u32 = 16;
+
+/// The default FIFO size per input, in samples.
+pub const DEFAULT_FIFO_SAMPLES: usize = 4096;
+
+/// The number of commands a [`MixerControl`] can queue.
+pub const MIXER_COMMAND_QUEUE: usize = 8;
+
+#[inline]
+fn gain_to_fixed(gain: f32) -> i32 {
+    (gain * (1 << FIXED_POINT_SHIFT) as f32) as i32
+}
+
+/// A command sent to a [`Mixer`] through its [`MixerControl`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MixerCommand {
+    /// Connects `input` with the given linear gain. Its FIFO starts empty.
+    Add { input: usize, gain: f32 },
+    /// Disconnects `input` and drops its queued samples.
+    Remove(usize),
+    /// Changes the linear gain of `input`.
+    SetGain { input: usize, gain: f32 },
+}
+
+/// A handle for changing the inputs of a running [`Mixer`] from another task.
+///
+/// Commands are applied at the start of the next `process_inputs` call.
+pub struct MixerControl {
+    commands: Channel<MixerCommand, MIXER_COMMAND_QUEUE>,
+}
+
+impl MixerControl {
+    pub const fn new() -> Self {
+        Self {
+            commands: Channel::new(),
+        }
+    }
+
+    /// Queues a command, waiting if the queue is full.
+    pub async fn send(&self, command: MixerCommand) {
+        self.commands.send(command).await
+    }
+
+    /// Queues a command, returning `Error::BufferFull` if the queue is full.
+    pub fn try_send(&self, command: MixerCommand) -> Result<(), Error> {
+        self.commands.try_send(command).map_err(|_| Error::BufferFull)
+    }
+
+    pub async fn add_input(&self, input: usize, gain: f32) {
+        self.send(MixerCommand::Add { input, gain }).await
+    }
+
+    pub async fn remove_input(&self, input: usize) {
+        self.send(MixerCommand::Remove(input)).await
+    }
+
+    pub async fn set_gain(&self, input: usize, gain: f32) {
+        self.send(MixerCommand::SetGain { input, gain }).await
+    }
+}
+
+impl Default for MixerControl {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A fixed-capacity sample FIFO.
+pub(crate) struct SampleFifo<const CAP: usize> {
+    data: [i32; CAP],
+    head: usize,
+    len: usize,
+}
+
+impl<const CAP: usize> SampleFifo<CAP> {
+    pub(crate) const fn new() -> Self {
+        Self { data: [0; CAP], head: 0, len: 0 }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    pub(crate) fn free(&self) -> usize {
+        CAP - self.len
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.head = 0;
+        self.len = 0;
+    }
+
+    #[inline]
+    pub(crate) fn push(&mut self, value: i32) {
+        let tail = (self.head + self.len) % CAP;
+        self.data[tail] = value;
+        self.len += 1;
+    }
+
+    /// Pops the oldest sample, or returns silence if empty.
+    #[inline]
+    pub(crate) fn pop(&mut self) -> i32 {
+        if self.len == 0 {
+            return 0;
+        }
+        let value = self.data[self.head];
+        self.head = (self.head + 1) % CAP;
+        self.len -= 1;
+        value
+    }
+}
+
+struct MixerInput<const FIFO: usize> {
+    connected: bool,
+    eof: bool,
+    gain: i32,
+    /// The largest payload read from this input so far, in samples.
+    max_payload: usize,
+    fifo: SampleFifo<FIFO>,
+}
+
+impl<const FIFO: usize> MixerInput<FIFO> {
+    fn connect(&mut self, gain: f32) {
+        self.connected = true;
+        self.eof = false;
+        self.gain = gain_to_fixed(gain);
+        self.fifo.clear();
+    }
+
+    fn disconnect(&mut self) {
+        self.connected = false;
+        self.fifo.clear();
+    }
+}
+
+/// An Element that sums up to `N` inputs with per-input gain.
+pub struct Mixer<'c, const N: usize, const FIFO: usize = DEFAULT_FIFO_SAMPLES> {
+    inputs: [MixerInput<FIFO>; N],
+    control: Option<&'c MixerControl>,
+    info: Option<Info>,
+    codec: Option<SampleCodec>,
+    started: bool,
+    frames_per_process: u16,
+}
+
+impl<'c, const N: usize, const FIFO: usize> Mixer<'c, N, FIFO> {
+    /// Creates a new mixer with every input connected.
+    ///
+    /// # Arguments
+    ///
+    /// * `gains` - The initial linear gain of each input.
+    pub fn new(gains: [f32; N], frames_per_process: u16) -> Self {
+        Self {
+            inputs: gains.map(|gain| MixerInput {
+                connected: true,
+                eof: false,
+                gain: gain_to_fixed(gain),
+                max_payload: 0,
+                fifo: SampleFifo::new(),
+            }),
+            control: None,
+            info: None,
+            codec: None,
+            started: false,
+            frames_per_process,
+        }
+    }
+
+    /// Attaches a control handle. Queued commands are applied on every `process_inputs` call.
+    pub fn with_control(mut self, control: &'c MixerControl) -> Self {
+        self.control = Some(control);
+        self
+    }
+
+    /// Returns `true` if an upstream with `info` can be connected.
+    pub fn accepts(&self, info: &Info) -> bool {
+        self.info.is_some_and(|own| own.is_same_format(info))
+    }
+
+    pub fn is_connected(&self, input: usize) -> bool {
+        self.inputs.get(input).is_some_and(|input| input.connected)
+    }
+
+    /// Applies a command directly. Returns `Error::InvalidParameter` for an unknown input.
+    pub fn apply_command(&mut self, command: MixerCommand) -> Result<(), Error> {
+        match command {
+            MixerCommand::Add { input, gain } => {
+                self.inputs.get_mut(input).ok_or(Error::InvalidParameter)?.connect(gain)
+            }
+            MixerCommand::Remove(input) => self.inputs.get_mut(input).ok_or(Error::InvalidParameter)?.disconnect(),
+            MixerCommand::SetGain { input, gain } => {
+                self.inputs.get_mut(input).ok_or(Error::InvalidParameter)?.gain = gain_to_fixed(gain)
+            }
+        }
+        Ok(())
+    }
+
+    /// Mixes the inputs in `inputs` into one output payload.
+    ///
+    /// `inputs[i]` feeds mixer input `i`; entries beyond `N` are ignored. `InPort::None` entries
+    /// are not read, so a connected input without a port holds the output back until it is
+    /// removed. Returns `Eof` once every input has finished.
+    pub async fn process_inputs<'a, C, P>(
+        &mut self,
+        inputs: &mut [InPort<'a, C>],
+        out_port: &mut OutPort<'a, P>,
+    ) -> ProcessResult<Error>
+    where
+        C: Consumer<'a>,
+        P: Producer<'a>,
+    {
+        self.mix_inputs(inputs, out_port, |_, _| {}).await
+    }
+
+    /// Like [`Mixer::process_inputs`], but calls `frame_gains` before mixing each frame with the
+    /// frame of every input (silence for disconnected ones) and the Q16.16 gains about to be
+    /// applied, which it may change for this frame.
+    pub(crate) async fn mix_inputs<'a, C, P, F>(
+        &mut self,
+        inputs: &mut [InPort<'a, C>],
+        out_port: &mut OutPort<'a, P>,
+        mut frame_gains: F,
+    ) -> ProcessResult<Error>
+    where
+        C: Consumer<'a>,
+        P: Producer<'a>,
+        F: FnMut(&[[i32; MAX_CHANNELS]; N], &mut [i32; N]),
+    {
+        let OutPort::Producer(producer) = out_port else {
+            return Err(Error::Unsupported);
+        };
+        let info = self.info.ok_or(Error::NotInitialized)?;
+        let codec = self.codec.ok_or(Error::NotInitialized)?;
+        let channels = info.channels as usize;
+        let bytes = codec.bytes_per_sample();
+
+        if let Some(control) = self.control {
+            while let Ok(command) = control.commands.try_receive() {
+                if self.apply_command(command).is_err() {
+                    warn!("Mixer: ignoring command for unknown input");
+                }
+            }
+        }
+
+        // Top up every running input that cannot fill a whole output payload, as long as its FIFO
+        // has room for the largest payload it has delivered. Otherwise the queued samples are
+        // mixed first and the input is read on a later call.
+        let low_water = self.frames_per_process as usize * channels;
+        for (input, port) in self.inputs.iter_mut().zip(inputs.iter_mut()) {
+            let InPort::Consumer(consumer) = port else {
+                continue;
+            };
+            if !input.connected
+                || input.eof
+                || input.fifo.len() >= low_water
+                || input.fifo.free() < input.max_payload
+            {
+                continue;
+            }
+            let payload = consumer.acquire_read().await;
+            let samples = payload.len() / bytes;
+            if samples > input.fifo.free() {
+                warn!("Mixer: input payload larger than the FIFO space, dropping it");
+                return Err(Error::BufferFull);
+            }
+            input.max_payload = input.max_payload.max(samples);
+            for sample in payload.chunks_exact(bytes) {
+                input.fifo.push(codec.read_i32(sample));
+            }
+            if matches!(payload.metadata.position, Position::Last | Position::Single) {
+                input.eof = true;
+            }
+        }
+
+        let mut out_payload = producer.acquire_write().await;
+        let capacity = out_payload.len() / (bytes * channels);
+
+        // Advance as far as every running input allows; finished inputs are padded with silence.
+        let running = self.inputs.iter().filter(|input| input.connected && !input.eof);
+        let frames = match running.map(|input| input.fifo.len() / channels).min() {
+            Some(frames) => frames,
+            None => self
+                .inputs
+                .iter()
+                .filter(|input| input.connected)
+                .map(|input| input.fifo.len().div_ceil(channels))
+                .max()
+                .unwrap_or(0),
+        }
+        .min(capacity);
+
+        let mut frame = [[0i32; MAX_CHANNELS]; N];
+        let mut gains = [0i32; N];
+        for out_frame in out_payload.chunks_exact_mut(bytes * channels).take(frames) {
+            for ((input, samples), gain) in self.inputs.iter_mut().zip(frame.iter_mut()).zip(gains.iter_mut()) {
+                for sample in samples[..channels].iter_mut() {
+                    *sample = if input.connected { input.fifo.pop() } else { 0 };
+                }
+                *gain = if input.connected { input.gain } else { 0 };
+            }
+            frame_gains(&frame, &mut gains);
+
+            for (c, sample) in out_frame.chunks_exact_mut(bytes).enumerate() {
+                let acc: i64 = frame.iter().zip(gains.iter()).map(|(s, g)| s[c] as i64 * *g as i64).sum();
+                let value = (acc >> FIXED_POINT_SHIFT).clamp(i32::MIN as i64, i32::MAX as i64);
+                codec.write_i32(sample, value as i32);
+            }
+        }
+
+        for input in self.inputs.iter_mut() {
+            if input.connected && input.eof && input.fifo.len() == 0 {
+                input.disconnect();
+            }
+        }
+        let finished = self.inputs.iter().all(|input| !input.connected);
+
+        out_payload.set_valid_length(frames * channels * bytes);
+        out_payload.set_position(match (self.started, finished) {
+            (false, false) => Position::First,
+            (true, false) => Position::Middle,
+            (false, true) => Position::Single,
+            (true, true) => Position::Last,
+        });
+        self.started = !finished;
+
+        if finished {
+            Ok(Eof)
+        } else {
+            Ok(Fine)
+        }
+    }
+}
+
+impl<const N: usize, const FIFO: usize> BaseElement for Mixer<'_, N, FIFO> {
+    type Error = Error;
+    type Info = Info;
+
+    fn get_in_info(&self) -> Option<Info> {
+        self.info
+    }
+
+    fn get_out_info(&self) -> Option<Info> {
+        self.info
+    }
+
+    fn available(&self) -> u32 {
+        u32::MAX
+    }
+
+    /// Initializes the mixer with the `Info` shared by all inputs.
+    async fn initialize(
+        &mut self,
+        upstream_info: Option<Info>,
+    ) -> Result<PortRequirements, Self::Error> {
+        let info = upstream_info.ok_or(Error::InvalidParameter)?;
+        if !Gain::is_supported(&info) || info.channels as usize > MAX_CHANNELS {
+            return Err(Error::Unsupported);
+        }
+        // Each FIFO must take one payload on top of the low-water mark.
+        let low_water = self.frames_per_process as usize * info.channels as usize;
+        if FIFO < 2 * low_water {
+            return Err(Error::InvalidParameter);
+        }
+        self.codec = Some(SampleCodec::from_info(&info).ok_or(Error::Unsupported)?);
+        self.info = Some(info);
+        self.started = false;
+        self.inputs.iter_mut().for_each(|input| {
+            input.eof = false;
+            input.max_payload = low_water;
+            input.fifo.clear();
+        });
+
+        let bytes_per_frame = info.get_alignment_bytes() as u16;
+        let size = PayloadSize {
+            min: bytes_per_frame,
+            preferred: bytes_per_frame * self.frames_per_process,
+        };
+        let mut requirements = PortRequirements::sink(size);
+        requirements.out = Some(size);
+        Ok(requirements)
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        self.inputs.iter_mut().for_each(|input| input.fifo.clear());
+        Ok(())
+    }
+
+    async fn reset(&mut self) -> Result<(), Self::Error> {
+        self.info = None;
+        self.codec = None;
+        self.started = false;
+        self.flush().await
+    }
+
+    /// Mixes a single input. Use [`Mixer::process_inputs`] to mix several.
+    async fn process<'a, C, P, T>(
+        &mut self,
+        in_port: &mut InPort<'a, C>,
+        out_port: &mut OutPort<'a, P>,
+        _inplace_port: &mut InPlacePort<'a, T>,
+    ) -> ProcessResult<Self::Error>
+    where
+        C: Consumer<'a>,
+        P: Producer<'a>,
+        T: Transformer<'a>,
+    {
+        self.process_inputs(core::slice::from_mut(in_port), out_port).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{new_slot, read_samples, write_samples};
+
+    #[tokio::test]
+    async fn test_mix_gain_saturation_and_eof() {
+        let mut mixer = Mixer::<2, 64>::new([1.0, 0.5], 4);
+        let requirements = mixer.initialize(Some(Info::new(48000, 1, 16, None))).await.unwrap();
+        let music = new_slot(requirements.in_.unwrap());
+        let chime = new_slot(requirements.in_.unwrap());
+        let out = new_slot(requirements.out.unwrap());
+
+        write_samples::<i16>(&music, &[1000, 2000, 30000, -30000], Position::First).await;
+        write_samples::<i16>(&chime, &[400, 400, 20000, -20000], Position::Single).await;
+        let mut inputs = [music.in_port(), chime.in_port()];
+        let result = mixer.process_inputs(&mut inputs, &mut out.out_port()).await.unwrap();
+        assert_eq!(result, Fine);
+        assert_eq!(read_samples::<i16>(&out).await, (vec![1200, 2200, i16::MAX, i16::MIN], Position::First));
+        // The chime reached `Last` and has been drained.
+        assert!(!mixer.is_connected(1));
+
+        // Only the music is read from now on.
+        write_samples::<i16>(&music, &[7, 8], Position::Last).await;
+        let result = mixer.process_inputs(&mut inputs, &mut out.out_port()).await.unwrap();
+        assert_eq!(result, Eof);
+        assert_eq!(read_samples::<i16>(&out).await, (vec![7, 8], Position::Last));
+    }
+
+    #[tokio::test]
+    async fn test_uneven_payloads_and_control() {
+        let control = MixerControl::new();
+        let mut mixer = Mixer::<2, 64>::new([1.0, 1.0], 4).with_control(&control);
+        let requirements = mixer.initialize(Some(Info::new(48000, 1, 16, None))).await.unwrap();
+        let a = new_slot(requirements.in_.unwrap());
+        let b = new_slot(requirements.in_.unwrap());
+        let out = new_slot(requirements.out.unwrap());
+        let mut inputs = [a.in_port(), b.in_port()];
+
+        // Input b delivers fewer frames: the output waits for it.
+        write_samples::<i16>(&a, &[1, 1, 1, 1], Position::First).await;
+        write_samples::<i16>(&b, &[10, 10], Position::First).await;
+        mixer.process_inputs(&mut inputs, &mut out.out_port()).await.unwrap();
+        assert_eq!(read_samples::<i16>(&out).await.0, [11, 11]);
+
+        // Input a still has 2 queued frames (below the low-water mark), so both are read again.
+        write_samples::<i16>(&a, &[2, 2, 2, 2], Position::Middle).await;
+        write_samples::<i16>(&b, &[20, 20, 20, 20], Position::Middle).await;
+        mixer.process_inputs(&mut inputs, &mut out.out_port()).await.unwrap();
+        assert_eq!(read_samples::<i16>(&out).await.0, [21, 21, 22, 22]);
+
+        // Removing b lets a play alone; re-adding it with a new gain brings it back.
+        control.try_send(MixerCommand::Remove(1)).unwrap();
+        write_samples::<i16>(&a, &[3, 3, 3, 3], Position::Middle).await;
+        mixer.process_inputs(&mut inputs, &mut out.out_port()).await.unwrap();
+        assert_eq!(read_samples::<i16>(&out).await.0, [2, 2, 3, 3]);
+        assert!(!mixer.is_connected(1));
+
+        control.try_send(MixerCommand::Add { input: 1, gain: 2.0 }).unwrap();
+        write_samples::<i16>(&a, &[4, 4, 4, 4], Position::Middle).await;
+        write_samples::<i16>(&b, &[30, 30, 30, 30], Position::Middle).await;
+        mixer.process_inputs(&mut inputs, &mut out.out_port()).await.unwrap();
+        assert_eq!(read_samples::<i16>(&out).await.0, [63, 63, 64, 64]);
+        assert!(matches!(mixer.apply_command(MixerCommand::Remove(2)), Err(Error::InvalidParameter)));
+    }
+
+    #[tokio::test]
+    async fn test_large_payload_waits_for_fifo_space() {
+        let mut mixer = Mixer::<2, 16>::new([1.0, 1.0], 4);
+        let info = Info::new(48000, 1, 16, None);
+        let requirements = mixer.initialize(Some(info)).await.unwrap();
+        assert!(mixer.accepts(&Info::new(48000, 1, 16, Some(1000))));
+        assert!(!mixer.accepts(&Info::new(44100, 1, 16, None)));
+
+        let a = new_slot(PayloadSize { min: 2, preferred: 30 });
+        let b = new_slot(requirements.in_.unwrap());
+        let out = new_slot(requirements.out.unwrap());
+        let mut inputs = [a.in_port(), b.in_port()];
+
+        // Input a delivers 15 frames at a time, b only 2: a must not be read again until its
+        // FIFO has room for another 15 samples, or part of the stream would be lost.
+        // Its FIFO runs below the low-water mark on the 7th call but only has room again on the 8th.
+        let mut mixed = Vec::new();
+        for call in 0..8 {
+            match call {
+                0 => write_samples::<i16>(&a, &(1..=15).collect::<Vec<i16>>(), Position::Middle).await,
+                7 => write_samples::<i16>(&a, &(16..=30).collect::<Vec<i16>>(), Position::Middle).await,
+                _ => {}
+            }
+            write_samples::<i16>(&b, &[0, 0], Position::Middle).await;
+            mixer.process_inputs(&mut inputs, &mut out.out_port()).await.unwrap();
+            mixed.extend(read_samples::<i16>(&out).await.0);
+        }
+        assert_eq!(mixed, (1..=16).collect::<Vec<i16>>());
+    }
+}
@@ -1,3 +1,42 @@
+/// The maximum number of interleaved channels that stateful transformers keep state for.
+pub const MAX_CHANNELS: usize = 8;
+
+/// The one-pole smoothing coefficient for a time constant.
+pub(crate) fn time_coefficient(ms: f32, sample_rate: u32) -> f32 {
+    let frames = ms * sample_rate as f32 / 1000.0;
+    if frames < 1.0 {
+        0.0
+    } else {
+        libm::expf(-1.0 / frames)
+    }
+}
+
 pub mod gain;
-pub use gain::Gain;
+pub use gain::{Gain, GainControl};
+
+pub mod format_converter;
+pub use format_converter::FormatConverter;
+
+pub mod resampler;
+pub use resampler::Resampler;
+
+pub mod channel_mixer;
+pub use channel_mixer::ChannelMixer;
+
+pub mod biquad;
+pub use biquad::{Biquad, Equalizer};
+
+pub mod compressor;
+pub use compressor::Compressor;
+
+pub mod noise_gate;
+pub use noise_gate::NoiseGate;
+
+pub mod fade;
+pub use fade::{Fade, FadeControl};
+
+pub mod mixer;
+pub use mixer::{Mixer, MixerControl};
 
+pub mod ducker;
+pub use ducker::{Ducker, DuckerConfig};
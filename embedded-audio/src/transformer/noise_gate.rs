@@ -0,0 +1,365 @@
+//! A noise gate and downward expander, Inplace Operation.
+//!
+//! The gate opens when the stereo-linked level rises above the threshold and closes once it
+//! has stayed below `threshold - hysteresis` for the hold time. While closed, the signal is
+//! attenuated by the expander ratio, but never below the floor. Open/close transitions are
+//! queued in an optional [`Channel`]; if it is full, the transition is dropped with a warning.
+
+use embedded_audio_driver::databus::{Consumer, Producer, Transformer};
+use embedded_audio_driver::element::{BaseElement, Fine, ProcessResult};
+use embedded_audio_driver::info::Info;
+use embedded_audio_driver::port::{InPlacePort, InPort, OutPort, PayloadSize, PortRequirements};
+use embedded_audio_driver::Error;
+
+use crate::sample::SampleCodec;
+use crate::Channel;
+use super::gain::{db_to_linear, linear_to_db, Gain};
+use super::time_coefficient;
+
+/// The release time of the level detector.
+const DETECTOR_RELEASE_MS: f32 = 10.0;
+
+/// The number of gate state changes a [`GateEvents`] channel can queue.
+pub const GATE_EVENT_QUEUE: usize = 8;
+
+/// A queue of [`GateEvent`]s.
+pub type GateEvents = Channel<GateEvent, GATE_EVENT_QUEUE>;
+
+/// A gate state change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GateEvent {
+    Opened,
+    Closed,
+}
+
+/// Parameters of a [`NoiseGate`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NoiseGateConfig {
+    /// Level at which the gate opens, in dBFS.
+    pub threshold_db: f32,
+    /// How far below the threshold the level must fall before the gate closes, in dB.
+    pub hysteresis_db: f32,
+    pub attack_ms: f32,
+    /// How long the level must stay below the closing threshold before the gate closes.
+    pub hold_ms: f32,
+    pub release_ms: f32,
+    /// The maximum attenuation while closed, in dB (e.g. -80.0).
+    pub floor_db: f32,
+    /// Expansion ratio below the closing threshold. `f32::INFINITY` attenuates straight to the floor.
+    pub ratio: f32,
+}
+
+impl Default for NoiseGateConfig {
+    fn default() -> Self {
+        Self {
+            threshold_db: -50.0,
+            hysteresis_db: 6.0,
+            attack_ms: 1.0,
+            hold_ms: 50.0,
+            release_ms: 100.0,
+            floor_db: -80.0,
+            ratio: f32::INFINITY,
+        }
+    }
+}
+
+impl NoiseGateConfig {
+    fn is_valid(&self) -> bool {
+        self.hysteresis_db >= 0.0
+            && self.attack_ms >= 0.0
+            && self.hold_ms >= 0.0
+            && self.release_ms >= 0.0
+            && self.floor_db <= 0.0
+            && self.ratio >= 1.0
+    }
+
+    /// The gain in dB applied while closed at `level_db`.
+    fn closed_gain_db(&self, level_db: f32) -> f32 {
+        if self.ratio.is_infinite() {
+            return self.floor_db;
+        }
+        let below = (level_db - (self.threshold_db - self.hysteresis_db)).min(0.0);
+        (below * (self.ratio - 1.0)).max(self.floor_db)
+    }
+}
+
+/// An Element that attenuates the signal while its level is below a threshold.
+pub struct NoiseGate<'s> {
+    config: NoiseGateConfig,
+    info: Option<Info>,
+    codec: Option<SampleCodec>,
+    events: Option<&'s GateEvents>,
+    open: bool,
+    level: f32,
+    gain: f32,
+    hold_frames: u32,
+    hold_remaining: u32,
+    detector_coef: f32,
+    attack_coef: f32,
+    release_coef: f32,
+    frames_per_process: u16,
+}
+
+impl<'s> NoiseGate<'s> {
+    /// Creates a new noise gate. The gate starts closed.
+    pub fn new(config: NoiseGateConfig, frames_per_process: u16) -> Self {
+        Self {
+            config,
+            info: None,
+            codec: None,
+            events: None,
+            open: false,
+            level: 0.0,
+            gain: 0.0,
+            hold_frames: 0,
+            hold_remaining: 0,
+            detector_coef: 0.0,
+            attack_coef: 0.0,
+            release_coef: 0.0,
+            frames_per_process,
+        }
+    }
+
+    /// Reports open/close transitions to `events`.
+    pub fn with_events(mut self, events: &'s GateEvents) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    pub fn config(&self) -> NoiseGateConfig {
+        self.config
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+
+    fn clear_state(&mut self) {
+        self.open = false;
+        self.level = 0.0;
+        self.gain = db_to_linear(self.config.floor_db);
+        self.hold_remaining = 0;
+    }
+
+    fn set_open(&mut self, open: bool) {
+        self.open = open;
+        if let Some(events) = self.events {
+            if events.try_send(if open { GateEvent::Opened } else { GateEvent::Closed }).is_err() {
+                warn!("NoiseGate: event queue full, dropping a state change");
+            }
+        }
+    }
+
+    /// Updates the gate state with the peak of one frame and returns the gain for it.
+    #[inline]
+    fn next_gain(&mut self, peak: f32) -> f32 {
+        self.level = if peak > self.level {
+            peak
+        } else {
+            peak + self.detector_coef * (self.level - peak)
+        };
+        let level_db = linear_to_db(self.level);
+
+        if level_db >= self.config.threshold_db {
+            if !self.open {
+                self.set_open(true);
+            }
+            self.hold_remaining = self.hold_frames;
+        } else if self.open {
+            if level_db >= self.config.threshold_db - self.config.hysteresis_db {
+                self.hold_remaining = self.hold_frames;
+            } else if self.hold_remaining > 0 {
+                self.hold_remaining -= 1;
+            } else {
+                self.set_open(false);
+            }
+        }
+
+        let target = if self.open {
+            1.0
+        } else {
+            db_to_linear(self.config.closed_gain_db(level_db))
+        };
+        let coef = if target > self.gain { self.attack_coef } else { self.release_coef };
+        self.gain = target + coef * (self.gain - target);
+        self.gain
+    }
+
+    fn process_frames(&mut self, codec: SampleCodec, payload: &mut [u8], channels: usize) {
+        let bytes = codec.bytes_per_sample();
+        for frame in payload.chunks_exact_mut(bytes * channels) {
+            let peak = frame
+                .chunks_exact(bytes)
+                .fold(0.0f32, |peak, sample| peak.max(codec.read_f32(sample).abs()));
+            let gain = self.next_gain(peak);
+            for sample in frame.chunks_exact_mut(bytes) {
+                let value = codec.read_f32(sample);
+                codec.write_f32(sample, value * gain);
+            }
+        }
+    }
+}
+
+impl BaseElement for NoiseGate<'_> {
+    type Error = Error;
+    type Info = Info;
+
+    fn get_in_info(&self) -> Option<Info> {
+        self.info
+    }
+
+    fn get_out_info(&self) -> Option<Info> {
+        self.info
+    }
+
+    async fn initialize(
+        &mut self,
+        upstream_info: Option<Info>,
+    ) -> Result<PortRequirements, Self::Error> {
+        let info = upstream_info.ok_or(Error::InvalidParameter)?;
+        if !Gain::is_supported(&info) {
+            return Err(Error::Unsupported);
+        }
+        if !self.config.is_valid() {
+            return Err(Error::InvalidParameter);
+        }
+        self.codec = Some(SampleCodec::from_info(&info).ok_or(Error::Unsupported)?);
+
+        let rate = info.sample_rate;
+        self.detector_coef = time_coefficient(DETECTOR_RELEASE_MS, rate);
+        self.attack_coef = time_coefficient(self.config.attack_ms, rate);
+        self.release_coef = time_coefficient(self.config.release_ms, rate);
+        self.hold_frames = (self.config.hold_ms * rate as f32 / 1000.0) as u32;
+        self.clear_state();
+        self.info = Some(info);
+
+        let bytes_per_frame = info.get_alignment_bytes() as u16;
+        Ok(PortRequirements::new_in_place(PayloadSize {
+            min: bytes_per_frame,
+            preferred: bytes_per_frame * self.frames_per_process,
+        }))
+    }
+
+    fn available(&self) -> u32 {
+        u32::MAX
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        self.clear_state();
+        Ok(())
+    }
+
+    async fn reset(&mut self) -> Result<(), Self::Error> {
+        self.info = None;
+        self.codec = None;
+        self.clear_state();
+        Ok(())
+    }
+
+    async fn process<'a, C, P, T>(
+        &mut self,
+        _in_port: &mut InPort<'a, C>,
+        _out_port: &mut OutPort<'a, P>,
+        in_place_port: &mut InPlacePort<'a, T>,
+    ) -> ProcessResult<Self::Error>
+    where
+        C: Consumer<'a>,
+        P: Producer<'a>,
+        T: Transformer<'a>,
+    {
+        if let InPlacePort::Transformer(transformer) = in_place_port {
+            let mut payload = transformer.acquire_transform().await;
+            let codec = self.codec.ok_or(Error::NotInitialized)?;
+            let channels = self.info.ok_or(Error::NotInitialized)?.channels as usize;
+
+            self.process_frames(codec, &mut payload, channels);
+            Ok(Fine)
+        } else {
+            Err(Error::Unsupported)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::run_in_place;
+    use embedded_audio_driver::payload::Position;
+
+    /// Alternating +/- `amplitude`, a crude full-band signal with a constant peak level.
+    fn square(amplitude: i16, frames: usize) -> Vec<i16> {
+        (0..frames).map(|i| if i % 2 == 0 { amplitude } else { -amplitude }).collect()
+    }
+
+    #[tokio::test]
+    async fn test_gate_open_hold_close() {
+        let events = GateEvents::new();
+        let config = NoiseGateConfig {
+            threshold_db: -40.0,
+            hysteresis_db: 6.0,
+            attack_ms: 0.0,
+            hold_ms: 10.0,
+            release_ms: 0.0,
+            floor_db: -40.0,
+            ratio: f32::INFINITY,
+        };
+        let mut gate = NoiseGate::new(config, 64).with_events(&events);
+        gate.initialize(Some(Info::new(8000, 1, 16, None))).await.unwrap();
+
+        // Hiss at about -60 dBFS stays attenuated by the floor.
+        let out = run_in_place(&mut gate, &square(33, 400), Position::Middle).await.0;
+        assert!(out.iter().all(|s| s.abs() <= 1));
+        assert!(!gate.is_open());
+        assert!(events.try_receive().is_err());
+
+        // Speech at about -20 dBFS opens the gate immediately.
+        let out = run_in_place(&mut gate, &square(3277, 400), Position::Middle).await.0;
+        assert_eq!(out[0], 3277);
+        assert!(gate.is_open());
+        assert_eq!(events.try_receive(), Ok(GateEvent::Opened));
+
+        // Back to hiss: the gate holds for 80 frames plus the detector release, then closes.
+        let out = run_in_place(&mut gate, &square(33, 800), Position::Middle).await.0;
+        assert_eq!(out[..80], square(33, 80)[..]);
+        assert!(out[400..].iter().all(|s| s.abs() <= 1));
+        assert!(!gate.is_open());
+        assert_eq!(events.try_receive(), Ok(GateEvent::Closed));
+
+        // An opening followed by a closing within one payload queues both.
+        let mut burst = square(3277, 8);
+        burst.extend(square(33, 792));
+        run_in_place(&mut gate, &burst, Position::Middle).await;
+        assert!(!gate.is_open());
+        assert_eq!(events.try_receive(), Ok(GateEvent::Opened));
+        assert_eq!(events.try_receive(), Ok(GateEvent::Closed));
+        assert!(events.try_receive().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_hysteresis_and_expander() {
+        let config = NoiseGateConfig {
+            threshold_db: -20.0,
+            hysteresis_db: 10.0,
+            attack_ms: 0.0,
+            hold_ms: 0.0,
+            release_ms: 0.0,
+            floor_db: -60.0,
+            ratio: 2.0,
+        };
+        let mut gate = NoiseGate::new(config, 64);
+        gate.initialize(Some(Info::new(8000, 1, 16, None))).await.unwrap();
+
+        // Open the gate with a loud signal first.
+        run_in_place(&mut gate, &square(16384, 100), Position::Middle).await;
+        assert!(gate.is_open());
+        // -26 dBFS is inside the hysteresis band: the gate stays open.
+        let out = run_in_place(&mut gate, &square(1638, 400), Position::Middle).await.0;
+        assert!(gate.is_open());
+        assert_eq!(out[399], -1638);
+
+        // -40 dBFS is 10 dB under the closing threshold: 2:1 expansion gives -10 dB.
+        let out = run_in_place(&mut gate, &square(328, 800), Position::Middle).await.0;
+        assert!(!gate.is_open());
+        assert!((out[799] as f32 / -328.0 - db_to_linear(-10.0)).abs() < 0.01, "{}", out[799]);
+    }
+}
@@ -0,0 +1,424 @@
+//! A sample-rate converter.
+//!
+//! Output frame `n` is interpolated at input position `n * in_rate / out_rate`, tracked as an
+//! exact rational so arbitrary ratios (e.g. 44100 -> 48000) never drift. Two interpolators are
+//! available: linear, and a Blackman-windowed sinc evaluated from a polyphase table with linear
+//! interpolation between phases. When downsampling the sinc cutoff follows the output Nyquist
+//! frequency to avoid aliasing.
+//!
+//! The interpolator needs a few input frames of look-ahead. Input before the stream start and
+//! after its end is treated as silence, and the tail is flushed when the `Last` payload arrives,
+//! so a stream of `N` input frames always yields exactly `ceil(N * out_rate / in_rate)` frames.
+
+use core::f32::consts::PI;
+use libm::{cosf, sinf};
+
+use embedded_audio_driver::databus::{Consumer, Producer, Transformer};
+use embedded_audio_driver::element::{BaseElement, Eof, Fine, ProcessResult};
+use embedded_audio_driver::info::Info;
+use embedded_audio_driver::payload::Position;
+use embedded_audio_driver::port::{InPlacePort, InPort, OutPort, PayloadSize, PortRequirements};
+use embedded_audio_driver::Error;
+
+use crate::sample::SampleCodec;
+use super::MAX_CHANNELS;
+
+const MAX_TAPS: usize = 32;
+const PHASES: usize = 64;
+
+/// Filter length of the windowed-sinc interpolator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SincQuality {
+    /// 8 taps.
+    Low,
+    /// 16 taps.
+    Medium,
+    /// 32 taps.
+    High,
+}
+
+impl SincQuality {
+    fn taps(self) -> usize {
+        match self {
+            SincQuality::Low => 8,
+            SincQuality::Medium => 16,
+            SincQuality::High => MAX_TAPS,
+        }
+    }
+}
+
+/// The interpolation method of a [`Resampler`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResamplerMode {
+    /// Linear interpolation between neighbouring frames. Cheap, but with audible aliasing.
+    Linear,
+    /// Windowed-sinc polyphase interpolation.
+    Sinc(SincQuality),
+}
+
+fn gcd(a: u32, b: u32) -> u32 {
+    if b == 0 { a } else { gcd(b, a % b) }
+}
+
+/// An Element that converts the sample rate of the upstream stream to `out_rate`.
+pub struct Resampler {
+    out_rate: u32,
+    mode: ResamplerMode,
+    in_info: Option<Info>,
+    out_info: Option<Info>,
+    codec: Option<SampleCodec>,
+    /// Output frames advance `step_num / step_den` input frames.
+    step_num: u64,
+    step_den: u64,
+    taps: usize,
+    /// Polyphase kernel, `PHASES + 1` rows of `taps` coefficients.
+    table: [f32; (PHASES + 1) * MAX_TAPS],
+    /// Per-channel history of the last `taps` input frames, stored twice so that
+    /// `history[c][pos..pos + taps]` is always the contiguous window, oldest first.
+    history: [[f32; 2 * MAX_TAPS]; MAX_CHANNELS],
+    history_pos: usize,
+    frames_in: u64,
+    frames_out: u64,
+    frames_per_process: u16,
+}
+
+impl Resampler {
+    /// Creates a new resampler.
+    ///
+    /// # Arguments
+    ///
+    /// * `out_rate` - The output sample rate in Hz.
+    /// * `mode` - The interpolation method.
+    pub fn new(out_rate: u32, mode: ResamplerMode, frames_per_process: u16) -> Self {
+        Self {
+            out_rate,
+            mode,
+            in_info: None,
+            out_info: None,
+            codec: None,
+            step_num: 1,
+            step_den: 1,
+            taps: 2,
+            table: [0.0; (PHASES + 1) * MAX_TAPS],
+            history: [[0.0; 2 * MAX_TAPS]; MAX_CHANNELS],
+            history_pos: 0,
+            frames_in: 0,
+            frames_out: 0,
+            frames_per_process,
+        }
+    }
+
+    /// The number of output frames produced from `in_frames` input frames.
+    fn output_frames_for(&self, in_frames: u64) -> u64 {
+        (in_frames * self.step_den).div_ceil(self.step_num)
+    }
+
+    /// The largest number of frames a single call to `process` can emit.
+    fn max_output_frames(&self, in_frames: u64) -> u64 {
+        // The tail flushed at the end of the stream adds up to `taps / 2 + 1` input frames.
+        self.output_frames_for(in_frames + self.taps as u64 / 2 + 1) + 1
+    }
+
+    /// Fills the polyphase table with a normalized Blackman-windowed sinc.
+    fn build_table(&mut self) {
+        let half = (self.taps / 2) as f32;
+        // Keep the passband slightly below the lower of the two Nyquist frequencies.
+        let cutoff = 0.9 * (self.step_den as f32 / self.step_num as f32).min(1.0);
+
+        for phase in 0..=PHASES {
+            let frac = phase as f32 / PHASES as f32;
+            let row = &mut self.table[phase * MAX_TAPS..phase * MAX_TAPS + self.taps];
+            let mut sum = 0.0;
+            for (k, coef) in row.iter_mut().enumerate() {
+                // Distance from the interpolation point to the frame at window position `k`.
+                let d = k as f32 - half + 1.0 - frac;
+                let x = PI * cutoff * d;
+                let sinc = if x.abs() < 1e-6 { 1.0 } else { sinf(x) / x };
+                let w = d / half;
+                let window = if w.abs() >= 1.0 {
+                    0.0
+                } else {
+                    0.42 + 0.5 * cosf(PI * w) + 0.08 * cosf(2.0 * PI * w)
+                };
+                *coef = sinc * window;
+                sum += *coef;
+            }
+            // Unity gain at DC for every phase.
+            row.iter_mut().for_each(|coef| *coef /= sum);
+        }
+    }
+
+    /// Computes the interpolation weights for a fractional position `rem / step_den`.
+    fn weights(&self, rem: u64, weights: &mut [f32; MAX_TAPS]) {
+        let frac = rem as f32 / self.step_den as f32;
+        match self.mode {
+            ResamplerMode::Linear => {
+                weights[0] = 1.0 - frac;
+                weights[1] = frac;
+            }
+            ResamplerMode::Sinc(_) => {
+                let pos = frac * PHASES as f32;
+                let phase = (pos as usize).min(PHASES - 1);
+                let a = pos - phase as f32;
+                let row0 = &self.table[phase * MAX_TAPS..phase * MAX_TAPS + self.taps];
+                let row1 = &self.table[(phase + 1) * MAX_TAPS..(phase + 1) * MAX_TAPS + self.taps];
+                for ((w, c0), c1) in weights.iter_mut().zip(row0).zip(row1) {
+                    *w = c0 + (c1 - c0) * a;
+                }
+            }
+        }
+    }
+
+    /// Pushes one input frame and writes every output frame that became computable into
+    /// `out`, starting at `*out_len`. `frame` is `None` for the silence after the stream end.
+    fn push_frame(
+        &mut self,
+        frame: Option<&[u8]>,
+        out: &mut [u8],
+        out_len: &mut usize,
+        limit: u64,
+    ) -> Result<(), Error> {
+        let codec = self.codec.ok_or(Error::NotInitialized)?;
+        let channels = self.in_info.ok_or(Error::NotInitialized)?.channels as usize;
+        let bytes = codec.bytes_per_sample();
+
+        for c in 0..channels {
+            let value = frame.map_or(0.0, |f| codec.read_f32(&f[c * bytes..]));
+            self.history[c][self.history_pos] = value;
+            self.history[c][self.history_pos + self.taps] = value;
+        }
+        self.history_pos = (self.history_pos + 1) % self.taps;
+        self.frames_in += 1;
+
+        // The window now covers input frames `frames_in - taps ..= frames_in - 1`, centred so
+        // that it serves every output frame whose position lies in `[newest - half, newest - half + 1)`.
+        let half = (self.taps / 2) as u64;
+        let mut weights = [0.0f32; MAX_TAPS];
+        while self.frames_out < limit {
+            let pos = self.frames_out * self.step_num;
+            let index = pos / self.step_den;
+            if index + half >= self.frames_in {
+                break;
+            }
+
+            let frame_bytes = bytes * channels;
+            if *out_len + frame_bytes > out.len() {
+                return Err(Error::BufferFull);
+            }
+
+            self.weights(pos % self.step_den, &mut weights);
+            for c in 0..channels {
+                let window = &self.history[c][self.history_pos..self.history_pos + self.taps];
+                let value: f32 = window.iter().zip(&weights).map(|(x, w)| x * w).sum();
+                codec.write_f32(&mut out[*out_len + c * bytes..], value);
+            }
+            *out_len += frame_bytes;
+            self.frames_out += 1;
+        }
+        Ok(())
+    }
+
+    fn clear_state(&mut self) {
+        self.history = [[0.0; 2 * MAX_TAPS]; MAX_CHANNELS];
+        self.history_pos = 0;
+        self.frames_in = 0;
+        self.frames_out = 0;
+    }
+}
+
+impl BaseElement for Resampler {
+    type Error = Error;
+    type Info = Info;
+
+    fn get_in_info(&self) -> Option<Info> {
+        self.in_info
+    }
+
+    fn get_out_info(&self) -> Option<Info> {
+        self.out_info
+    }
+
+    fn available(&self) -> u32 {
+        u32::MAX
+    }
+
+    async fn initialize(
+        &mut self,
+        upstream_info: Option<Info>,
+    ) -> Result<PortRequirements, Self::Error> {
+        let in_info = upstream_info.ok_or(Error::InvalidParameter)?;
+        if !in_info.vaild() || self.out_rate == 0 {
+            return Err(Error::InvalidParameter);
+        }
+        if in_info.channels as usize > MAX_CHANNELS {
+            return Err(Error::Unsupported);
+        }
+        self.codec = Some(SampleCodec::from_info(&in_info).ok_or(Error::Unsupported)?);
+
+        let divisor = gcd(in_info.sample_rate, self.out_rate);
+        self.step_num = (in_info.sample_rate / divisor) as u64;
+        self.step_den = (self.out_rate / divisor) as u64;
+        self.taps = match self.mode {
+            ResamplerMode::Linear => 2,
+            ResamplerMode::Sinc(quality) => quality.taps(),
+        };
+        if let ResamplerMode::Sinc(_) = self.mode {
+            self.build_table();
+        }
+        self.clear_state();
+
+        let mut out_info = in_info;
+        out_info.sample_rate = self.out_rate;
+        out_info.num_frames = in_info.num_frames.map(|frames| self.output_frames_for(frames));
+        self.in_info = Some(in_info);
+        self.out_info = Some(out_info);
+
+        let frame = in_info.get_alignment_bytes() as u16;
+        let max_out = |frames: u16| {
+            (self.max_output_frames(frames as u64) * frame as u64).min(u16::MAX as u64) as u16
+        };
+        let mut requirements = PortRequirements::sink(PayloadSize {
+            min: frame,
+            preferred: frame * self.frames_per_process,
+        });
+        requirements.out = Some(PayloadSize {
+            min: max_out(1),
+            preferred: max_out(self.frames_per_process),
+        });
+        Ok(requirements)
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        self.clear_state();
+        Ok(())
+    }
+
+    async fn reset(&mut self) -> Result<(), Self::Error> {
+        self.in_info = None;
+        self.out_info = None;
+        self.codec = None;
+        self.clear_state();
+        Ok(())
+    }
+
+    async fn process<'a, C, P, T>(
+        &mut self,
+        in_port: &mut InPort<'a, C>,
+        out_port: &mut OutPort<'a, P>,
+        _inplace_port: &mut InPlacePort<'a, T>,
+    ) -> ProcessResult<Self::Error>
+    where
+        C: Consumer<'a>,
+        P: Producer<'a>,
+        T: Transformer<'a>,
+    {
+        let (InPort::Consumer(consumer), OutPort::Producer(producer)) = (in_port, out_port) else {
+            return Err(Error::Unsupported);
+        };
+        let in_info = self.in_info.ok_or(Error::NotInitialized)?;
+        let frame_bytes = in_info.get_alignment_bytes() as usize;
+
+        let in_payload = consumer.acquire_read().await;
+        let mut out_payload = producer.acquire_write().await;
+        let position = in_payload.metadata.position;
+        let is_last = matches!(position, Position::Last | Position::Single);
+
+        let mut out_len = 0;
+        let mut result = Ok(());
+        for frame in in_payload.chunks_exact(frame_bytes) {
+            result = self.push_frame(Some(frame), &mut out_payload, &mut out_len, u64::MAX);
+            if result.is_err() {
+                break;
+            }
+        }
+
+        if is_last && result.is_ok() {
+            // Feed silence until every output frame inside the input duration has been produced.
+            let total = self.output_frames_for(self.frames_in);
+            while self.frames_out < total && result.is_ok() {
+                result = self.push_frame(None, &mut out_payload, &mut out_len, total);
+            }
+        }
+
+        if let Err(err) = result {
+            out_payload.set_valid_length(0);
+            return Err(err);
+        }
+
+        out_payload.set_valid_length(out_len);
+        out_payload.set_position(position);
+
+        if is_last {
+            self.clear_state();
+            Ok(Eof)
+        } else {
+            Ok(Fine)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::run_chunks;
+
+    /// Feeds `input` (interleaved i16) through the resampler in payloads of `chunk_frames`
+    /// frames and returns the interleaved output.
+    async fn run_resampler(resampler: &mut Resampler, info: Info, input: &[i16], chunk_frames: usize) -> Vec<i16> {
+        let requirements = resampler.initialize(Some(info)).await.unwrap();
+        let channels = info.channels as usize;
+        let out_size = resampler.max_output_frames(chunk_frames as u64) as usize * channels * 2;
+        let (output, eof) = run_chunks(resampler, &requirements, input, chunk_frames * channels, out_size).await;
+        assert!(eof);
+        output
+    }
+
+    #[tokio::test]
+    async fn test_linear_upsample() {
+        let info = Info::new(24000, 1, 16, Some(4));
+        let mut resampler = Resampler::new(48000, ResamplerMode::Linear, 64);
+        let output = run_resampler(&mut resampler, info, &[0, 100, 200, 300], 4).await;
+
+        assert_eq!(output, [0, 50, 100, 150, 200, 250, 300, 150]);
+        let out_info = resampler.get_out_info().unwrap();
+        assert_eq!(out_info.sample_rate, 48000);
+        assert_eq!(out_info.num_frames, Some(8));
+    }
+
+    #[tokio::test]
+    async fn test_exact_frame_count_across_payloads() {
+        // 44.1 kHz -> 48 kHz stereo, 1000 frames split over payloads that do not divide evenly.
+        let frames = 1000;
+        let info = Info::new(44100, 2, 16, Some(frames));
+        let input: Vec<i16> = (0..frames * 2).map(|i| (i % 200) as i16).collect();
+
+        for mode in [ResamplerMode::Linear, ResamplerMode::Sinc(SincQuality::Medium)] {
+            let mut resampler = Resampler::new(48000, mode, 128);
+            let output = run_resampler(&mut resampler, info, &input, 97).await;
+            let expected = (frames * 48000).div_ceil(44100);
+            assert_eq!(output.len() as u64, expected * 2);
+            assert_eq!(resampler.get_out_info().unwrap().num_frames, Some(expected));
+        }
+
+        // Downsampling 48 kHz -> 16 kHz
+        let info = Info::new(48000, 1, 16, Some(frames));
+        let input = vec![0i16; frames as usize];
+        let mut resampler = Resampler::new(16000, ResamplerMode::Sinc(SincQuality::Low), 128);
+        let output = run_resampler(&mut resampler, info, &input, 100).await;
+        assert_eq!(output.len() as u64, frames / 3 + 1);
+    }
+
+    #[tokio::test]
+    async fn test_sinc_preserves_dc_and_channels() {
+        // A constant signal must stay constant away from the stream edges, per channel.
+        let info = Info::new(44100, 2, 16, None);
+        let input: Vec<i16> = (0..2000).map(|i| if i % 2 == 0 { 10000 } else { -5000 }).collect();
+        let mut resampler = Resampler::new(48000, ResamplerMode::Sinc(SincQuality::High), 256);
+        let output = run_resampler(&mut resampler, info, &input, 256).await;
+
+        for frame in output[100..output.len() - 100].chunks_exact(2) {
+            assert!((frame[0] - 10000).abs() <= 2, "left {}", frame[0]);
+            assert!((frame[1] + 5000).abs() <= 2, "right {}", frame[1]);
+        }
+    }
+}
@@ -3,12 +3,12 @@ use embassy_executor::Spawner;
 use log::*;
 use embedded_io_adapters::std::FromStd;
 
-use embedded_audio::databus::slot::HeapSlot;
 use embedded_audio::decoder::WavDecoder;
+use embedded_audio::pipeline::Pipeline;
 use embedded_audio::stream::cpal_output::{Config, CpalOutputStream};
 use embedded_audio::transformer::Gain;
-use embedded_audio_driver::databus::{Consumer, Operation, Producer, Transformer, Databus};
-use embedded_audio_driver::element::{BaseElement, Eof, Fine};
+use embedded_audio::transformer::resampler::{Resampler, ResamplerMode, SincQuality};
+use embedded_audio_driver::element::BaseElement;
 use embedded_audio_driver::stream::BaseStream;
 
 
@@ -20,83 +20,50 @@ async fn playback_wav() {
     // 1. Set up the CPAL host and output device.
     let host = cpal::default_host();
     let device = host.default_output_device().expect("no output device available");
-    let supported_config = device.default_output_config().expect("no default config");
-    let mut config: cpal::StreamConfig = supported_config.into();
-    
-    config.channels = 2; // stereo
-    // config.sample_rate = cpal::SampleRate(44100); // and 44.1kHz sample rate
-    config.sample_rate = cpal::SampleRate(48000); // Some devices prefer 48kHz
+    let sample_rate = device.default_output_config().expect("no default config").sample_rate();
 
     info!("Using output device: \"{}\"", device.name().unwrap());
-    info!("Using output config: {:?}", config);
     
     // 2. Create the pipeline elements.
     // Source: A WavDecoder reading from an in-memory file.
     let wav_data = include_bytes!("../../../../res/light-rain.wav");
     let cursor = FromStd::new(std::io::Cursor::new(wav_data));
-    let mut decoder = WavDecoder::new(cursor, 512);
+    let decoder = WavDecoder::new(cursor, 512);
+
+    // Transformer: A Resampler converting the file to the device sample rate.
+    let resampler = Resampler::new(sample_rate.0, ResamplerMode::Sinc(SincQuality::Medium), 512);
 
     // Transformer: A Gain element to increase volume.
-    let mut gain = Gain::new(1.3, 512);
+    let gain = Gain::new(1.3, 512);
 
-    // Sink: A CpalOutputStream to send data to the sound card.
-    let mut cpal_stream = CpalOutputStream::<i16, 2>::new(
+    // Sink: A CpalOutputStream to send data to the sound card. It picks a device
+    // configuration matching the resampled stream when initialized.
+    let cpal_stream = CpalOutputStream::new(
         Config {
             rb_capacity: None,
             latency_ms: 100,
             frames_per_process: 512,
         },
         device,
-        config,
     );
 
-    // 3. Initialize the elements in sequence, passing info downstream.
-    let decoder_port_requirement = decoder.initialize(None).await.expect("Decoder init failed");
-    let decoder_info = decoder.get_out_info();
-    
-    let gain_port_requirement = gain.initialize(decoder_info).await.expect("Gain init failed");
-    let gain_info = gain.get_out_info();
+    // 3. Initialize the elements in sequence and create the databuses between them:
+    //    decoder -> resampler, and resampler -> gain (in-place) -> stream.
+    let mut pipeline = Pipeline::new((decoder, resampler, gain, cpal_stream));
+    pipeline.initialize().await.expect("Pipeline init failed");
 
-    let stream_port_requirement = cpal_stream.initialize(gain_info).await.expect("CpalStream init failed");
-    
-    info!("Decoder Info: {:#?}", decoder_info.unwrap());
+    info!("Decoder Info: {:#?}", pipeline.elements().0.get_out_info().unwrap());
+    info!("Using output config: {:?}", pipeline.elements().3.device_config().unwrap());
     info!("Playback starting...");
-    
-    // 4. Create the databus (a slot with a transformer).
-    let mut slot = HeapSlot::new_heap(4096); // `true` enables the transformer stage
-    slot.register(Operation::Produce, decoder_port_requirement.out.unwrap());
-    slot.register(Operation::Consume, stream_port_requirement.in_.unwrap());
-    slot.register(Operation::InPlace, gain_port_requirement.in_place.unwrap());
-
-    // 5. Set up the ports.
-    let mut dec_out_port = slot.out_port();
-    let mut gain_inplace_port = slot.in_place_port();
-    let mut stream_in_port = slot.in_port();
-
-    // 6. Start the audio stream.
-    cpal_stream.start().expect("Failed to start CPAL stream");
-
-    // 7. Run the processing loop.
-    loop {
-        // Step 1: Decode a chunk of the WAV file into the slot.
-        if let Eof = decoder.process(&mut Default::default(), &mut dec_out_port, &mut Default::default()).await.unwrap() {
-            // If the decoder is done, we still need to process the last chunk through the rest of the pipeline.
-        }
 
-        // Step 2: Apply gain to the data in-place in the slot.
-        gain.process(&mut Default::default(), &mut Default::default(), &mut gain_inplace_port).await.unwrap();
+    // 4. Start the audio stream.
+    pipeline.elements_mut().3.start().expect("Failed to start CPAL stream");
 
-        // Step 3: Send the processed chunk from the slot to the CPAL stream.
-        match cpal_stream.process(&mut stream_in_port, &mut Default::default(), &mut Default::default()).await.unwrap() {
-            Eof => {
-                info!("Playback finished.");
-                break;
-            }
-            Fine => { /* Continue processing */ }
-        }
-    }
+    // 5. Run the processing loop until the stream has played the last payload.
+    pipeline.run().await.expect("Playback failed");
+    info!("Playback finished.");
 
-    cpal_stream.stop().unwrap();
+    pipeline.elements_mut().3.stop().unwrap();
     info!("Playback loop finished.");
 }
 